@@ -0,0 +1,77 @@
+// Parsing a typical compressed response: the owned parsers against the
+// borrowing MessageRef. Run with `cargo bench`.
+use std::hint::black_box;
+
+use criterion::{criterion_group, criterion_main, Criterion};
+
+use librig::dns::{Answer, RecordClass, RecordType, Response};
+use librig::message::{Message, MessageRef};
+use librig::rdata::RData;
+
+// an MX answer with NS authority and glue, about 700 bytes
+fn response() -> Vec<u8> {
+    let mut msg = Message::query("example.com", RecordType::MX, RecordClass::IN).unwrap();
+    msg.set_qr(true).set_ra(true);
+    for i in 0..10 {
+        msg.add_answer(Answer::new(
+            "example.com.".to_string(),
+            RecordType::MX,
+            RecordClass::IN,
+            300,
+            RData::MX {
+                preference: i * 10,
+                exchange: format!("mx{}.mail.example.com.", i),
+            },
+        ));
+        msg.add_additional(Answer::new(
+            format!("mx{}.mail.example.com.", i),
+            RecordType::A,
+            RecordClass::IN,
+            300,
+            RData::A([192, 0, 2, i as u8].into()),
+        ));
+    }
+    for ns in ["ns1", "ns2", "ns3", "ns4"] {
+        msg.add_authority(Answer::new(
+            "example.com.".to_string(),
+            RecordType::NS,
+            RecordClass::IN,
+            86400,
+            RData::NS(format!("{}.example.com.", ns)),
+        ));
+    }
+    msg.to_bytes()
+}
+
+fn parse(c: &mut Criterion) {
+    let bytes = response();
+
+    c.bench_function("Response::from_bytes", |b| {
+        b.iter(|| Response::from_bytes(black_box(bytes.clone())).unwrap())
+    });
+    c.bench_function("Message::from_bytes", |b| {
+        b.iter(|| Message::from_bytes(black_box(&bytes)).unwrap())
+    });
+
+    // what a proxy or capture reader typically needs: the header, the
+    // question and the answer TTLs
+    c.bench_function("MessageRef::parse and scan", |b| {
+        b.iter(|| {
+            let msg = MessageRef::parse(black_box(&bytes)).unwrap();
+            let question = msg.questions().next().unwrap();
+            let min_ttl = msg.answer().map(|r| r.ttl).min();
+            (msg.flags().rcode(), question.rtype, min_ttl)
+        })
+    });
+    c.bench_function("MessageRef::to_message", |b| {
+        b.iter(|| {
+            MessageRef::parse(black_box(&bytes))
+                .unwrap()
+                .to_message()
+                .unwrap()
+        })
+    });
+}
+
+criterion_group!(benches, parse);
+criterion_main!(benches);
@@ -0,0 +1,390 @@
+// Load generation, dnsperf style
+//
+// Queries from a list are sent round robin over one UDP socket, at a target
+// rate if one is set, with at most `concurrency` of them waiting for a reply
+// at any time. Each query is given an ID that is not already outstanding,
+// so replies are matched to their queries by ID alone; queries unanswered
+// after the timeout are counted as lost and their IDs freed. The report
+// gives the achieved rate, the latency percentiles and histogram, the
+// rcodes seen and the number of timeouts.
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::net::{SocketAddr, UdpSocket};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::dns::{rcode_str, HDR_SIZE};
+use crate::spec::QuerySpec;
+use crate::stats::{millis, Latency};
+
+const DEFAULT_DURATION: Duration = Duration::from_secs(10);
+const DEFAULT_CONCURRENCY: usize = 100;
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5);
+// how often the receiver looks for timed out queries when idle
+const POLL: Duration = Duration::from_millis(10);
+// upper bounds of the latency histogram buckets; the last catches the rest
+const BUCKETS: [Duration; 13] = [
+    Duration::from_micros(100),
+    Duration::from_micros(250),
+    Duration::from_micros(500),
+    Duration::from_millis(1),
+    Duration::from_micros(2500),
+    Duration::from_millis(5),
+    Duration::from_millis(10),
+    Duration::from_millis(25),
+    Duration::from_millis(50),
+    Duration::from_millis(100),
+    Duration::from_millis(250),
+    Duration::from_millis(500),
+    Duration::from_secs(1),
+];
+const BAR_WIDTH: usize = 40;
+
+// A load test of one server. Each spec's own @server, if any, is ignored.
+pub struct Bench {
+    server: SocketAddr,
+    queries: Vec<Vec<u8>>,
+    qps: Option<u32>,
+    duration: Duration,
+    concurrency: usize,
+    timeout: Duration,
+}
+
+// what the sender and receiver share
+#[derive(Default)]
+struct State {
+    outstanding: HashMap<u16, Instant>,
+    times: Vec<Duration>,
+    rcodes: BTreeMap<u8, usize>,
+    timeouts: usize,
+    unexpected: usize,
+}
+
+#[derive(Debug)]
+pub struct Report {
+    pub server: SocketAddr,
+    pub qps: Option<u32>,
+    pub sent: usize,
+    pub timeouts: usize,
+    pub unexpected: usize, // replies to no outstanding query, e.g. after their timeout
+    pub elapsed: Duration,
+    pub rcodes: BTreeMap<u8, usize>,
+    pub times: Vec<Duration>,
+}
+
+impl Bench {
+    // fails if a query cannot be encoded
+    pub fn new(server: SocketAddr, queries: &[QuerySpec]) -> Result<Self, String> {
+        Ok(Self {
+            server,
+            queries: queries
+                .iter()
+                .map(QuerySpec::to_bytes)
+                .collect::<Result<_, _>>()?,
+            qps: None,
+            duration: DEFAULT_DURATION,
+            concurrency: DEFAULT_CONCURRENCY,
+            timeout: DEFAULT_TIMEOUT,
+        })
+    }
+
+    // queries per second to send; without one, queries go out as fast as
+    // the concurrency limit allows
+    pub fn set_qps(&mut self, qps: u32) -> &mut Self {
+        self.qps = Some(qps).filter(|&q| q > 0);
+        self
+    }
+
+    pub fn set_duration(&mut self, duration: Duration) -> &mut Self {
+        self.duration = duration;
+        self
+    }
+
+    pub fn set_concurrency(&mut self, concurrency: usize) -> &mut Self {
+        self.concurrency = concurrency.clamp(1, u16::MAX as usize);
+        self
+    }
+
+    pub fn set_timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.timeout = timeout;
+        self
+    }
+
+    // Send queries for the duration, then wait for the last replies
+    pub fn run(&self) -> Result<Report, String> {
+        if self.queries.is_empty() {
+            return Err("no queries to send".to_string());
+        }
+        let fail = |e: std::io::Error| format!("{}: {}", self.server, e);
+        let local = if self.server.is_ipv4() {
+            "0.0.0.0:0"
+        } else {
+            "[::]:0"
+        };
+        let socket = UdpSocket::bind(local).map_err(fail)?;
+        socket.connect(self.server).map_err(fail)?;
+        socket.set_read_timeout(Some(POLL)).map_err(fail)?;
+
+        let state = Arc::new((Mutex::new(State::default()), Condvar::new()));
+        let done = Arc::new(AtomicBool::new(false));
+        let receiver = {
+            let socket = socket.try_clone().map_err(fail)?;
+            let (state, done, timeout) = (state.clone(), done.clone(), self.timeout);
+            thread::spawn(move || receive(socket, &state, &done, timeout))
+        };
+
+        let (lock, freed) = &*state;
+        let start = Instant::now();
+        let mut sent = 0;
+        let mut id: u16 = rand::random();
+        'send: while start.elapsed() < self.duration {
+            if let Some(qps) = self.qps {
+                let due = start + Duration::from_secs_f64(sent as f64 / qps as f64);
+                thread::sleep(due.saturating_duration_since(Instant::now()));
+                if start.elapsed() >= self.duration {
+                    break;
+                }
+            }
+
+            let mut s = lock.lock().unwrap();
+            while s.outstanding.len() >= self.concurrency {
+                s = freed.wait_timeout(s, POLL).unwrap().0;
+                if start.elapsed() >= self.duration {
+                    break 'send;
+                }
+            }
+            while s.outstanding.contains_key(&id) {
+                id = id.wrapping_add(1);
+            }
+            let mut packet = self.queries[sent % self.queries.len()].clone();
+            packet[..2].copy_from_slice(&id.to_be_bytes());
+            s.outstanding.insert(id, Instant::now());
+            drop(s);
+
+            // a failed send, e.g. after an ICMP unreachable, is left to time out
+            let _ = socket.send(&packet);
+            id = id.wrapping_add(1);
+            sent += 1;
+        }
+        let elapsed = start.elapsed();
+
+        // the receiver times out whatever is still unanswered
+        let s = {
+            let mut s = lock.lock().unwrap();
+            while !s.outstanding.is_empty() {
+                s = freed.wait_timeout(s, POLL).unwrap().0;
+            }
+            std::mem::take(&mut *s)
+        };
+        done.store(true, Ordering::Relaxed);
+        let _ = receiver.join();
+
+        Ok(Report {
+            server: self.server,
+            qps: self.qps,
+            sent,
+            timeouts: s.timeouts,
+            unexpected: s.unexpected,
+            elapsed,
+            rcodes: s.rcodes,
+            times: s.times,
+        })
+    }
+}
+
+// match replies to outstanding queries until done, expiring any older
+// than timeout
+fn receive(
+    socket: UdpSocket,
+    state: &(Mutex<State>, Condvar),
+    done: &AtomicBool,
+    timeout: Duration,
+) {
+    let (lock, freed) = state;
+    let mut buf = [0; 65535];
+
+    while !done.load(Ordering::Relaxed) {
+        let reply = match socket.recv(&mut buf) {
+            Ok(n) if n >= HDR_SIZE && buf[2] & 0x80 != 0 => Some(&buf[..n]),
+            _ => None,
+        };
+        let now = Instant::now();
+
+        let mut s = lock.lock().unwrap();
+        if let Some(reply) = reply {
+            let id = u16::from_be_bytes([reply[0], reply[1]]);
+            match s.outstanding.remove(&id) {
+                Some(sent) => {
+                    s.times.push(now - sent);
+                    *s.rcodes.entry(reply[3] & 0x0f).or_default() += 1;
+                }
+                None => s.unexpected += 1,
+            }
+        }
+        let waiting = s.outstanding.len();
+        s.outstanding
+            .retain(|_, sent| now.duration_since(*sent) < timeout);
+        s.timeouts += waiting - s.outstanding.len();
+        drop(s);
+        freed.notify_all();
+    }
+}
+
+impl Report {
+    pub fn replies(&self) -> usize {
+        self.times.len()
+    }
+
+    // the number of replies in each histogram bucket
+    pub fn histogram(&self) -> [usize; BUCKETS.len() + 1] {
+        let mut counts = [0; BUCKETS.len() + 1];
+        for t in &self.times {
+            counts[BUCKETS.iter().position(|b| t < b).unwrap_or(BUCKETS.len())] += 1;
+        }
+        counts
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let secs = self.elapsed.as_secs_f64().max(f64::EPSILON);
+        let rate = |n: usize| n as f64 / secs;
+        let percent = |n: usize| n as f64 * 100.0 / self.sent.max(1) as f64;
+
+        writeln!(
+            f,
+            "; {} for {:.3}s{}",
+            self.server,
+            secs,
+            match self.qps {
+                Some(q) => format!(", target {} qps", q),
+                None => String::new(),
+            }
+        )?;
+        write!(
+            f,
+            "; {} queries sent ({:.1} qps), {} replies ({:.1} qps), {} timed out ({:.2}%)",
+            self.sent,
+            rate(self.sent),
+            self.replies(),
+            rate(self.replies()),
+            self.timeouts,
+            percent(self.timeouts)
+        )?;
+        if self.unexpected > 0 {
+            write!(f, "\n; {} unexpected or late replies", self.unexpected)?;
+        }
+        if !self.rcodes.is_empty() {
+            let rcodes: Vec<String> = self
+                .rcodes
+                .iter()
+                .map(|(&r, n)| format!("{} {}", rcode_str(r), n))
+                .collect();
+            write!(f, "\n; rcodes {}", rcodes.join(", "))?;
+        }
+
+        let latency = match Latency::new(&self.times) {
+            Some(l) => l,
+            None => return Ok(()),
+        };
+        write!(f, "\n; latency {}\n; latency histogram", latency)?;
+        let counts = self.histogram();
+        let first = counts.iter().position(|&n| n > 0).unwrap_or(0);
+        let last = counts.iter().rposition(|&n| n > 0).unwrap_or(0);
+        let most = *counts.iter().max().unwrap_or(&1);
+        for (i, &n) in counts.iter().enumerate().take(last + 1).skip(first) {
+            let bound = match BUCKETS.get(i) {
+                Some(b) => format!("< {}", millis(*b)),
+                None => format!(">= {}", millis(BUCKETS[BUCKETS.len() - 1])),
+            };
+            let line = format!(
+                "{:>13} {:>9} {:>6.2}% {}",
+                bound,
+                n,
+                n as f64 * 100.0 / self.replies() as f64,
+                "#".repeat((n * BAR_WIDTH).div_ceil(most))
+            );
+            write!(f, "\n{}", line.trim_end())?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::{Server, Zone};
+    use crate::zone;
+    use std::net::TcpListener;
+
+    #[test]
+    fn test_histogram() {
+        let report = Report {
+            server: "127.0.0.1:53".parse().unwrap(),
+            qps: Some(100),
+            sent: 5,
+            timeouts: 1,
+            unexpected: 0,
+            elapsed: Duration::from_secs(2),
+            rcodes: BTreeMap::from([(0, 3), (3, 1)]),
+            times: [50, 80, 300, 2000]
+                .into_iter()
+                .map(Duration::from_micros)
+                .collect(),
+        };
+        assert!(report.histogram()[..5] == [2, 0, 1, 0, 1]);
+        assert!(report.histogram()[5..].iter().all(|&n| n == 0));
+
+        let out = report.to_string();
+        assert!(out.starts_with("; 127.0.0.1:53 for 2.000s, target 100 qps\n"));
+        assert!(
+            out.contains("; 5 queries sent (2.5 qps), 4 replies (2.0 qps), 1 timed out (20.00%)\n")
+        );
+        assert!(out.contains("; rcodes NOERROR 3, NXDOMAIN 1\n"));
+        assert!(out.ends_with(
+            "\n    < 0.100ms         2  50.00% ########################################\
+             \n    < 0.250ms         0   0.00%\
+             \n    < 0.500ms         1  25.00% ####################\
+             \n    < 1.000ms         0   0.00%\
+             \n    < 2.500ms         1  25.00% ####################"
+        ));
+    }
+
+    #[test]
+    fn test_bench() {
+        let zone = "$ORIGIN example.com.
+@    3600 IN SOA ns1 hostmaster 1 3600 600 86400 300
+www  300  IN A   192.0.2.1
+";
+        let zone = zone::parse_zone(zone, "example.com.").unwrap();
+        let server = Arc::new(Server::new(vec![Zone::new("example.com.", zone).unwrap()]));
+        let udp = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let tcp = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = udp.local_addr().unwrap();
+        thread::spawn(move || server.serve(udp, tcp));
+
+        let queries = [
+            QuerySpec::parse("www.example.com").unwrap(),
+            QuerySpec::parse("nope.example.com").unwrap(),
+        ];
+        let report = Bench::new(addr, &queries)
+            .unwrap()
+            .set_qps(200)
+            .set_duration(Duration::from_millis(100))
+            .set_concurrency(4)
+            .run()
+            .unwrap();
+
+        // every query is answered, alternating between the two names
+        let rcode = |r: u8| report.rcodes.get(&r).copied().unwrap_or(0);
+        assert!(report.sent > 0 && report.replies() == report.sent);
+        assert!(report.timeouts == 0 && report.unexpected == 0);
+        assert!(rcode(0) == report.sent.div_ceil(2) && rcode(3) == report.sent / 2);
+        // 200 qps for 100ms is 20 queries; a slow machine only sends fewer
+        assert!(report.sent <= 40);
+
+        assert!(Bench::new(addr, &[]).unwrap().run().is_err());
+    }
+}
@@ -0,0 +1,268 @@
+// Side by side answers from several servers
+//
+// rig --compare sends the same query to every server at once and lays the
+// replies out as a table with a column per server: the rcode, the AD flag
+// and the round trip time, then a row for each distinct answer record with
+// the TTL each server gave it, or - where a server left it out. Rows where
+// the servers disagree are marked with a *, and the last line names what
+// differed, which is how split horizon views and stale caches show up.
+use std::collections::BTreeMap;
+use std::thread;
+use std::time::Duration;
+
+use crate::dns::{rcode_str, Response};
+use crate::spec::QuerySpec;
+use crate::stats::millis;
+
+pub type Reply = Result<(Response, Duration), String>;
+
+// Ask every server, given as an address, the spec's question in parallel.
+// The spec's own server, if any, is not asked.
+pub fn query_all(spec: &QuerySpec, servers: &[String], timeout: Duration) -> Vec<Reply> {
+    let mut spec = spec.clone();
+    spec.server = None;
+    let spec = &spec;
+
+    thread::scope(|s| {
+        let handles: Vec<_> = servers
+            .iter()
+            .map(|addr| s.spawn(move || crate::time_lookup(spec, addr, timeout)))
+            .collect();
+        handles
+            .into_iter()
+            .map(|h| h.join().unwrap_or(Err("lookup failed".to_string())))
+            .collect()
+    })
+}
+
+// Render the replies, one column per server in labels
+pub fn render(spec: &QuerySpec, labels: &[String], replies: &[Reply]) -> String {
+    let responses: Vec<Option<&Response>> = replies
+        .iter()
+        .map(|r| r.as_ref().ok().map(|(resp, _)| resp))
+        .collect();
+    let mut differs = Vec::new();
+    let mut rows = vec![(false, String::new(), labels.to_vec())];
+
+    let rcodes: Vec<String> = replies
+        .iter()
+        .map(|r| match r {
+            Ok((resp, _)) => rcode_str(resp.header.flags.rcode()).to_string(),
+            Err(e) if e == "timed out" => e.clone(),
+            Err(_) => "error".to_string(),
+        })
+        .collect();
+    let rcode_differs = rcodes.windows(2).any(|w| w[0] != w[1]);
+    if rcode_differs {
+        differs.push("rcode");
+    }
+    rows.push((rcode_differs, "rcode".to_string(), rcodes));
+
+    let ad: Vec<Option<bool>> = responses
+        .iter()
+        .map(|r| r.map(|resp| resp.header.flags.ad()))
+        .collect();
+    let ad_differs = disagree(&ad);
+    if ad_differs {
+        differs.push("ad");
+    }
+    let cells = ad
+        .iter()
+        .map(|a| match a {
+            Some(true) => "yes".to_string(),
+            Some(false) => "no".to_string(),
+            None => String::new(),
+        })
+        .collect();
+    rows.push((ad_differs, "ad".to_string(), cells));
+
+    let times = replies
+        .iter()
+        .map(|r| match r {
+            Ok((_, time)) => millis(*time),
+            Err(_) => String::new(),
+        })
+        .collect();
+    rows.push((false, "time".to_string(), times));
+
+    // each distinct record with the lowest TTL each server gave it
+    let mut records: BTreeMap<String, Vec<Option<u32>>> = BTreeMap::new();
+    for (i, resp) in responses.iter().enumerate() {
+        for a in resp.map(|r| r.answer.as_slice()).unwrap_or_default() {
+            let key = format!("{} {} {}", a.name, a.rtype, a.rdata);
+            let key = if spec.idn { crate::show_idn(&key) } else { key };
+            let ttls = records.entry(key).or_insert(vec![None; replies.len()]);
+            ttls[i] = Some(ttls[i].map_or(a.ttl, |t| t.min(a.ttl)));
+        }
+    }
+    let (mut answers_differ, mut ttls_differ) = (false, false);
+    for (record, ttls) in records {
+        // only servers that replied count
+        let present: Vec<Option<bool>> = responses
+            .iter()
+            .zip(&ttls)
+            .map(|(resp, ttl)| resp.map(|_| ttl.is_some()))
+            .collect();
+        let missing = disagree(&present);
+        let ttl_differs = disagree(&ttls);
+        answers_differ |= missing;
+        ttls_differ |= ttl_differs;
+
+        let cells = responses
+            .iter()
+            .zip(&ttls)
+            .map(|(resp, ttl)| match (resp, ttl) {
+                (None, _) => String::new(),
+                (Some(_), Some(t)) => t.to_string(),
+                (Some(_), None) => "-".to_string(),
+            })
+            .collect();
+        rows.push((missing || ttl_differs, record, cells));
+    }
+    if answers_differ {
+        differs.push("answers");
+    }
+    if ttls_differ {
+        differs.push("ttls");
+    }
+
+    let mut out = vec![format!("; {} {} {}", spec.name, spec.rclass, spec.rtype)];
+    out.extend(table(&rows));
+    for (label, reply) in labels.iter().zip(replies) {
+        if let Err(e) = reply {
+            out.push(format!("; {}: {}", label, e));
+        }
+    }
+    out.push(match differs.is_empty() {
+        true => format!("; all {} servers agree", labels.len()),
+        false => format!("; differs in {}", differs.join(", ")),
+    });
+    out.join("\n")
+}
+
+// true if the values that are there are not all the same
+fn disagree<T: PartialEq>(values: &[Option<T>]) -> bool {
+    let mut given = values.iter().flatten();
+    match given.next() {
+        Some(first) => given.any(|v| v != first),
+        None => false,
+    }
+}
+
+// align rows of (marked, label, cells) into columns
+fn table(rows: &[(bool, String, Vec<String>)]) -> Vec<String> {
+    let label_width = rows
+        .iter()
+        .map(|(_, l, _)| l.chars().count())
+        .max()
+        .unwrap_or(0);
+    let mut widths = vec![0; rows[0].2.len()];
+    for (_, _, cells) in rows {
+        for (w, c) in widths.iter_mut().zip(cells) {
+            *w = (*w).max(c.chars().count());
+        }
+    }
+
+    rows.iter()
+        .map(|(marked, label, cells)| {
+            let mut line = format!(
+                "{} {:<w$}",
+                if *marked { '*' } else { ' ' },
+                label,
+                w = label_width
+            );
+            for (c, w) in cells.iter().zip(&widths) {
+                line.push_str(&format!("  {:<w$}", c, w = w));
+            }
+            line.trim_end().to_string()
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dns::{Answer, RecordClass, RecordType};
+    use crate::message::Message;
+    use crate::rdata::RData;
+
+    fn reply(ad: bool, answers: &[(u8, u32)]) -> Reply {
+        let mut msg = Message::query("www.example.com", RecordType::A, RecordClass::IN).unwrap();
+        msg.set_qr(true).set_ad(ad);
+        for &(last, ttl) in answers {
+            msg.add_answer(Answer::new(
+                "www.example.com.".to_string(),
+                RecordType::A,
+                RecordClass::IN,
+                ttl,
+                RData::A([192, 0, 2, last].into()),
+            ));
+        }
+        Ok((
+            Response::from_bytes(msg.to_bytes().unwrap()).unwrap(),
+            Duration::from_millis(2),
+        ))
+    }
+
+    #[test]
+    fn test_render() {
+        let spec = QuerySpec::parse("www.example.com").unwrap();
+        let labels = ["@a", "@b", "@c"].map(String::from);
+
+        let replies = [
+            reply(true, &[(1, 300), (2, 300)]),
+            reply(false, &[(1, 120), (3, 300)]),
+            Err("timed out".to_string()),
+        ];
+        let out = render(&spec, &labels, &replies);
+        assert!(
+            out == "; www.example.com IN A
+                                @a       @b       @c
+* rcode                         NOERROR  NOERROR  timed out
+* ad                            yes      no
+  time                          2.000ms  2.000ms
+* www.example.com. A 192.0.2.1  300      120
+* www.example.com. A 192.0.2.2  300      -
+* www.example.com. A 192.0.2.3  -        300
+; @c: timed out
+; differs in rcode, ad, answers, ttls"
+        );
+
+        let replies = [reply(true, &[(1, 300)]), reply(true, &[(1, 300)])];
+        let out = render(&spec, &labels[..2], &replies);
+        assert!(
+            out.ends_with("\n  www.example.com. A 192.0.2.1  300      300\n; all 2 servers agree")
+        );
+    }
+
+    #[test]
+    fn test_render_idn() {
+        let mut spec = QuerySpec::parse("xn--bcher-kva.de").unwrap();
+        let labels = ["@a", "@b"].map(String::from);
+        let mut msg = Message::query("xn--bcher-kva.de", RecordType::A, RecordClass::IN).unwrap();
+        msg.set_qr(true).add_answer(Answer::new(
+            "xn--bcher-kva.de.".to_string(),
+            RecordType::A,
+            RecordClass::IN,
+            60,
+            RData::A([192, 0, 2, 1].into()),
+        ));
+        let resp = Response::from_bytes(msg.to_bytes().unwrap()).unwrap();
+        let replies = [
+            Ok((resp.clone(), Duration::from_millis(1))),
+            Ok((resp, Duration::from_millis(1))),
+        ];
+
+        let out = render(&spec, &labels, &replies);
+        assert!(out.contains("\n  xn--bcher-kva.de. (bücher.de.) A 192.0.2.1  60"));
+        spec.idn = false;
+        assert!(!render(&spec, &labels, &replies).contains("bücher"));
+    }
+
+    #[test]
+    fn test_disagree() {
+        assert!(!disagree(&[Some(1), None, Some(1)]));
+        assert!(disagree(&[Some(1), None, Some(2)]));
+        assert!(!disagree::<u32>(&[None, None]));
+    }
+}
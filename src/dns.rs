@@ -1,38 +1,94 @@
-use byteorder::{BigEndian, ReadBytesExt};
-use std::io::Cursor;
-use std::net::UdpSocket;
+use byteorder::{BigEndian, ByteOrder, ReadBytesExt};
+use std::fmt;
+use std::io::{self, Cursor, Read, Write};
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs, UdpSocket};
+use std::str::FromStr;
+use std::time::Duration;
 
 use bincode::Options;
 use bitfield::bitfield;
 use serde::{Deserialize, Serialize};
 
+use crate::message::{Compressor, Message};
+use crate::name::Name;
+use crate::rdata::RData;
+use crate::tsig::{self, Session};
+use crate::zone::parse_labels;
+
 const BUF_SIZE: usize = 1024;
-const HDR_SIZE: usize = 12;
-const RESP_DATA_SIZE: usize = 12;
+pub(crate) const HDR_SIZE: usize = 12;
+const RESP_DATA_SIZE: usize = 10;
+const TCP_TIMEOUT: Duration = Duration::from_secs(10);
+pub(crate) const MAX_POINTERS: usize = 64;
 
 // TYPE fields are used in resource records - RFC 1035 3.2.2
-#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Copy)]
+// QTYPE values (AXFR, ANY, ...) are a superset - RFC 1035 3.2.3
+#[derive(Serialize, Deserialize, PartialEq, Eq, Hash, Debug, Clone, Copy)]
 pub enum RecordType {
-    A = 1, // 1 a host address
-    NS,    // 2 an authoritative name server
-    MD,    // 3 a mail destination (Obsolete - use MX)
-    MF,    // 4 a mail forwarder (Obsolete - use MX)
-    CNAME, // 5 the canonical name for an alias
-    SOA,   // 6 marks the start of a zone of authority
-    MB,    // 7 a mailbox hostname name (EXPERIMENTAL)
-    MG,    // 8 a mail group member (EXPERIMENTAL)
-    MR,    // 9 a mail rename hostname name (EXPERIMENTAL)
-    NULL,  // 10 a null RR (EXPERIMENTAL)
-    WKS,   // 11 a well known service description
-    PTR,   // 12 a hostname name pointer
-    HINFO, // 13 host information
-    MINFO, // 14 mailbox or mail list information
-    MX,    // 15 mail exchange
-    TXT,   // 16 text strings
+    A,            // 1 a host address
+    NS,           // 2 an authoritative name server
+    MD,           // 3 a mail destination (Obsolete - use MX)
+    MF,           // 4 a mail forwarder (Obsolete - use MX)
+    CNAME,        // 5 the canonical name for an alias
+    SOA,          // 6 marks the start of a zone of authority
+    MB,           // 7 a mailbox hostname name (EXPERIMENTAL)
+    MG,           // 8 a mail group member (EXPERIMENTAL)
+    MR,           // 9 a mail rename hostname name (EXPERIMENTAL)
+    NULL,         // 10 a null RR (EXPERIMENTAL)
+    WKS,          // 11 a well known service description
+    PTR,          // 12 a hostname name pointer
+    HINFO,        // 13 host information
+    MINFO,        // 14 mailbox or mail list information
+    MX,           // 15 mail exchange
+    TXT,          // 16 text strings
+    AAAA,         // 28 an IPv6 host address - RFC 3596
+    SRV,          // 33 service location - RFC 2782
+    OPT,          // 41 EDNS pseudo record - RFC 6891
+    DS,           // 43 delegation signer - RFC 4034
+    RRSIG,        // 46 RRset signature - RFC 4034
+    NSEC,         // 47 next secure - RFC 4034
+    DNSKEY,       // 48 DNS public key - RFC 4034
+    TSIG,         // 250 transaction signature - RFC 8945
+    IXFR,         // 251 incremental zone transfer - RFC 1995
+    AXFR,         // 252 full zone transfer
+    ANY,          // 255 all records
+    CAA,          // 257 certification authority authorization - RFC 8659
+    Unknown(u16), // anything else, kept as its numeric value - RFC 3597
 }
 
+const KNOWN_TYPES: [RecordType; 28] = [
+    RecordType::A,
+    RecordType::NS,
+    RecordType::MD,
+    RecordType::MF,
+    RecordType::CNAME,
+    RecordType::SOA,
+    RecordType::MB,
+    RecordType::MG,
+    RecordType::MR,
+    RecordType::NULL,
+    RecordType::WKS,
+    RecordType::PTR,
+    RecordType::HINFO,
+    RecordType::MINFO,
+    RecordType::MX,
+    RecordType::TXT,
+    RecordType::AAAA,
+    RecordType::SRV,
+    RecordType::OPT,
+    RecordType::DS,
+    RecordType::RRSIG,
+    RecordType::NSEC,
+    RecordType::DNSKEY,
+    RecordType::TSIG,
+    RecordType::IXFR,
+    RecordType::AXFR,
+    RecordType::ANY,
+    RecordType::CAA,
+];
+
 impl RecordType {
-    fn from_u16(value: u16) -> RecordType {
+    pub fn from_u16(value: u16) -> RecordType {
         match value {
             1 => RecordType::A,
             2 => RecordType::NS,
@@ -50,10 +106,53 @@ impl RecordType {
             14 => RecordType::MINFO,
             15 => RecordType::MX,
             16 => RecordType::TXT,
-            _ => {
-                eprintln!("Invalid Type: {}", value);
-                std::process::exit(1);
-            }
+            28 => RecordType::AAAA,
+            33 => RecordType::SRV,
+            41 => RecordType::OPT,
+            43 => RecordType::DS,
+            46 => RecordType::RRSIG,
+            47 => RecordType::NSEC,
+            48 => RecordType::DNSKEY,
+            250 => RecordType::TSIG,
+            251 => RecordType::IXFR,
+            252 => RecordType::AXFR,
+            255 => RecordType::ANY,
+            257 => RecordType::CAA,
+            _ => RecordType::Unknown(value),
+        }
+    }
+
+    pub fn to_u16(self) -> u16 {
+        match self {
+            RecordType::A => 1,
+            RecordType::NS => 2,
+            RecordType::MD => 3,
+            RecordType::MF => 4,
+            RecordType::CNAME => 5,
+            RecordType::SOA => 6,
+            RecordType::MB => 7,
+            RecordType::MG => 8,
+            RecordType::MR => 9,
+            RecordType::NULL => 10,
+            RecordType::WKS => 11,
+            RecordType::PTR => 12,
+            RecordType::HINFO => 13,
+            RecordType::MINFO => 14,
+            RecordType::MX => 15,
+            RecordType::TXT => 16,
+            RecordType::AAAA => 28,
+            RecordType::SRV => 33,
+            RecordType::OPT => 41,
+            RecordType::DS => 43,
+            RecordType::RRSIG => 46,
+            RecordType::NSEC => 47,
+            RecordType::DNSKEY => 48,
+            RecordType::TSIG => 250,
+            RecordType::IXFR => 251,
+            RecordType::AXFR => 252,
+            RecordType::ANY => 255,
+            RecordType::CAA => 257,
+            RecordType::Unknown(v) => v,
         }
     }
 
@@ -70,35 +169,93 @@ impl RecordType {
             RecordType::MR => "MR",
             RecordType::NULL => "NULL",
             RecordType::WKS => "WKS",
-            RecordType::PTR => "PTW",
+            RecordType::PTR => "PTR",
             RecordType::HINFO => "HINFO",
             RecordType::MINFO => "MINFO",
             RecordType::MX => "MX",
             RecordType::TXT => "TXT",
+            RecordType::AAAA => "AAAA",
+            RecordType::SRV => "SRV",
+            RecordType::OPT => "OPT",
+            RecordType::DS => "DS",
+            RecordType::RRSIG => "RRSIG",
+            RecordType::NSEC => "NSEC",
+            RecordType::DNSKEY => "DNSKEY",
+            RecordType::TSIG => "TSIG",
+            RecordType::IXFR => "IXFR",
+            RecordType::AXFR => "AXFR",
+            RecordType::ANY => "ANY",
+            RecordType::CAA => "CAA",
+            RecordType::Unknown(_) => "UNKNOWN",
+        }
+    }
+}
+
+impl fmt::Display for RecordType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RecordType::Unknown(v) => write!(f, "TYPE{}", v),
+            t => f.write_str(t.get_str()),
+        }
+    }
+}
+
+impl FromStr for RecordType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(t) = KNOWN_TYPES
+            .iter()
+            .find(|t| t.get_str().eq_ignore_ascii_case(s))
+        {
+            return Ok(*t);
+        }
+
+        // generic TYPEnnn syntax
+        match s.get(..4) {
+            Some(p) if p.eq_ignore_ascii_case("TYPE") => s[4..]
+                .parse::<u16>()
+                .map(RecordType::from_u16)
+                .map_err(|_| format!("unknown record type {}", s)),
+            _ => Err(format!("unknown record type {}", s)),
         }
     }
 }
 
 // CLASS fields appear in resource records - RFC 1035 3.2.4
-#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Copy)]
+#[derive(Serialize, Deserialize, PartialEq, Eq, Hash, Debug, Clone, Copy)]
 pub enum RecordClass {
-    IN = 1, // 1 the Internet
-    CS,     // 2 the CSNET class (Obsolete - used only for examples in some obsolete RFCs)
-    CH,     // 3 the CHAOS class
-    HS,     // 4 Hesiod [Dyer 87]
+    IN,           // 1 the Internet
+    CS,           // 2 the CSNET class (Obsolete - used only for examples in some obsolete RFCs)
+    CH,           // 3 the CHAOS class
+    HS,           // 4 Hesiod [Dyer 87]
+    NONE,         // 254 used by dynamic updates - RFC 2136
+    ANY,          // 255 any class
+    Unknown(u16), // anything else; OPT records carry the UDP size here
 }
 
 impl RecordClass {
-    fn from_u16(value: u16) -> RecordClass {
+    pub fn from_u16(value: u16) -> RecordClass {
         match value {
             1 => RecordClass::IN,
             2 => RecordClass::CS,
             3 => RecordClass::CH,
             4 => RecordClass::HS,
-            _ => {
-                eprintln!("Invalid Class: {}", value);
-                std::process::exit(1);
-            }
+            254 => RecordClass::NONE,
+            255 => RecordClass::ANY,
+            _ => RecordClass::Unknown(value),
+        }
+    }
+
+    pub fn to_u16(self) -> u16 {
+        match self {
+            RecordClass::IN => 1,
+            RecordClass::CS => 2,
+            RecordClass::CH => 3,
+            RecordClass::HS => 4,
+            RecordClass::NONE => 254,
+            RecordClass::ANY => 255,
+            RecordClass::Unknown(v) => v,
         }
     }
 
@@ -108,39 +265,82 @@ impl RecordClass {
             RecordClass::CS => "CS",
             RecordClass::CH => "CH",
             RecordClass::HS => "HS",
+            RecordClass::NONE => "NONE",
+            RecordClass::ANY => "ANY",
+            RecordClass::Unknown(_) => "UNKNOWN",
+        }
+    }
+}
+
+impl fmt::Display for RecordClass {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RecordClass::Unknown(v) => write!(f, "CLASS{}", v),
+            c => f.write_str(c.get_str()),
+        }
+    }
+}
+
+impl FromStr for RecordClass {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(c) = [1, 2, 3, 4, 254, 255]
+            .map(RecordClass::from_u16)
+            .iter()
+            .find(|c| c.get_str().eq_ignore_ascii_case(s))
+        {
+            return Ok(*c);
+        }
+
+        // generic CLASSnnn syntax
+        match s.get(..5) {
+            Some(p) if p.eq_ignore_ascii_case("CLASS") => s[5..]
+                .parse::<u16>()
+                .map(RecordClass::from_u16)
+                .map_err(|_| format!("unknown record class {}", s)),
+            _ => Err(format!("unknown record class {}", s)),
         }
     }
 }
 
 // Header Flags bitfield
 bitfield! {
-    #[derive(Serialize, Deserialize, PartialEq, Debug)]
-    struct HeaderFlags(MSB0 [u16]);
+    #[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+    pub struct HeaderFlags(MSB0 [u16]);
     u8;
-    qr, set_qr: 0;
-    opcode, set_opcode: 4, 1;
-    aa, _: 5;
-    tc, _: 6;
-    rd, set_rd: 7;
-    ra, _: 8;
-    z, _: 9;
-    ad, set_ad: 10;
-    auth, _: 11;
-    rcode, _: 15, 12;
+    pub qr, set_qr: 0;
+    pub opcode, set_opcode: 4, 1;
+    pub aa, set_aa: 5;
+    pub tc, set_tc: 6;
+    pub rd, set_rd: 7;
+    pub ra, set_ra: 8;
+    pub z, set_z: 9;
+    pub ad, set_ad: 10;
+    pub cd, set_cd: 11;
+    pub rcode, set_rcode: 15, 12;
 }
 
-#[derive(Serialize, Deserialize, PartialEq, Debug)]
+impl<T: AsMut<[u16]> + AsRef<[u16]>> HeaderFlags<T> {
+    // bit 11 is CD (checking disabled) - RFC 4035 3.2.2
+    #[deprecated(note = "use cd")]
+    pub fn auth(&self) -> bool {
+        self.cd()
+    }
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
 pub struct Header {
-    id: u16,
-    flags: HeaderFlags<[u16; 1]>,
-    qd_count: u16,
-    an_count: u16,
-    ns_count: u16,
-    ar_count: u16,
+    pub id: u16,
+    pub flags: HeaderFlags<[u16; 1]>,
+    pub qd_count: u16,
+    pub an_count: u16,
+    pub ns_count: u16,
+    pub ar_count: u16,
 }
 
 impl Header {
-    fn new(id: Option<u16>) -> Self {
+    pub(crate) fn new(id: Option<u16>) -> Self {
         Self {
             id: match id {
                 Some(n) => n,
@@ -153,135 +353,261 @@ impl Header {
             ar_count: 0,
         }
     }
-}
 
-fn get_name(bytes: &Vec<u8>) -> (String, usize) {
-    let mut ptr = 0;
-    let mut label: Vec<u8> = Vec::new();
-    loop {
-        let t = bytes[ptr];
-        if t == 0 {
-            break;
+    pub(crate) fn to_bytes(&self) -> Vec<u8> {
+        bincode::DefaultOptions::new()
+            .with_big_endian()
+            .with_fixint_encoding()
+            .serialize(self)
+            .expect("header is fixed size")
+    }
+
+    pub(crate) fn from_bytes(bytes: &[u8]) -> Result<Self, String> {
+        if bytes.len() < HDR_SIZE {
+            return Err(format!(
+                "Failed to deserialize header; only {} bytes",
+                bytes.len()
+            ));
         }
-        if !t.is_ascii_alphanumeric() {
-            label.extend(&bytes[(ptr + 1)..((t as usize) + ptr + 1)]);
-            label.push('.' as u8);
+
+        bincode::DefaultOptions::new()
+            .with_big_endian()
+            .with_fixint_encoding()
+            .deserialize(&bytes[..HDR_SIZE])
+            .map_err(|e| format!("Failed to deserialize header: {}", e))
+    }
+}
+
+// RCODE values - RFC 1035 4.1.1, RFC 2136 2.2
+// OPCODE values - RFC 1035 4.1.1, RFC 1996, RFC 2136
+pub fn opcode_str(opcode: u8) -> &'static str {
+    match opcode {
+        0 => "QUERY",
+        1 => "IQUERY",
+        2 => "STATUS",
+        4 => "NOTIFY",
+        5 => "UPDATE",
+        _ => "RESERVED",
+    }
+}
+
+pub fn rcode_str(rcode: u8) -> &'static str {
+    match rcode {
+        0 => "NOERROR",
+        1 => "FORMERR",
+        2 => "SERVFAIL",
+        3 => "NXDOMAIN",
+        4 => "NOTIMP",
+        5 => "REFUSED",
+        6 => "YXDOMAIN",
+        7 => "YXRRSET",
+        8 => "NXRRSET",
+        9 => "NOTAUTH",
+        10 => "NOTZONE",
+        _ => "RESERVED",
+    }
+}
+
+// append a raw label in presentation format, escaping as RFC 1035 5.1 does
+pub(crate) fn push_label(name: &mut String, label: &[u8]) {
+    for &b in label {
+        match b {
+            b'.' | b'\\' | b'"' | b'(' | b')' | b';' | b'@' | b'$' => {
+                name.push('\\');
+                name.push(b as char);
+            }
+            0x21..=0x7e => name.push(b as char),
+            _ => name.push_str(&format!("\\{:03}", b)),
         }
-        ptr += t as usize + 1;
     }
+}
 
-    let name = String::from_utf8(label).expect("Failed to parse name");
+// Decode a possibly compressed name at offset in msg - RFC 1035 4.1.4
+// Returns the name with its trailing dot and the number of bytes it
+// occupies at offset (pointer targets elsewhere in msg do not count)
+pub(crate) fn read_name(msg: &[u8], offset: usize) -> Result<(String, usize), String> {
+    Name::from_wire(msg, offset).map(|(name, len)| (name.to_string(), len))
+}
 
-    return (name, ptr + 1);
+// wire format of a presentation name, with or without its trailing dot.
+// Names are taken as they are, without the IDNA checks given to names
+// users type.
+pub(crate) fn encode_name(name: &str) -> Result<Vec<u8>, String> {
+    parse_labels(name)
+        .and_then(|(labels, _)| Name::from_labels(labels))
+        .map(|n| n.to_wire())
+        .map_err(|e| format!("{}: {}", name, e))
 }
 
-#[derive(Serialize, Deserialize, PartialEq, Debug)]
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
 pub struct Question {
-    name: Vec<u8>,
-    rtype: RecordType,
-    rclass: RecordClass,
+    pub name: Vec<u8>,
+    pub rtype: RecordType,
+    pub rclass: RecordClass,
 }
 
 impl Question {
     pub fn get_name_str(&self) -> String {
-        let (name, _) = get_name(&self.name);
-
-        return name;
-    }
-
-    fn generate_label(hostname: String) -> Vec<u8> {
-        let mut label: Vec<u8> = Vec::new();
-
-        // Label consists of len field, followed by chunk
-        for chunk in hostname.split(".") {
-            let l = chunk.len();
-            label.push(l as u8);
-            label.extend(chunk.as_bytes());
+        match Name::from_wire(&self.name, 0) {
+            Ok((name, _)) => name.to_string(),
+            Err(e) => format!("<{}>", e),
         }
+    }
 
-        label.push(0 as u8); // trailing null byte
-
-        return label;
+    pub(crate) fn write(&self, msg: &mut Vec<u8>, names: &mut Compressor) {
+        names.write_wire(msg, &self.name);
+        msg.extend(self.rtype.to_u16().to_be_bytes());
+        msg.extend(self.rclass.to_u16().to_be_bytes());
     }
 
-    fn from_bytes(bytes: Vec<u8>) -> (Self, usize) {
-        let (_, mut ptr) = get_name(&bytes);
+    fn generate_label(hostname: String) -> Result<Vec<u8>, String> {
+        hostname
+            .parse::<Name>()
+            .map(|n| n.to_wire())
+            .map_err(|e| format!("{}: {}", hostname, e))
+    }
 
-        let mut cur = Cursor::new(bytes[ptr..].to_vec());
+    pub(crate) fn from_bytes(msg: &[u8], offset: usize) -> Result<(Self, usize), String> {
+        let (name, mut ptr) = Name::from_wire(msg, offset)?;
 
-        let rtype =
-            RecordType::from_u16(cur.read_u16::<BigEndian>().expect("failed to parse type"));
-        let rclass =
-            RecordClass::from_u16(cur.read_u16::<BigEndian>().expect("failed to parse class"));
+        let fixed = msg
+            .get(offset + ptr..offset + ptr + 4)
+            .ok_or("question runs past end of message")?;
 
         let question = Self {
-            name: bytes[..ptr].to_vec(),
-            rtype: rtype,
-            rclass: rclass,
+            name: name.to_wire(),
+            rtype: RecordType::from_u16(BigEndian::read_u16(&fixed[0..2])),
+            rclass: RecordClass::from_u16(BigEndian::read_u16(&fixed[2..4])),
         };
 
         ptr += 4; //advance remaining bytes past question
 
-        return (question, ptr);
+        Ok((question, ptr))
     }
 
-    fn new(hostname: String, rtype: RecordType, rclass: RecordClass) -> Self {
-        Self {
-            name: Self::generate_label(hostname),
+    pub(crate) fn new(
+        hostname: String,
+        rtype: RecordType,
+        rclass: RecordClass,
+    ) -> Result<Self, String> {
+        Ok(Self {
+            name: Self::generate_label(hostname)?,
             rtype: rtype, // hard code A for now
             rclass: rclass,
+        })
+    }
+
+    // a question for a name that is already checked, such as one read
+    // from a message, so without the checks given to typed names
+    pub(crate) fn for_name(name: &Name, rtype: RecordType, rclass: RecordClass) -> Self {
+        Self {
+            name: name.to_wire(),
+            rtype,
+            rclass,
         }
     }
 }
 
-#[derive(Serialize, Deserialize, PartialEq, Debug)]
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
 pub struct Answer {
-    pub domain: u16,
+    pub name: String,
     pub rtype: RecordType,
     pub rclass: RecordClass,
     pub ttl: u32,
     pub len: u16,
     pub data: Vec<u8>,
+    pub rdata: RData,
 }
 
 impl Answer {
-    fn from_bytes(bytes: Vec<u8>) -> (Self, usize) {
-        let mut ptr = 0;
-
-        let mut cur = Cursor::new(&bytes);
-
-        let domain = cur.read_u16::<BigEndian>().expect("failed to parse domain");
-        let rtype =
-            RecordType::from_u16(cur.read_u16::<BigEndian>().expect("failed to parse type"));
-        let rclass =
-            RecordClass::from_u16(cur.read_u16::<BigEndian>().expect("failed to parse class"));
-        let ttl = cur.read_u32::<BigEndian>().expect("failed to parse ttl");
-        let data_len = cur
-            .read_u16::<BigEndian>()
-            .expect("failed to parse data len");
-
-        let data: Vec<u8> =
-            bytes[cur.position() as usize..cur.position() as usize + data_len as usize].to_vec();
-
-        let ans = Answer {
-            domain: domain,
-            rtype: rtype,
-            rclass: rclass,
-            ttl: ttl,
-            len: data_len,
-            data: data,
-        };
+    pub fn new(
+        name: String,
+        rtype: RecordType,
+        rclass: RecordClass,
+        ttl: u32,
+        rdata: RData,
+    ) -> Self {
+        // a record whose names cannot be encoded has no data here; writing
+        // it out reports why
+        let data = rdata.to_bytes().unwrap_or_default();
+        Self {
+            name,
+            rtype,
+            rclass,
+            ttl,
+            len: data.len() as u16,
+            data,
+            rdata,
+        }
+    }
+
+    // parse the record at offset in msg, returning it and its length
+    pub(crate) fn from_bytes(msg: &[u8], offset: usize) -> Result<(Self, usize), String> {
+        let (name, ptr) = read_name(msg, offset)?;
+
+        let start = offset + ptr;
+        let fixed = msg
+            .get(start..start + RESP_DATA_SIZE)
+            .ok_or("record runs past end of message")?;
 
-        ptr += RESP_DATA_SIZE + data_len as usize;
+        let mut cur = Cursor::new(fixed);
+        let rtype = RecordType::from_u16(cur.read_u16::<BigEndian>().unwrap());
+        let rclass = RecordClass::from_u16(cur.read_u16::<BigEndian>().unwrap());
+        let ttl = cur.read_u32::<BigEndian>().unwrap();
+        let data_len = cur.read_u16::<BigEndian>().unwrap();
 
-        return (ans, ptr);
+        let data_start = start + RESP_DATA_SIZE;
+        msg.get(data_start..data_start + data_len as usize)
+            .ok_or("record data runs past end of message")?;
+        let rdata = RData::from_bytes(rtype, msg, data_start, data_len as usize)?;
+
+        // keep the data with any name compression undone, so a record is
+        // the same however the message carrying it was packed
+        let ans = Answer::new(name, rtype, rclass, ttl, rdata);
+
+        Ok((ans, ptr + RESP_DATA_SIZE + data_len as usize))
+    }
+
+    // uncompressed wire format of the record
+    pub fn to_bytes(&self) -> Result<Vec<u8>, String> {
+        let mut bytes = Vec::new();
+        self.write(&mut bytes, &mut Compressor::disabled())?;
+        Ok(bytes)
+    }
+
+    // append the record to msg, compressing names where allowed
+    pub(crate) fn write(&self, msg: &mut Vec<u8>, names: &mut Compressor) -> Result<(), String> {
+        names.write(msg, &self.name)?;
+        msg.extend(self.rtype.to_u16().to_be_bytes());
+        msg.extend(self.rclass.to_u16().to_be_bytes());
+        msg.extend(self.ttl.to_be_bytes());
+
+        // RDLENGTH is only known once the rdata is written
+        let len_at = msg.len();
+        msg.extend([0, 0]);
+        self.rdata.write(msg, names)?;
+        let len = (msg.len() - len_at - 2) as u16;
+        msg[len_at..len_at + 2].copy_from_slice(&len.to_be_bytes());
+        Ok(())
+    }
+}
+
+// master file format - RFC 1035 5.1
+impl fmt::Display for Answer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}\t{}\t{}\t{}\t{}",
+            self.name, self.ttl, self.rclass, self.rtype, self.rdata
+        )
     }
 }
 
 #[derive(Serialize, Deserialize, PartialEq, Debug)]
 pub struct Query {
-    header: Header,
-    question: Question,
+    pub(crate) header: Header,
+    pub(crate) question: Question,
+    pub(crate) authority: Vec<Answer>, // e.g. the client's SOA in an IXFR query
 }
 
 impl Query {
@@ -291,7 +617,13 @@ impl Query {
         rtype: RecordType,
         rclass: RecordClass,
     ) -> Option<Response> {
-        let mut query = Self::new(hostname, rtype, rclass);
+        let mut query = match Self::new(hostname, rtype, rclass) {
+            Ok(q) => q,
+            Err(e) => {
+                eprintln!("Query failed: {}", e);
+                return None;
+            }
+        };
 
         let response = match query.send_query(server) {
             Ok(r) => r,
@@ -304,124 +636,207 @@ impl Query {
         return Some(response);
     }
 
-    fn new(hostname: String, rtype: RecordType, rclass: RecordClass) -> Self {
+    // like do_query, but returning the bytes sent and received. The
+    // response is left unparsed so a malformed one can still be inspected.
+    pub fn do_query_wire(
+        hostname: String,
+        server: String,
+        rtype: RecordType,
+        rclass: RecordClass,
+    ) -> Result<(Vec<u8>, Vec<u8>), String> {
+        Self::new(hostname, rtype, rclass)
+            .map_err(|e| format!("Query failed: {}", e))?
+            .send_query_bytes(server)
+            .map_err(|e| format!("Query failed: {}", e))
+    }
+
+    pub(crate) fn new(
+        hostname: String,
+        rtype: RecordType,
+        rclass: RecordClass,
+    ) -> Result<Self, String> {
         let mut query = Query {
             header: Header::new(None),
-            question: Question::new(hostname, rtype, rclass),
+            question: Question::new(hostname, rtype, rclass)?,
+            authority: Vec::new(),
         };
 
         // enable "standard query" bits
         query.header.flags.set_rd(true);
         query.header.flags.set_ad(true);
 
-        return query;
+        return Ok(query);
     }
 
-    fn query_serialize(&mut self) -> Vec<u8> {
-        let mut ser_query: Vec<u8> = Vec::new();
+    pub(crate) fn query_serialize(&mut self) -> Result<Vec<u8>, String> {
+        self.header.ns_count = self.authority.len() as u16;
 
-        match bincode::DefaultOptions::new()
-            .with_big_endian()
-            .with_fixint_encoding()
-            .serialize(&self.header)
-        {
-            Ok(mut v) => ser_query.append(&mut v),
-            Err(e) => {
-                eprintln!("Failed to serialize query header: {}", e);
-                std::process::exit(1);
-            }
-        };
+        Message {
+            header: self.header.clone(),
+            questions: vec![self.question.clone()],
+            answer: Vec::new(),
+            authority: self.authority.clone(),
+            additional: Vec::new(),
+        }
+        .to_bytes()
+    }
 
-        ser_query.append(&mut self.question.name);
+    fn send_query(&mut self, addr: String) -> std::io::Result<Response> {
+        let (_, received) = self.send_query_bytes(addr)?;
 
-        match bincode::DefaultOptions::new()
-            .with_big_endian()
-            .with_fixint_encoding()
-            .serialize(&(self.question.rtype as u16))
-        {
-            Ok(mut v) => ser_query.append(&mut v),
-            Err(e) => {
-                eprintln!("Failed to serialize query question type: {}", e);
-                std::process::exit(1);
-            }
-        };
+        Response::from_bytes(received).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
 
-        match bincode::DefaultOptions::new()
-            .with_big_endian()
-            .with_fixint_encoding()
-            .serialize(&(self.question.rclass as u16))
-        {
-            Ok(mut v) => ser_query.append(&mut v),
-            Err(e) => {
-                eprintln!("Failed to serialize query question class: {}", e);
-                std::process::exit(1);
-            }
+    // the query as sent over UDP and the response as received, unparsed
+    fn send_query_bytes(&mut self, addr: String) -> std::io::Result<(Vec<u8>, Vec<u8>)> {
+        let packet_bytes = self
+            .query_serialize()
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+
+        let server = addr
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no address for server"))?;
+        let local = if server.is_ipv4() {
+            "0.0.0.0:0"
+        } else {
+            "[::]:0"
         };
+        let socket = UdpSocket::bind(local)?;
+        socket.connect(server)?;
+        socket.send(&packet_bytes)?;
+
+        let mut buf = [0; BUF_SIZE];
+        let size = socket.recv(&mut buf)?;
 
-        return ser_query;
+        Ok((packet_bytes, buf[..size].to_vec()))
     }
 
-    fn send_query(&mut self, addr: String) -> std::io::Result<Response> {
-        let packet_bytes = self.query_serialize();
+    // send the query over a new TCP connection, which is returned so the
+    // caller can read one or more responses with Response::from_stream.
+    // The query is signed if a TSIG session is given.
+    pub(crate) fn send_tcp(
+        &mut self,
+        addr: &str,
+        tsig: Option<&mut Session>,
+    ) -> io::Result<TcpStream> {
+        let invalid = |e| io::Error::new(io::ErrorKind::InvalidInput, e);
+        let mut packet_bytes = self.query_serialize().map_err(invalid)?;
+        if let Some(session) = tsig {
+            session
+                .sign(&mut packet_bytes, tsig::now())
+                .map_err(invalid)?;
+        }
 
-        let socket = UdpSocket::bind("0.0.0.0:0").expect("Failed to bind to address");
+        let mut stream = TcpStream::connect(addr)?;
+        stream.set_read_timeout(Some(TCP_TIMEOUT))?;
 
-        socket
-            .send_to(&packet_bytes, addr)
-            .expect("Failed to connect to DNS server");
+        write_tcp_message(&mut stream, &packet_bytes)?;
 
-        let mut buf = [0; BUF_SIZE];
-        let mut rvec: Vec<u8> = Vec::new();
-        match socket.recv(&mut buf) {
-            Ok(size) => rvec.extend(&buf[..size]),
-            Err(e) => println!("recv function failed: {:?}", e),
+        Ok(stream)
+    }
+}
+
+// read one length prefixed message from a TCP stream - RFC 1035 4.2.2
+// None means the peer closed the connection between messages
+pub fn read_tcp_message<R: Read>(stream: &mut R) -> io::Result<Option<Vec<u8>>> {
+    let mut len = [0; 2];
+    match stream.read_exact(&mut len) {
+        Ok(()) => {}
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+    }
+
+    let mut buf = vec![0; u16::from_be_bytes(len) as usize];
+    stream.read_exact(&mut buf)?;
+
+    Ok(Some(buf))
+}
+
+// write one length prefixed message to a TCP stream
+pub fn write_tcp_message<W: Write>(stream: &mut W, msg: &[u8]) -> io::Result<()> {
+    let mut buf = (msg.len() as u16).to_be_bytes().to_vec();
+    buf.extend(msg);
+    stream.write_all(&buf)
+}
+
+// Send a message to server over UDP and wait for the response with the
+// same ID, skipping anything else that arrives
+pub(crate) fn exchange_udp(
+    server: SocketAddr,
+    packet: &[u8],
+    timeout: Duration,
+) -> io::Result<Vec<u8>> {
+    let local = if server.is_ipv4() {
+        "0.0.0.0:0"
+    } else {
+        "[::]:0"
+    };
+    let socket = UdpSocket::bind(local)?;
+    socket.connect(server)?;
+    socket.set_read_timeout(Some(timeout))?;
+    socket.send(packet)?;
+
+    let mut buf = [0; 65535];
+    loop {
+        let size = socket.recv(&mut buf)?;
+        if size >= HDR_SIZE && buf[..2] == packet[..2] && buf[2] & 0x80 != 0 {
+            return Ok(buf[..size].to_vec());
         }
+    }
+}
 
-        let resp = Response::from_bytes(rvec);
-        return Ok(resp);
+// Send a message to server over a new TCP connection and read one response
+pub(crate) fn exchange_tcp(
+    server: SocketAddr,
+    packet: &[u8],
+    timeout: Duration,
+) -> io::Result<Vec<u8>> {
+    let mut stream = TcpStream::connect_timeout(&server, timeout)?;
+    stream.set_read_timeout(Some(timeout))?;
+    write_tcp_message(&mut stream, packet)?;
+
+    match read_tcp_message(&mut stream)? {
+        Some(resp) if resp.len() >= HDR_SIZE && resp[..2] == packet[..2] => Ok(resp),
+        Some(_) => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "response ID does not match",
+        )),
+        None => Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "connection closed",
+        )),
     }
 }
 
-#[derive(Serialize, Deserialize, PartialEq, Debug)]
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
 pub struct Response {
     pub header: Header,
-    pub question: Question,
+    pub question: Option<Question>, // absent in AXFR continuation messages
     pub answer: Vec<Answer>,
+    pub authority: Vec<Answer>,
+    pub additional: Vec<Answer>,
 }
 
 impl Response {
-    fn from_bytes(bytes: Vec<u8>) -> Self {
-        if bytes.len() < HDR_SIZE {
-            eprintln!("Failed to deserialize header; only {} bytes", bytes.len());
-            std::process::exit(1);
-        }
-
-        let header: Header = bincode::DefaultOptions::new()
-            .with_big_endian()
-            .with_fixint_encoding()
-            .deserialize(&bytes[..HDR_SIZE])
-            .unwrap();
-
-        let mut _ptr = HDR_SIZE;
-
-        let (question, ptr) = Question::from_bytes(bytes[HDR_SIZE..].to_vec());
-        _ptr += ptr;
+    pub fn from_bytes(bytes: Vec<u8>) -> Result<Self, String> {
+        Message::from_bytes(&bytes).map(Self::from)
+    }
 
-        let mut answers: Vec<Answer> = Vec::new();
+    // wire format with compressed names; section counts come from the
+    // sections
+    pub fn to_bytes(&self) -> Result<Vec<u8>, String> {
+        Message::from(self.clone()).to_bytes()
+    }
 
-        for _ in 0..header.an_count {
-            let (answer, ptr) = Answer::from_bytes(bytes[_ptr..].to_vec());
-            _ptr += ptr;
-            answers.push(answer);
+    // Parse the next message from a TCP stream, as used by zone transfers
+    // where one query yields many responses. None at a clean end of stream.
+    pub fn from_stream<R: Read>(stream: &mut R) -> Result<Option<Self>, String> {
+        match read_tcp_message(stream) {
+            Ok(Some(bytes)) => Self::from_bytes(bytes).map(Some),
+            Ok(None) => Ok(None),
+            Err(e) => Err(format!("Failed to read message: {}", e)),
         }
-
-        let resp = Response {
-            header: header,
-            question: question,
-            answer: answers,
-        };
-
-        return resp;
     }
 }
 
@@ -466,8 +881,22 @@ mod tests {
             5, 0x67, 0x6f, 0x67, 0x6c, 0x65, 0x03, 0x63, 0x6f, 0x6d, 0x00,
         ];
 
-        assert!(Question::generate_label(String::from("google.com")) == l1);
-        assert!(Question::generate_label(String::from("gogle.com")) == l2);
+        assert!(Question::generate_label(String::from("google.com")).unwrap() == l1);
+        assert!(Question::generate_label(String::from("gogle.com")).unwrap() == l2);
+
+        // invalid names are refused rather than sent as the root
+        assert!(Question::generate_label(String::from("a..com")).is_err());
+        let long = format!("{}.com", "a".repeat(64));
+        assert!(Query::new(long, RecordType::A, RecordClass::IN).is_err());
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn test_auth_is_cd() {
+        let mut flags = HeaderFlags([0]);
+        assert!(!flags.auth());
+        flags.set_cd(true);
+        assert!(flags.auth() && flags.0 == [0x0010]);
     }
 
     #[test]
@@ -481,10 +910,10 @@ mod tests {
         let rtype = RecordType::A;
         let rclass = RecordClass::IN;
 
-        let mut q = Query::new(hostname, rtype, rclass);
+        let mut q = Query::new(hostname, rtype, rclass).unwrap();
 
         // ignore the randomized ID
-        assert!(q.query_serialize()[2..] == expected[2..]);
+        assert!(q.query_serialize().unwrap()[2..] == expected[2..]);
     }
 
     #[test]
@@ -515,18 +944,55 @@ mod tests {
         assert!(RecordClass::from_u16(4) == RecordClass::HS);
     }
 
+    #[test]
+    fn test_record_type_class_from_str() {
+        assert!("a".parse::<RecordType>() == Ok(RecordType::A));
+        assert!("MX".parse::<RecordType>() == Ok(RecordType::MX));
+        assert!("PTR".parse::<RecordType>() == Ok(RecordType::PTR));
+        assert!("BOGUS".parse::<RecordType>().is_err());
+        assert!("in".parse::<RecordClass>() == Ok(RecordClass::IN));
+        assert!("CH".parse::<RecordClass>() == Ok(RecordClass::CH));
+        assert!("XX".parse::<RecordClass>().is_err());
+    }
+
     #[test]
     fn test_get_name() {
         let bytes: Vec<u8> = vec![6, 103, 111, 111, 103, 108, 101, 3, 99, 111, 109, 0];
 
-        assert!(get_name(&bytes) == (String::from("google.com."), 12));
+        let q = Question {
+            name: bytes,
+            rtype: RecordType::A,
+            rclass: RecordClass::IN,
+        };
+        assert!(q.get_name_str() == "google.com.");
+
+        // labels are copied whole and escaped, not filtered
+        let q = Question {
+            name: vec![3, b'a', b'.', b'-', 3, 99, 111, 109, 0],
+            ..q
+        };
+        assert!(q.get_name_str() == "a\\.-.com.");
+    }
+
+    #[test]
+    fn test_read_name_compressed() {
+        // google.com at 0, www -> pointer to 0 at 12, a pointer loop at 18
+        let bytes: Vec<u8> = vec![
+            6, 103, 111, 111, 103, 108, 101, 3, 99, 111, 109, 0, 3, 119, 119, 119, 0xc0, 0, 0xc0,
+            18,
+        ];
+
+        assert!(read_name(&bytes, 0) == Ok((String::from("google.com."), 12)));
+        assert!(read_name(&bytes, 12) == Ok((String::from("www.google.com."), 6)));
+        assert!(read_name(&bytes, 18).is_err());
+        assert!(read_name(&bytes[..5], 0).is_err());
     }
 
     #[test]
     fn test_gen_label() {
         let bytes: Vec<u8> = vec![6, 103, 111, 111, 103, 108, 101, 3, 99, 111, 109, 0];
 
-        assert!(Question::generate_label("google.com".to_string()) == bytes);
+        assert!(Question::generate_label("google.com".to_string()).unwrap() == bytes);
     }
 
     #[test]
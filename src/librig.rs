@@ -1,28 +1,57 @@
-use dns::Answer;
+use dns::{Answer, RecordClass, RecordType, Response};
+use name::Name;
+use spec::QuerySpec;
 use std::{
     fs::File,
     io::{self, BufRead},
-    net::Ipv4Addr,
+    net::{IpAddr, SocketAddr, ToSocketAddrs},
     path::Path,
+    time::{Duration, Instant},
 };
+use tsig::Key;
 
-mod dns;
+pub mod bench;
+pub mod compare;
+pub mod dns;
+pub mod message;
+pub mod name;
+#[cfg(feature = "async")]
+pub mod nonblocking;
+pub mod notify;
+pub mod pcap;
+pub mod proxy;
+pub mod rdata;
+pub mod secondary;
+pub mod server;
+pub mod spec;
+pub mod stats;
+pub mod tsig;
+pub mod update;
+pub mod wire;
+pub mod xfr;
+pub mod zone;
 
 const RESOLVCONF: &str = "/etc/resolv.conf";
+const CRAFTED_TIMEOUT: Duration = Duration::from_secs(5);
 
-fn display_answer(r: Answer) {
-    match r.len {
-        4 => {
-            println!(
-                "{:16} {} {} {}",
-                Ipv4Addr::new(r.data[0], r.data[1], r.data[2], r.data[3]),
-                r.ttl,
-                r.rclass.get_str(),
-                r.rtype.get_str()
-            )
-        }
-        _ => {}
-    }
+fn format_rdata(r: &Answer) -> String {
+    r.rdata.to_string()
+}
+
+fn format_answer(r: &Answer) -> String {
+    format!("{:16} {} {} {}", format_rdata(r), r.ttl, r.rclass, r.rtype)
+}
+
+// follow each name in text that carries A-labels with its U-label form,
+// e.g. xn--bcher-kva.de. (bücher.de.)
+pub(crate) fn show_idn(text: &str) -> String {
+    text.split(' ')
+        .map(|t| match t.parse::<Name>() {
+            Ok(n) if n.is_idn() => format!("{} ({})", t, n.to_unicode()),
+            _ => t.to_string(),
+        })
+        .collect::<Vec<String>>()
+        .join(" ")
 }
 
 fn read_lines<P>(filename: P) -> io::Result<io::Lines<io::BufReader<File>>>
@@ -58,23 +87,280 @@ pub fn parse_resolvconf_nameserver(conf: Option<String>) -> String {
     return String::from("127.0.0.1");
 }
 
-pub fn do_lookup(hostname: String, nameserver: String) {
-    let response = dns::Query::do_query(
-        hostname,
-        nameserver.to_string(),
-        dns::RecordType::A,
-        dns::RecordClass::IN,
-    );
-
-    match response {
-        Some(r) => {
-            println!("{}", r.question.get_name_str());
-            for a in r.answer {
-                display_answer(a);
+// add the default DNS port to a server unless it already carries one
+pub fn with_default_port(server: &str) -> String {
+    if server.parse::<SocketAddr>().is_ok() {
+        return server.to_string();
+    }
+
+    match server.parse::<IpAddr>() {
+        Ok(IpAddr::V6(ip)) => format!("[{}]:53", ip),
+        _ if server.contains(':') => server.to_string(),
+        _ => format!("{}:53", server),
+    }
+}
+
+// Resolve a server given as an IP or hostname, with or without a port,
+// through the system resolver. Queries are sent from a socket of the
+// address's family; IPv4 is still preferred as hosts without a working
+// IPv6 route are the more common case.
+pub fn resolve_server(server: &str) -> Result<SocketAddr, String> {
+    let addrs: Vec<SocketAddr> = with_default_port(server)
+        .to_socket_addrs()
+        .map_err(|e| format!("failed to resolve server {}: {}", server, e))?
+        .collect();
+
+    addrs
+        .iter()
+        .find(|a| a.is_ipv4())
+        .or_else(|| addrs.first())
+        .copied()
+        .ok_or(format!("no addresses found for server {}", server))
+}
+
+// perform a lookup and render the result the way do_lookup prints it;
+// safe to call from several threads at once
+pub fn lookup(
+    hostname: String,
+    nameserver: String,
+    rtype: RecordType,
+    rclass: RecordClass,
+) -> Option<String> {
+    let response = dns::Query::do_query(hostname.clone(), nameserver, rtype, rclass)?;
+    Some(render(&response, hostname, true))
+}
+
+fn render(response: &Response, hostname: String, idn: bool) -> String {
+    let mut out = match &response.question {
+        Some(q) => q.get_name_str(),
+        None => hostname,
+    };
+    for a in &response.answer {
+        out.push('\n');
+        out.push_str(&format_answer(a));
+    }
+
+    if idn {
+        out = out
+            .lines()
+            .map(show_idn)
+            .collect::<Vec<String>>()
+            .join("\n");
+    }
+    out
+}
+
+// run a single query spec, using nameserver unless the spec names its own
+pub fn lookup_spec(spec: &QuerySpec, nameserver: &str) -> Result<String, String> {
+    let server = match &spec.server {
+        Some(s) => resolve_server(s)?.to_string(),
+        None => nameserver.to_string(),
+    };
+
+    if spec.wire || spec.is_crafted() {
+        return lookup_raw(spec, server);
+    }
+
+    let response = dns::Query::do_query(spec.name.clone(), server, spec.rtype, spec.rclass)
+        .ok_or(format!("{}: DNS Lookup failed", spec.name))?;
+
+    Ok(render_spec(spec, &response))
+}
+
+// send the spec's query once, giving up after timeout, and return the
+// response with its round trip time
+pub fn time_lookup(
+    spec: &QuerySpec,
+    nameserver: &str,
+    timeout: Duration,
+) -> Result<(Response, Duration), String> {
+    let server = match &spec.server {
+        Some(s) => resolve_server(s)?,
+        None => nameserver
+            .parse::<SocketAddr>()
+            .map_err(|e| format!("{}: {}", nameserver, e))?,
+    };
+
+    let query = spec.to_bytes()?;
+    let start = Instant::now();
+    let received = dns::exchange_udp(server, &query, timeout).map_err(|e| match e.kind() {
+        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => "timed out".to_string(),
+        _ => format!("Query failed: {}", e),
+    })?;
+    let time = start.elapsed();
+
+    Ok((Response::from_bytes(received)?, time))
+}
+
+// look up by bytes, for crafted queries and --wire, which shows the
+// annotated query and response before the usual output
+fn lookup_raw(spec: &QuerySpec, server: String) -> Result<String, String> {
+    let (sent, received) = if spec.is_crafted() {
+        let addr = server
+            .parse::<SocketAddr>()
+            .map_err(|e| format!("{}: {}", server, e))?;
+        let sent = spec.to_bytes()?;
+        let received = dns::exchange_udp(addr, &sent, CRAFTED_TIMEOUT)
+            .map_err(|e| format!("Query failed: {}", e))?;
+        (sent, received)
+    } else {
+        dns::Query::do_query_wire(spec.name.clone(), server.clone(), spec.rtype, spec.rclass)?
+    };
+
+    let dump = match spec.wire {
+        true => format!(
+            ";; sent to {}\n{}\n;; received from {}\n{}\n",
+            server,
+            wire::annotate(&sent),
+            server,
+            wire::annotate(&received)
+        ),
+        false => String::new(),
+    };
+    match Response::from_bytes(received) {
+        Ok(response) => Ok(format!("{}{}", dump, render_spec(spec, &response))),
+        Err(e) => Err(format!("{}{}: {}", dump, spec.name, e)),
+    }
+}
+
+fn render_spec(spec: &QuerySpec, response: &Response) -> String {
+    if !spec.short {
+        return render(response, spec.name.clone(), spec.idn);
+    }
+
+    response
+        .answer
+        .iter()
+        .map(|a| match spec.idn {
+            true => show_idn(&format_rdata(a)),
+            false => format_rdata(a),
+        })
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+// transfer zone from server and render it in master file format
+pub fn zone_transfer(zone: &str, server: &str, key: Option<&Key>) -> Result<String, String> {
+    let records = xfr::axfr(zone, server, key)?;
+
+    let mut out = format!("; {} transferred from {}\n", zone, server);
+    for r in &records {
+        out.push_str(&r.to_string());
+        out.push('\n');
+    }
+    out.push_str(&format!("; {} records", records.len()));
+
+    Ok(out)
+}
+
+// request the changes to zone since serial and render each serial step
+// as removed (-) and added (+) records
+pub fn zone_diff(
+    zone: &str,
+    server: &str,
+    serial: u32,
+    key: Option<&Key>,
+) -> Result<String, String> {
+    let mut out = format!("; {} changes since serial {} from {}", zone, serial, server);
+
+    match xfr::ixfr(zone, server, serial, key)? {
+        xfr::Ixfr::UpToDate(soa) => {
+            out.push_str(&format!("\n; up to date\n{}", soa));
+        }
+        xfr::Ixfr::Full(records) => {
+            out.push_str("\n; server sent the full zone");
+            for r in &records {
+                out.push_str(&format!("\n{}", r));
             }
         }
-        None => {
-            eprintln!("DNS Lookup failed");
+        xfr::Ixfr::Incremental(diffs) => {
+            for d in &diffs {
+                out.push_str(&format!("\n; serial {} -> {}", d.from, d.to));
+                for r in &d.removed {
+                    out.push_str(&format!("\n- {}", r));
+                }
+                for r in &d.added {
+                    out.push_str(&format!("\n+ {}", r));
+                }
+            }
         }
     }
+
+    Ok(out)
+}
+
+pub fn do_lookup(hostname: String, nameserver: String) {
+    match lookup(hostname, nameserver, RecordType::A, RecordClass::IN) {
+        Some(out) => println!("{}", out),
+        None => eprintln!("DNS Lookup failed"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use server::{Server, Zone};
+    use std::net::{TcpListener, UdpSocket};
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    fn test_with_default_port() {
+        assert!(with_default_port("1.1.1.1") == "1.1.1.1:53");
+        assert!(with_default_port("1.1.1.1:5353") == "1.1.1.1:5353");
+        assert!(with_default_port("::1") == "[::1]:53");
+        assert!(with_default_port("[::1]:5353") == "[::1]:5353");
+        assert!(with_default_port("ns1.example.com") == "ns1.example.com:53");
+    }
+
+    #[test]
+    fn test_show_idn() {
+        assert!(show_idn("xn--bcher-kva.de.") == "xn--bcher-kva.de. (bücher.de.)");
+        assert!(
+            show_idn("10 mx.xn--bcher-kva.de. 300 IN MX")
+                == "10 mx.xn--bcher-kva.de. (mx.bücher.de.) 300 IN MX"
+        );
+        assert!(show_idn("192.0.2.1        300 IN A") == "192.0.2.1        300 IN A");
+    }
+
+    #[test]
+    fn test_lookup_wire() {
+        let zone = "$ORIGIN example.com.
+@    3600 IN SOA ns1 hostmaster 1 3600 600 86400 300
+www  300  IN A   192.0.2.1
+";
+        let zone = zone::parse_zone(zone, "example.com.").unwrap();
+        let server = Arc::new(Server::new(vec![Zone::new("example.com.", zone).unwrap()]));
+        let udp = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let tcp = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = udp.local_addr().unwrap().to_string();
+        thread::spawn(move || server.serve(udp, tcp));
+
+        let mut spec = QuerySpec::new("www.example.com".to_string());
+        spec.wire = true;
+        let out = lookup_spec(&spec, &addr).unwrap();
+        let (sent, received) = out.split_once(";; received from").unwrap();
+
+        assert!(sent.starts_with(&format!(";; sent to {}\n; 33 bytes\n", addr)));
+        assert!(sent.contains("flags: rd ad; opcode QUERY (0); rcode NOERROR (0)"));
+        assert!(received.contains("flags: qr aa rd; opcode QUERY (0); rcode NOERROR (0)"));
+        assert!(received.contains("RDATA: 192.0.2.1"));
+        assert!(received.ends_with("www.example.com.\n192.0.2.1        300 IN A"));
+
+        let mut spec = QuerySpec::parse("www.example.com +norec +cd +ednsopt=10:0102").unwrap();
+        spec.wire = true;
+        let out = lookup_spec(&spec, &addr).unwrap();
+        let (sent, received) = out.split_once(";; received from").unwrap();
+        assert!(sent.contains("flags: ad cd; opcode QUERY (0)"));
+        assert!(sent.contains("option: COOKIE (10)"));
+        assert!(received.contains("flags: qr aa; opcode QUERY (0)"));
+        assert!(received.ends_with("192.0.2.1        300 IN A"));
+    }
+
+    #[test]
+    fn test_resolve_server() {
+        assert!(resolve_server("127.0.0.1") == Ok("127.0.0.1:53".parse().unwrap()));
+        assert!(resolve_server("localhost:5353").unwrap().port() == 5353);
+        assert!(resolve_server("nonexistent.invalid").is_err());
+    }
 }
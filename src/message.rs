@@ -0,0 +1,769 @@
+// DNS messages - RFC 1035 4.1
+//
+// A Message holds all four sections and can be built for any opcode, so
+// the same type serves queries, responses, updates and notifies. Names are
+// compressed on write wherever RFC 1035 allows it. MessageRef reads a
+// message in place for hot paths that only look at part of it.
+use byteorder::{BigEndian, ByteOrder};
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::dns::{
+    encode_name, push_label, Answer, Header, HeaderFlags, Question, RecordClass, RecordType,
+    Response, HDR_SIZE, MAX_POINTERS,
+};
+use crate::name::Name;
+use crate::rdata::RData;
+
+// pointers can only reach the first 16K of a message
+const MAX_OFFSET: usize = 0x3fff;
+const MAX_NAME: usize = 255;
+
+// Names already written to a message and where, so later copies can point
+// back at them - RFC 1035 4.1.4
+pub(crate) struct Compressor {
+    offsets: Option<HashMap<Vec<u8>, u16>>,
+}
+
+impl Compressor {
+    pub(crate) fn new() -> Self {
+        Self {
+            offsets: Some(HashMap::new()),
+        }
+    }
+
+    // writes every name in full, for data outside a message
+    pub(crate) fn disabled() -> Self {
+        Self { offsets: None }
+    }
+
+    // Append name to msg, replacing its longest already written suffix
+    // with a pointer. msg must be the whole message so far.
+    pub(crate) fn write(&mut self, msg: &mut Vec<u8>, name: &str) -> Result<(), String> {
+        self.write_wire(msg, &encode_name(name)?);
+        Ok(())
+    }
+
+    // as write, for a name already in uncompressed wire format
+    pub(crate) fn write_wire(&mut self, msg: &mut Vec<u8>, wire: &[u8]) {
+        let offsets = match self.offsets.as_mut() {
+            Some(o) => o,
+            None => return msg.extend(wire),
+        };
+
+        let mut pos = 0;
+        while wire[pos] != 0 {
+            // suffixes match case-insensitively
+            let suffix = wire[pos..].to_ascii_lowercase();
+            if let Some(&target) = offsets.get(&suffix) {
+                msg.extend(&wire[..pos]);
+                msg.extend((0xc000 | target).to_be_bytes());
+                return;
+            }
+            if msg.len() + pos <= MAX_OFFSET {
+                offsets.insert(suffix, (msg.len() + pos) as u16);
+            }
+            pos += wire[pos] as usize + 1;
+        }
+        msg.extend(wire);
+    }
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub struct Message {
+    pub header: Header,
+    pub questions: Vec<Question>,
+    pub answer: Vec<Answer>,
+    pub authority: Vec<Answer>,
+    pub additional: Vec<Answer>,
+}
+
+impl Default for Message {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Message {
+    // an empty message with a random ID and no flags set
+    pub fn new() -> Self {
+        Self {
+            header: Header::new(None),
+            questions: Vec::new(),
+            answer: Vec::new(),
+            authority: Vec::new(),
+            additional: Vec::new(),
+        }
+    }
+
+    // a recursive query for name
+    pub fn query(name: &str, rtype: RecordType, rclass: RecordClass) -> Result<Self, String> {
+        let mut msg = Self::new();
+        msg.set_rd(true).add_question(name, rtype, rclass)?;
+        Ok(msg)
+    }
+
+    // an empty response to query, echoing its ID, opcode, RD and question
+    pub fn response_to(query: &Message) -> Self {
+        let mut msg = Self::new();
+        msg.set_id(query.header.id)
+            .set_qr(true)
+            .set_opcode(query.header.flags.opcode())
+            .set_rd(query.header.flags.rd());
+        msg.questions = query.questions.clone();
+        msg
+    }
+
+    pub fn set_id(&mut self, id: u16) -> &mut Self {
+        self.header.id = id;
+        self
+    }
+
+    pub fn set_opcode(&mut self, opcode: u8) -> &mut Self {
+        self.header.flags.set_opcode(opcode);
+        self
+    }
+
+    pub fn set_rcode(&mut self, rcode: u8) -> &mut Self {
+        self.header.flags.set_rcode(rcode);
+        self
+    }
+
+    pub fn set_qr(&mut self, qr: bool) -> &mut Self {
+        self.header.flags.set_qr(qr);
+        self
+    }
+
+    pub fn set_aa(&mut self, aa: bool) -> &mut Self {
+        self.header.flags.set_aa(aa);
+        self
+    }
+
+    pub fn set_tc(&mut self, tc: bool) -> &mut Self {
+        self.header.flags.set_tc(tc);
+        self
+    }
+
+    pub fn set_rd(&mut self, rd: bool) -> &mut Self {
+        self.header.flags.set_rd(rd);
+        self
+    }
+
+    pub fn set_ra(&mut self, ra: bool) -> &mut Self {
+        self.header.flags.set_ra(ra);
+        self
+    }
+
+    pub fn set_z(&mut self, z: bool) -> &mut Self {
+        self.header.flags.set_z(z);
+        self
+    }
+
+    pub fn set_ad(&mut self, ad: bool) -> &mut Self {
+        self.header.flags.set_ad(ad);
+        self
+    }
+
+    pub fn set_cd(&mut self, cd: bool) -> &mut Self {
+        self.header.flags.set_cd(cd);
+        self
+    }
+
+    // fails if name is not a valid domain name
+    pub fn add_question(
+        &mut self,
+        name: &str,
+        rtype: RecordType,
+        rclass: RecordClass,
+    ) -> Result<&mut Self, String> {
+        self.questions
+            .push(Question::new(name.to_string(), rtype, rclass)?);
+        Ok(self)
+    }
+
+    pub fn add_answer(&mut self, rr: Answer) -> &mut Self {
+        self.answer.push(rr);
+        self
+    }
+
+    pub fn add_authority(&mut self, rr: Answer) -> &mut Self {
+        self.authority.push(rr);
+        self
+    }
+
+    pub fn add_additional(&mut self, rr: Answer) -> &mut Self {
+        self.additional.push(rr);
+        self
+    }
+
+    // Wire format with compressed names; section counts come from the
+    // sections rather than the header. Fails if a name cannot be encoded.
+    pub fn to_bytes(&self) -> Result<Vec<u8>, String> {
+        let mut header = self.header.clone();
+        header.qd_count = self.questions.len() as u16;
+        header.an_count = self.answer.len() as u16;
+        header.ns_count = self.authority.len() as u16;
+        header.ar_count = self.additional.len() as u16;
+
+        let mut msg = header.to_bytes();
+        let mut names = Compressor::new();
+        for q in &self.questions {
+            q.write(&mut msg, &mut names);
+        }
+        for rr in self
+            .answer
+            .iter()
+            .chain(&self.authority)
+            .chain(&self.additional)
+        {
+            rr.write(&mut msg, &mut names)?;
+        }
+        Ok(msg)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, String> {
+        let header = Header::from_bytes(bytes)?;
+        let mut ptr = HDR_SIZE;
+
+        let mut questions = Vec::new();
+        for _ in 0..header.qd_count {
+            let (q, len) = Question::from_bytes(bytes, ptr)?;
+            ptr += len;
+            questions.push(q);
+        }
+
+        let mut sections: [Vec<Answer>; 3] = Default::default();
+        let counts = [header.an_count, header.ns_count, header.ar_count];
+        for (section, count) in sections.iter_mut().zip(counts) {
+            for _ in 0..count {
+                let (rr, len) = Answer::from_bytes(bytes, ptr)?;
+                ptr += len;
+                section.push(rr);
+            }
+        }
+        let [answer, authority, additional] = sections;
+
+        Ok(Self {
+            header,
+            questions,
+            answer,
+            authority,
+            additional,
+        })
+    }
+}
+
+impl From<Response> for Message {
+    fn from(resp: Response) -> Self {
+        Self {
+            header: resp.header,
+            questions: resp.question.into_iter().collect(),
+            answer: resp.answer,
+            authority: resp.authority,
+            additional: resp.additional,
+        }
+    }
+}
+
+// a Response keeps only the first question
+impl From<Message> for Response {
+    fn from(msg: Message) -> Self {
+        Self {
+            header: msg.header,
+            question: msg.questions.into_iter().next(),
+            answer: msg.answer,
+            authority: msg.authority,
+            additional: msg.additional,
+        }
+    }
+}
+
+// Check the name at offset in msg, following pointers, without decoding
+// it. Returns the bytes it occupies at offset - RFC 1035 4.1.4
+fn check_name(msg: &[u8], offset: usize) -> Result<usize, String> {
+    let mut ptr = offset;
+    let mut consumed = None;
+    let mut jumps = 0;
+    let mut total = 0;
+
+    loop {
+        let len = *msg.get(ptr).ok_or("name runs past end of message")? as usize;
+        match len & 0xc0 {
+            0x00 if len == 0 => break,
+            0x00 => {
+                total += len + 1;
+                if total > MAX_NAME {
+                    return Err("name longer than 255 bytes".to_string());
+                }
+                ptr += len + 1;
+            }
+            0xc0 => {
+                let low = *msg.get(ptr + 1).ok_or("pointer runs past end of message")? as usize;
+                if consumed.is_none() {
+                    consumed = Some(ptr + 2 - offset);
+                }
+                jumps += 1;
+                if jumps > MAX_POINTERS {
+                    return Err("compression pointer loop".to_string());
+                }
+                ptr = ((len & 0x3f) << 8) | low;
+            }
+            _ => return Err(format!("unsupported label type {:#04x}", len)),
+        }
+    }
+
+    Ok(consumed.unwrap_or_else(|| ptr + 1 - offset))
+}
+
+// the bytes a name checked by check_name occupies at offset
+fn skip_name(msg: &[u8], mut offset: usize) -> usize {
+    let start = offset;
+    loop {
+        match msg[offset] {
+            0 => return offset + 1 - start,
+            len if len & 0xc0 == 0xc0 => return offset + 2 - start,
+            len => offset += len as usize + 1,
+        }
+    }
+}
+
+// A name inside a message, decoded only as it is read
+#[derive(Clone, Copy)]
+pub struct NameRef<'a> {
+    msg: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> NameRef<'a> {
+    // the labels from the leftmost, without the root
+    pub fn labels(&self) -> Labels<'a> {
+        Labels {
+            msg: self.msg,
+            ptr: self.offset,
+        }
+    }
+
+    pub fn to_name(&self) -> Result<Name, String> {
+        Name::from_labels(self.labels().map(|l| l.to_vec()).collect())
+    }
+
+    pub fn eq_ignore_ascii_case(&self, other: &NameRef) -> bool {
+        let mut a = self.labels();
+        let mut b = other.labels();
+        loop {
+            match (a.next(), b.next()) {
+                (None, None) => return true,
+                (Some(x), Some(y)) if x.eq_ignore_ascii_case(y) => {}
+                _ => return false,
+            }
+        }
+    }
+}
+
+// presentation format with its trailing dot
+impl fmt::Display for NameRef<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut name = String::new();
+        for label in self.labels() {
+            push_label(&mut name, label);
+            name.push('.');
+        }
+        if name.is_empty() {
+            name.push('.');
+        }
+        f.write_str(&name)
+    }
+}
+
+pub struct Labels<'a> {
+    msg: &'a [u8],
+    ptr: usize,
+}
+
+impl<'a> Iterator for Labels<'a> {
+    type Item = &'a [u8];
+
+    fn next(&mut self) -> Option<&'a [u8]> {
+        loop {
+            let len = self.msg[self.ptr] as usize;
+            match len {
+                0 => return None,
+                _ if len & 0xc0 == 0xc0 => {
+                    self.ptr = ((len & 0x3f) << 8) | self.msg[self.ptr + 1] as usize;
+                }
+                _ => {
+                    let label = &self.msg[self.ptr + 1..self.ptr + 1 + len];
+                    self.ptr += len + 1;
+                    return Some(label);
+                }
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct QuestionRef<'a> {
+    pub name: NameRef<'a>,
+    pub rtype: RecordType,
+    pub rclass: RecordClass,
+}
+
+#[derive(Clone, Copy)]
+pub struct RecordRef<'a> {
+    pub name: NameRef<'a>,
+    pub rtype: RecordType,
+    pub rclass: RecordClass,
+    pub ttl: u32,
+    msg: &'a [u8],
+    data: usize,
+    len: usize,
+}
+
+impl<'a> RecordRef<'a> {
+    // the raw rdata, in which names may point elsewhere in the message
+    pub fn data(&self) -> &'a [u8] {
+        &self.msg[self.data..self.data + self.len]
+    }
+
+    pub fn rdata(&self) -> Result<RData, String> {
+        RData::from_bytes(self.rtype, self.msg, self.data, self.len)
+    }
+
+    pub fn to_answer(&self) -> Result<Answer, String> {
+        Ok(Answer::new(
+            self.name.to_string(),
+            self.rtype,
+            self.rclass,
+            self.ttl,
+            self.rdata()?,
+        ))
+    }
+}
+
+// the records of one section, read in order
+pub struct Records<'a> {
+    msg: &'a [u8],
+    ptr: usize,
+    remaining: u16,
+}
+
+impl<'a> Iterator for Records<'a> {
+    type Item = RecordRef<'a>;
+
+    fn next(&mut self) -> Option<RecordRef<'a>> {
+        if self.remaining == 0 {
+            return None;
+        }
+        self.remaining -= 1;
+
+        let name = NameRef {
+            msg: self.msg,
+            offset: self.ptr,
+        };
+        let fixed = self.ptr + skip_name(self.msg, self.ptr);
+        let len = BigEndian::read_u16(&self.msg[fixed + 8..]) as usize;
+        self.ptr = fixed + 10 + len;
+
+        Some(RecordRef {
+            name,
+            rtype: RecordType::from_u16(BigEndian::read_u16(&self.msg[fixed..])),
+            rclass: RecordClass::from_u16(BigEndian::read_u16(&self.msg[fixed + 2..])),
+            ttl: BigEndian::read_u32(&self.msg[fixed + 4..]),
+            msg: self.msg,
+            data: fixed + 10,
+            len,
+        })
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining as usize, Some(self.remaining as usize))
+    }
+}
+
+// A message borrowed from the buffer it arrived in. Parsing checks the
+// layout once without copying; names and rdata are only decoded when
+// asked for, and to_message makes an owned copy.
+#[derive(Clone, Copy)]
+pub struct MessageRef<'a> {
+    msg: &'a [u8],
+    header: &'a [u8],
+    // where the question, answer, authority and additional sections start
+    sections: [usize; 4],
+}
+
+impl<'a> MessageRef<'a> {
+    pub fn parse(msg: &'a [u8]) -> Result<Self, String> {
+        if msg.len() < HDR_SIZE {
+            return Err(format!("message too short; only {} bytes", msg.len()));
+        }
+
+        let mut sections = [HDR_SIZE; 4];
+        let mut ptr = HDR_SIZE;
+        for _ in 0..BigEndian::read_u16(&msg[4..]) {
+            ptr += check_name(msg, ptr)? + 4;
+            if ptr > msg.len() {
+                return Err("question runs past end of message".to_string());
+            }
+        }
+
+        for (i, count_at) in [6, 8, 10].into_iter().enumerate() {
+            sections[i + 1] = ptr;
+            for _ in 0..BigEndian::read_u16(&msg[count_at..]) {
+                ptr += check_name(msg, ptr)?;
+                let fixed = msg
+                    .get(ptr..ptr + 10)
+                    .ok_or("record runs past end of message")?;
+                ptr += 10 + BigEndian::read_u16(&fixed[8..]) as usize;
+                if ptr > msg.len() {
+                    return Err("record data runs past end of message".to_string());
+                }
+            }
+        }
+
+        Ok(Self {
+            msg,
+            header: &msg[..HDR_SIZE],
+            sections,
+        })
+    }
+
+    // the whole message as received
+    pub fn bytes(&self) -> &'a [u8] {
+        self.msg
+    }
+
+    pub fn id(&self) -> u16 {
+        BigEndian::read_u16(self.header)
+    }
+
+    pub fn flags(&self) -> HeaderFlags<[u16; 1]> {
+        HeaderFlags([BigEndian::read_u16(&self.header[2..])])
+    }
+
+    pub fn header(&self) -> Header {
+        Header::from_bytes(self.header).expect("length checked in parse")
+    }
+
+    pub fn questions(&self) -> impl Iterator<Item = QuestionRef<'a>> {
+        let msg = self.msg;
+        let mut ptr = self.sections[0];
+        (0..BigEndian::read_u16(&self.header[4..])).map(move |_| {
+            let name = NameRef { msg, offset: ptr };
+            let fixed = ptr + skip_name(msg, ptr);
+            ptr = fixed + 4;
+            QuestionRef {
+                name,
+                rtype: RecordType::from_u16(BigEndian::read_u16(&msg[fixed..])),
+                rclass: RecordClass::from_u16(BigEndian::read_u16(&msg[fixed + 2..])),
+            }
+        })
+    }
+
+    fn records(&self, section: usize) -> Records<'a> {
+        Records {
+            msg: self.msg,
+            ptr: self.sections[section],
+            remaining: BigEndian::read_u16(&self.header[4 + 2 * section..]),
+        }
+    }
+
+    pub fn answer(&self) -> Records<'a> {
+        self.records(1)
+    }
+
+    pub fn authority(&self) -> Records<'a> {
+        self.records(2)
+    }
+
+    pub fn additional(&self) -> Records<'a> {
+        self.records(3)
+    }
+
+    // an owned copy, decoding every name and rdata
+    pub fn to_message(&self) -> Result<Message, String> {
+        let section = |records: Records| -> Result<Vec<Answer>, String> {
+            records.map(|r| r.to_answer()).collect()
+        };
+        Ok(Message {
+            header: self.header(),
+            questions: self
+                .questions()
+                .map(|q| Ok(Question::for_name(&q.name.to_name()?, q.rtype, q.rclass)))
+                .collect::<Result<_, String>>()?,
+            answer: section(self.answer())?,
+            authority: section(self.authority())?,
+            additional: section(self.additional())?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mx(name: &str, exchange: &str) -> Answer {
+        Answer::new(
+            name.to_string(),
+            RecordType::MX,
+            RecordClass::IN,
+            300,
+            RData::MX {
+                preference: 10,
+                exchange: exchange.to_string(),
+            },
+        )
+    }
+
+    #[test]
+    fn test_compression() {
+        let mut msg = Message::query("example.com", RecordType::MX, RecordClass::IN).unwrap();
+        msg.set_id(1)
+            .set_qr(true)
+            .add_answer(mx("example.com.", "mail.EXAMPLE.com."));
+        let bytes = msg.to_bytes().unwrap();
+
+        // the owner points at the question, the exchange at its suffix
+        let question = HDR_SIZE + 13 + 4;
+        assert!(bytes[question..question + 2] == [0xc0, 12]);
+        let exchange = question + 2 + 10 + 2;
+        assert!(bytes[exchange..] == [4, b'm', b'a', b'i', b'l', 0xc0, 12]);
+
+        let parsed = Message::from_bytes(&bytes).unwrap();
+        assert!(parsed.answer[0].rdata == mx("x.", "mail.example.com.").rdata);
+        assert!(parsed.answer[0].name == "example.com.");
+        assert!(parsed.header.flags.qr() && parsed.header.flags.rd());
+    }
+
+    #[test]
+    fn test_names_kept_as_given() {
+        // a zone may hold names that fail the IDNA checks given to typed names
+        let bad = "xn--bcher-kva-.example.com.";
+        assert!(bad.parse::<Name>().is_err());
+        let records =
+            crate::zone::parse_zone(&format!("$TTL 60\n{} MX 10 {}\n", bad, bad), "example.com.")
+                .unwrap();
+        let (labels, _) = crate::zone::parse_labels(bad).unwrap();
+        let name = Name::from_labels(labels).unwrap();
+
+        let mut msg = Message::new();
+        msg.set_qr(true).answer = records;
+        msg.questions
+            .push(Question::for_name(&name, RecordType::MX, RecordClass::IN));
+        let bytes = msg.to_bytes().unwrap();
+
+        let parsed = Message::from_bytes(&bytes).unwrap();
+        assert!(parsed.questions[0].get_name_str() == bad);
+        assert!(parsed.answer[0].name == bad);
+        assert!(parsed.answer[0].rdata == mx("x.", bad).rdata);
+        assert!(MessageRef::parse(&bytes).unwrap().to_message().unwrap() == parsed);
+        let resp = Response::from_bytes(bytes).unwrap();
+        assert!(resp.question.unwrap().get_name_str() == bad);
+    }
+
+    #[test]
+    fn test_builder_and_response() {
+        let mut query = Message::query("www.example.com", RecordType::A, RecordClass::IN).unwrap();
+        query
+            .set_opcode(0)
+            .add_question("www.example.net", RecordType::AAAA, RecordClass::IN)
+            .unwrap();
+        let query = Message::from_bytes(&query.to_bytes().unwrap()).unwrap();
+        assert!(query.questions.len() == 2 && query.header.qd_count == 2);
+
+        let mut resp = Message::response_to(&query);
+        resp.set_aa(true).set_rcode(3);
+        let resp = Message::from_bytes(&resp.to_bytes().unwrap()).unwrap();
+        assert!(resp.header.id == query.header.id);
+        assert!(resp.header.flags.qr() && resp.header.flags.aa() && resp.header.flags.rd());
+        assert!(resp.header.flags.rcode() == 3);
+        assert!(resp.questions == query.questions);
+    }
+
+    #[test]
+    fn test_srv_target_not_compressed() {
+        let srv = Answer::new(
+            "_sip._udp.example.com.".to_string(),
+            RecordType::SRV,
+            RecordClass::IN,
+            300,
+            RData::SRV {
+                priority: 0,
+                weight: 0,
+                port: 5060,
+                target: "example.com.".to_string(),
+            },
+        );
+        let mut msg = Message::new();
+        msg.add_answer(srv);
+        let bytes = msg.to_bytes().unwrap();
+        assert!(bytes.ends_with(&encode_name("example.com.").unwrap()));
+    }
+
+    #[test]
+    fn test_invalid_names() {
+        // names that are not valid domain names are refused, not written as root
+        let long = format!("{}.example.com.", "a".repeat(64));
+        for (name, exchange) in [
+            ("a..example.com.", "mail.example.com."),
+            ("example.com.", long.as_str()),
+        ] {
+            let mut msg = Message::new();
+            msg.add_answer(mx(name, exchange));
+            assert!(msg.to_bytes().is_err());
+        }
+        let mut msg = Message::new();
+        msg.add_answer(mx(".", "mail.example.com."));
+        assert!(msg.to_bytes().is_ok());
+    }
+
+    #[test]
+    fn test_message_ref() {
+        let mut msg = Message::query("example.com", RecordType::MX, RecordClass::IN).unwrap();
+        msg.set_qr(true)
+            .add_answer(mx("example.com.", "mail.example.com."))
+            .add_answer(mx("EXAMPLE.com.", "mx2.example.com."))
+            .add_additional(Answer::new(
+                "mail.example.com.".to_string(),
+                RecordType::A,
+                RecordClass::IN,
+                60,
+                RData::A([192, 0, 2, 25].into()),
+            ));
+        let bytes = msg.to_bytes().unwrap();
+
+        let view = MessageRef::parse(&bytes).unwrap();
+        assert!(view.id() == msg.header.id && view.flags().qr());
+        let question = view.questions().next().unwrap();
+        assert!(question.name.to_string() == "example.com." && question.rtype == RecordType::MX);
+
+        let answer: Vec<RecordRef> = view.answer().collect();
+        assert!(answer.len() == 2 && view.authority().count() == 0);
+        assert!(answer[1].name.eq_ignore_ascii_case(&question.name));
+        assert!(answer[1].rdata().unwrap() == mx("x.", "mx2.example.com.").rdata);
+        // the exchange's rdata points back into the question
+        assert!(answer[0].data() == [0, 10, 4, b'm', b'a', b'i', b'l', 0xc0, 12]);
+        assert!(view.additional().next().unwrap().ttl == 60);
+
+        assert!(view.to_message().unwrap() == Message::from_bytes(&bytes).unwrap());
+    }
+
+    #[test]
+    fn test_message_ref_malformed() {
+        let bytes = Message::query("example.com", RecordType::A, RecordClass::IN)
+            .unwrap()
+            .to_bytes()
+            .unwrap();
+        assert!(MessageRef::parse(&bytes[..bytes.len() - 1]).is_err());
+        assert!(MessageRef::parse(&bytes[..8]).is_err());
+
+        // a question whose name points at itself
+        let mut looped = bytes[..HDR_SIZE].to_vec();
+        looped.extend([0xc0, 12, 0, 1, 0, 1]);
+        assert!(MessageRef::parse(&looped).is_err());
+
+        // an answer count with no answer
+        let mut short = bytes.clone();
+        short[7] = 1;
+        assert!(MessageRef::parse(&short).is_err());
+    }
+}
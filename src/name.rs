@@ -0,0 +1,499 @@
+// Domain names - RFC 1035 3.1, 4.1.4 and 5.1
+//
+// A Name is a sequence of raw labels, leftmost first, without the empty
+// root label. Names are always fully qualified; the text form may omit the
+// trailing dot. Comparison ignores ASCII case, and ordering is the
+// canonical DNSSEC order of RFC 4034 6.1.
+//
+// Internationalized names (IDNA 2008, RFC 5891) are converted to A-labels
+// when parsed, using UTS 46 nontransitional processing, and can be shown
+// with their U-labels again through to_unicode.
+use std::cmp::Ordering;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::str::FromStr;
+
+use idna::uts46::{AsciiDenyList, DnsLength, Hyphens, Uts46};
+
+use crate::dns::{push_label, MAX_POINTERS};
+use crate::zone::parse_labels;
+
+pub const MAX_LABEL_LEN: usize = 63;
+pub const MAX_NAME_LEN: usize = 255;
+const ACE_PREFIX: &[u8] = b"xn--";
+
+#[derive(Clone, Debug, Default)]
+pub struct Name {
+    labels: Vec<Vec<u8>>,
+}
+
+impl Name {
+    pub fn root() -> Self {
+        Self::default()
+    }
+
+    // labels leftmost first, checked against the label and name limits
+    pub fn from_labels(labels: Vec<Vec<u8>>) -> Result<Self, String> {
+        if labels.iter().any(|l| l.is_empty()) {
+            return Err("empty label".to_string());
+        }
+        if let Some(l) = labels.iter().find(|l| l.len() > MAX_LABEL_LEN) {
+            return Err(format!(
+                "label of {} bytes is longer than {}",
+                l.len(),
+                MAX_LABEL_LEN
+            ));
+        }
+        let name = Self { labels };
+        if name.wire_len() > MAX_NAME_LEN {
+            return Err(format!(
+                "name of {} bytes is longer than {}",
+                name.wire_len(),
+                MAX_NAME_LEN
+            ));
+        }
+        Ok(name)
+    }
+
+    // Decode a possibly compressed name at offset in msg. Returns the name
+    // and the number of bytes it occupies at offset (pointer targets
+    // elsewhere in msg do not count).
+    pub fn from_wire(msg: &[u8], offset: usize) -> Result<(Self, usize), String> {
+        let mut labels = Vec::new();
+        let mut wire_len = 1;
+        let mut ptr = offset;
+        let mut consumed = None;
+        let mut jumps = 0;
+
+        loop {
+            let len = *msg.get(ptr).ok_or("name runs past end of message")? as usize;
+            match len & 0xc0 {
+                0x00 if len == 0 => break,
+                0x00 => {
+                    let label = msg
+                        .get(ptr + 1..ptr + 1 + len)
+                        .ok_or("label runs past end of message")?;
+                    wire_len += len + 1;
+                    if wire_len > MAX_NAME_LEN {
+                        return Err(format!("name is longer than {} bytes", MAX_NAME_LEN));
+                    }
+                    labels.push(label.to_vec());
+                    ptr += len + 1;
+                }
+                0xc0 => {
+                    let low = *msg.get(ptr + 1).ok_or("pointer runs past end of message")? as usize;
+                    if consumed.is_none() {
+                        consumed = Some(ptr + 2 - offset);
+                    }
+                    jumps += 1;
+                    if jumps > MAX_POINTERS {
+                        return Err("compression pointer loop".to_string());
+                    }
+                    ptr = ((len & 0x3f) << 8) | low;
+                }
+                _ => return Err(format!("unsupported label type {:#04x}", len)),
+            }
+        }
+
+        Ok((
+            Self { labels },
+            consumed.unwrap_or_else(|| ptr + 1 - offset),
+        ))
+    }
+
+    // uncompressed wire format, ending in the root label
+    pub fn to_wire(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(self.wire_len());
+        for l in &self.labels {
+            bytes.push(l.len() as u8);
+            bytes.extend(l);
+        }
+        bytes.push(0);
+        bytes
+    }
+
+    pub fn wire_len(&self) -> usize {
+        self.labels.iter().map(|l| l.len() + 1).sum::<usize>() + 1
+    }
+
+    pub fn labels(&self) -> &[Vec<u8>] {
+        &self.labels
+    }
+
+    pub fn is_root(&self) -> bool {
+        self.labels.is_empty()
+    }
+
+    pub fn is_wildcard(&self) -> bool {
+        self.labels.first().is_some_and(|l| l == b"*")
+    }
+
+    pub fn to_lowercase(&self) -> Self {
+        Self {
+            labels: self.labels.iter().map(|l| l.to_ascii_lowercase()).collect(),
+        }
+    }
+
+    // presentation format with each valid A-label shown as its U-label
+    pub fn to_unicode(&self) -> String {
+        if self.is_root() {
+            return ".".to_string();
+        }
+        let mut name = String::new();
+        for l in &self.labels {
+            match decode_a_label(l) {
+                Some(u) => name.push_str(&u),
+                None => push_label(&mut name, l),
+            }
+            name.push('.');
+        }
+        name
+    }
+
+    pub fn is_idn(&self) -> bool {
+        self.labels.iter().any(|l| is_a_label(l))
+    }
+
+    // the name with its leftmost label removed; None for the root
+    pub fn parent(&self) -> Option<Self> {
+        if self.is_root() {
+            return None;
+        }
+        Some(Self {
+            labels: self.labels[1..].to_vec(),
+        })
+    }
+
+    // label prepended to this name
+    pub fn child(&self, label: &[u8]) -> Result<Self, String> {
+        let mut labels = vec![label.to_vec()];
+        labels.extend(self.labels.iter().cloned());
+        Self::from_labels(labels)
+    }
+
+    // this name and each of its parents in turn, ending with the root
+    pub fn ancestors(&self) -> impl Iterator<Item = Name> + '_ {
+        (0..=self.labels.len()).map(move |i| Self {
+            labels: self.labels[i..].to_vec(),
+        })
+    }
+
+    // true if this name is other or lies beneath it
+    pub fn is_subdomain_of(&self, other: &Name) -> bool {
+        self.labels.len() >= other.labels.len()
+            && self.labels[self.labels.len() - other.labels.len()..]
+                .iter()
+                .zip(&other.labels)
+                .all(|(a, b)| a.eq_ignore_ascii_case(b))
+    }
+
+    // the longest name both this name and other are subdomains of
+    pub fn common_ancestor(&self, other: &Name) -> Name {
+        let shared = self
+            .labels
+            .iter()
+            .rev()
+            .zip(other.labels.iter().rev())
+            .take_while(|(a, b)| a.eq_ignore_ascii_case(b))
+            .count();
+        Self {
+            labels: self.labels[self.labels.len() - shared..].to_vec(),
+        }
+    }
+
+    // The name one label below ancestor on the way down to this one, i.e.
+    // where a zone cut beneath ancestor would have to be for this name to
+    // be delegated. None unless this name lies strictly below ancestor.
+    pub fn next_closer(&self, ancestor: &Name) -> Option<Name> {
+        if self.labels.len() <= ancestor.labels.len() || !self.is_subdomain_of(ancestor) {
+            return None;
+        }
+        Some(Self {
+            labels: self.labels[self.labels.len() - ancestor.labels.len() - 1..].to_vec(),
+        })
+    }
+
+    // labels from ancestor's child down to this name, leftmost first, or
+    // None if this name is not beneath ancestor
+    pub fn relative_to(&self, ancestor: &Name) -> Option<&[Vec<u8>]> {
+        if !self.is_subdomain_of(ancestor) {
+            return None;
+        }
+        Some(&self.labels[..self.labels.len() - ancestor.labels.len()])
+    }
+}
+
+fn is_a_label(label: &[u8]) -> bool {
+    label.len() > ACE_PREFIX.len() && label[..ACE_PREFIX.len()].eq_ignore_ascii_case(ACE_PREFIX)
+}
+
+// the U-label for an A-label, or None if label is not one or is invalid
+fn decode_a_label(label: &[u8]) -> Option<String> {
+    if !is_a_label(label) {
+        return None;
+    }
+    let (unicode, result) = Uts46::new().to_unicode(label, AsciiDenyList::EMPTY, Hyphens::Allow);
+    result.ok().map(|_| unicode.into_owned())
+}
+
+// Convert a name given in Unicode to A-labels, e.g. bücher.de to
+// xn--bcher-kva.de. ASCII names are returned as they are once any xn--
+// labels in them are found to decode.
+pub fn to_ascii(text: &str) -> Result<String, String> {
+    if text.is_ascii() {
+        if let Some(l) = text
+            .split('.')
+            .find(|l| is_a_label(l.as_bytes()) && decode_a_label(l.as_bytes()).is_none())
+        {
+            return Err(format!("invalid A-label {}", l));
+        }
+        return Ok(text.to_string());
+    }
+
+    Uts46::new()
+        .to_ascii(
+            text.as_bytes(),
+            AsciiDenyList::EMPTY,
+            Hyphens::Check,
+            DnsLength::VerifyAllowRootDot,
+        )
+        .map(|n| n.into_owned())
+        .map_err(|_| "not a valid internationalized name".to_string())
+}
+
+// Presentation format with \. and \DDD escapes; the trailing dot is
+// optional and the name is always taken as fully qualified. Unicode names
+// are converted to A-labels.
+impl FromStr for Name {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, String> {
+        let (labels, _) = parse_labels(&to_ascii(text)?)?;
+        Self::from_labels(labels)
+    }
+}
+
+impl fmt::Display for Name {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.is_root() {
+            return f.write_str(".");
+        }
+        let mut name = String::new();
+        for l in &self.labels {
+            push_label(&mut name, l);
+            name.push('.');
+        }
+        f.write_str(&name)
+    }
+}
+
+impl PartialEq for Name {
+    fn eq(&self, other: &Self) -> bool {
+        self.labels.len() == other.labels.len()
+            && self
+                .labels
+                .iter()
+                .zip(&other.labels)
+                .all(|(a, b)| a.eq_ignore_ascii_case(b))
+    }
+}
+
+impl Eq for Name {}
+
+impl Hash for Name {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.labels.len().hash(state);
+        for l in &self.labels {
+            l.len().hash(state);
+            for b in l {
+                b.to_ascii_lowercase().hash(state);
+            }
+        }
+    }
+}
+
+// Canonical order - RFC 4034 6.1: compare the rightmost labels first, each
+// as lowercased octets, with a name sorting before its subdomains
+impl Ord for Name {
+    fn cmp(&self, other: &Self) -> Ordering {
+        for (a, b) in self.labels.iter().rev().zip(other.labels.iter().rev()) {
+            let ord = a
+                .iter()
+                .map(u8::to_ascii_lowercase)
+                .cmp(b.iter().map(u8::to_ascii_lowercase));
+            if ord != Ordering::Equal {
+                return ord;
+            }
+        }
+        self.labels.len().cmp(&other.labels.len())
+    }
+}
+
+impl PartialOrd for Name {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    fn name(text: &str) -> Name {
+        text.parse().unwrap()
+    }
+
+    #[test]
+    fn test_name_parse() {
+        assert!(
+            name("www.example.com").labels()
+                == [b"www".to_vec(), b"example".to_vec(), b"com".to_vec()]
+        );
+        assert!(name("www.example.com.") == name("www.example.com"));
+        assert!(name(".").is_root());
+        assert!(name(r"a\.b.example").labels()[0] == b"a.b");
+        assert!(name(r"\065\032b.example").labels()[0] == b"A b");
+        assert!(name(r"a\.b.example").to_string() == r"a\.b.example.");
+        assert!(name(r"\000.example").to_string() == r"\000.example.");
+
+        assert!("".parse::<Name>().is_err());
+        assert!("a..example".parse::<Name>().is_err());
+        assert!(".example".parse::<Name>().is_err());
+        assert!(r"bad\".parse::<Name>().is_err());
+        assert!(r"\256.example".parse::<Name>().is_err());
+        assert!(format!("{}.example", "a".repeat(64))
+            .parse::<Name>()
+            .is_err());
+        assert!(format!("{}.example", "a".repeat(63))
+            .parse::<Name>()
+            .is_ok());
+
+        // 4 labels of 63 bytes and the root make 257 bytes
+        let long = vec!["a".repeat(63); 4].join(".");
+        assert!(long.parse::<Name>().unwrap_err().contains("255"));
+        let long = [
+            "a".repeat(63),
+            "a".repeat(63),
+            "a".repeat(63),
+            "a".repeat(61),
+        ]
+        .join(".");
+        assert!(long.parse::<Name>().unwrap().wire_len() == 255);
+    }
+
+    #[test]
+    fn test_name_wire() {
+        let n = name(r"w\.w.Example.com");
+        let wire = n.to_wire();
+        assert!(wire == b"\x03w.w\x07Example\x03com\x00");
+        assert!(Name::from_wire(&wire, 0) == Ok((n, wire.len())));
+
+        // www at 13 points back to example.com at 0; 19 points at itself
+        let mut msg = b"\x07example\x03com\x00\x03www\xc0\x00\xc0\x13".to_vec();
+        assert!(Name::from_wire(&msg, 13) == Ok((name("www.example.com"), 6)));
+        assert!(Name::from_wire(&msg, 19).is_err());
+        assert!(Name::from_wire(&msg[..4], 0).is_err());
+        assert!(Name::from_wire(b"\x00", 0) == Ok((Name::root(), 1)));
+
+        // a chain of pointers that grows past 255 bytes is rejected
+        msg.clear();
+        let mut start = 0;
+        for i in 0..4 {
+            let prev = start;
+            start = msg.len();
+            msg.push(63);
+            msg.extend([b'a'; 63]);
+            match i {
+                0 => msg.push(0),
+                _ => msg.extend([0xc0, prev as u8]),
+            }
+        }
+        assert!(Name::from_wire(&msg, 0).is_ok());
+        assert!(Name::from_wire(&msg, start).is_err());
+    }
+
+    #[test]
+    fn test_name_equality() {
+        assert!(name("WWW.Example.COM") == name("www.example.com"));
+        assert!(name("www.example.com") != name("www.example.net"));
+        assert!(name("a.b") != name("a.b.c"));
+
+        let set: HashSet<Name> = ["example.com", "EXAMPLE.com.", "Example.Com"]
+            .iter()
+            .map(|t| name(t))
+            .collect();
+        assert!(set.len() == 1);
+    }
+
+    #[test]
+    fn test_name_canonical_order() {
+        // RFC 4034 6.1
+        let ordered = [
+            "example",
+            "a.example",
+            "yljkjljk.a.example",
+            "Z.a.example",
+            r"zABC.a.EXAMPLE",
+            "z.example",
+            r"\001.z.example",
+            r"*.z.example",
+            r"\200.z.example",
+        ];
+        let mut names: Vec<Name> = ordered.iter().rev().map(|t| name(t)).collect();
+        names.sort();
+        let sorted: Vec<Name> = ordered.iter().map(|t| name(t)).collect();
+        assert!(names == sorted);
+        assert!(Name::root() < name("example"));
+    }
+
+    #[test]
+    fn test_name_idn() {
+        let n = name("Bücher.DE");
+        assert!(n.to_string() == "xn--bcher-kva.de.");
+        assert!(n == name("xn--bcher-kva.de"));
+        assert!(n.is_idn() && !name("example.de").is_idn());
+        assert!(n.to_unicode() == "bücher.de.");
+        assert!(name("www.XN--BCHER-KVA.de").to_unicode() == "www.bücher.de.");
+        assert!(name("例え.テスト").to_string() == "xn--r8jz45g.xn--zckzah.");
+
+        // ASCII names are left alone, including labels IDNA would refuse
+        assert!(name("_sip._tcp.Example.com").to_string() == "_sip._tcp.Example.com.");
+        assert!(name("_sip._tcp.example.com").to_unicode() == "_sip._tcp.example.com.");
+
+        assert!("xn--bcher-kva-.de".parse::<Name>().is_err());
+        assert!("a\u{200d}b.de".parse::<Name>().is_err());
+        assert!("-bücher.de".parse::<Name>().is_err());
+        assert!(to_ascii(&format!("{}ü.de", "a".repeat(60))).is_err());
+    }
+
+    #[test]
+    fn test_name_hierarchy() {
+        let www = name("www.Example.com");
+        let zone = name("example.COM");
+
+        assert!(www.parent() == Some(zone.clone()));
+        assert!(Name::root().parent().is_none());
+        assert!(zone.child(b"www").unwrap() == www);
+        assert!(zone.child(b"").is_err());
+        assert!(name("*.example.com").is_wildcard() && !www.is_wildcard());
+
+        let ancestors: Vec<String> = www.ancestors().map(|n| n.to_string()).collect();
+        assert!(ancestors == ["www.Example.com.", "Example.com.", "com.", "."]);
+
+        assert!(www.is_subdomain_of(&zone));
+        assert!(zone.is_subdomain_of(&zone));
+        assert!(www.is_subdomain_of(&Name::root()));
+        assert!(!zone.is_subdomain_of(&www));
+        assert!(!name("wwwexample.com").is_subdomain_of(&zone));
+
+        assert!(www.common_ancestor(&name("mail.example.com")) == zone);
+        assert!(www.common_ancestor(&name("example.net")).is_root());
+
+        let deep = name("a.b.c.example.com");
+        assert!(deep.next_closer(&zone) == Some(name("c.example.com")));
+        assert!(zone.next_closer(&zone).is_none());
+        assert!(deep.next_closer(&name("example.net")).is_none());
+        assert!(deep.relative_to(&zone) == Some(&deep.labels()[..3]));
+    }
+}
@@ -0,0 +1,383 @@
+// Async query API built on tokio, enabled with the "async" feature
+//
+// A Client owns one UDP socket to a server; any number of queries can be in
+// flight on it at once and responses are matched back to their callers by
+// the header ID. Truncated responses are retried over TCP.
+use std::collections::HashMap;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{lookup_host, TcpStream, UdpSocket};
+use tokio::sync::oneshot;
+use tokio::task::JoinHandle;
+
+use crate::dns::{Query, RecordClass, RecordType, Response};
+
+const BUF_SIZE: usize = 4096;
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5);
+
+type Pending = Arc<Mutex<HashMap<u16, oneshot::Sender<Vec<u8>>>>>;
+
+pub struct Client {
+    server: SocketAddr,
+    socket: Arc<UdpSocket>,
+    pending: Pending,
+    timeout: Duration,
+    reader: JoinHandle<()>,
+}
+
+// Removes a query from the pending table when its future completes or is
+// dropped, so cancelled queries do not leak IDs
+struct PendingGuard<'a> {
+    pending: &'a Pending,
+    id: u16,
+}
+
+impl Drop for PendingGuard<'_> {
+    fn drop(&mut self) {
+        self.pending.lock().unwrap().remove(&self.id);
+    }
+}
+
+impl Client {
+    pub async fn connect(server: &str) -> io::Result<Self> {
+        let server = lookup_host(server).await?.next().ok_or_else(|| {
+            io::Error::new(io::ErrorKind::NotFound, "no address found for server")
+        })?;
+
+        let local = if server.is_ipv4() {
+            "0.0.0.0:0"
+        } else {
+            "[::]:0"
+        };
+        let socket = Arc::new(UdpSocket::bind(local).await?);
+        socket.connect(server).await?;
+
+        let pending: Pending = Arc::default();
+        let reader = tokio::spawn(Self::dispatch(socket.clone(), pending.clone()));
+
+        Ok(Self {
+            server,
+            socket,
+            pending,
+            timeout: DEFAULT_TIMEOUT,
+            reader,
+        })
+    }
+
+    pub fn server(&self) -> SocketAddr {
+        self.server
+    }
+
+    // default timeout used by query()
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    // number of queries currently waiting for a response
+    pub fn in_flight(&self) -> usize {
+        self.pending.lock().unwrap().len()
+    }
+
+    pub async fn query(
+        &self,
+        hostname: String,
+        rtype: RecordType,
+        rclass: RecordClass,
+    ) -> io::Result<Response> {
+        self.query_timeout(hostname, rtype, rclass, self.timeout)
+            .await
+    }
+
+    pub async fn query_timeout(
+        &self,
+        hostname: String,
+        rtype: RecordType,
+        rclass: RecordClass,
+        timeout: Duration,
+    ) -> io::Result<Response> {
+        let invalid = |e| io::Error::new(io::ErrorKind::InvalidInput, e);
+        let mut packet = Query::new(hostname, rtype, rclass)
+            .and_then(|mut q| q.query_serialize())
+            .map_err(invalid)?;
+
+        let (tx, rx) = oneshot::channel();
+        let id = self.register(tx)?;
+        let _guard = PendingGuard {
+            pending: &self.pending,
+            id,
+        };
+        packet[..2].copy_from_slice(&id.to_be_bytes());
+
+        let exchange = async {
+            self.socket.send(&packet).await?;
+            let bytes = rx.await.map_err(|_| {
+                io::Error::new(io::ErrorKind::BrokenPipe, "response reader stopped")
+            })?;
+
+            let response = parse(bytes)?;
+            if !response.header.flags.tc() {
+                return Ok(response);
+            }
+
+            // truncated; retry the same message over TCP
+            let response = parse(tcp_exchange(self.server, &packet).await?)?;
+            if response.header.id != id {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("unexpected message ID {}", response.header.id),
+                ));
+            }
+            Ok(response)
+        };
+
+        match tokio::time::timeout(timeout, exchange).await {
+            Ok(r) => r,
+            Err(_) => Err(io::Error::new(io::ErrorKind::TimedOut, "query timed out")),
+        }
+    }
+
+    // Pick an ID not already in flight and record the waiting sender. A few
+    // random picks are tried before searching the IDs in turn, which fails
+    // only when all of them are in use.
+    fn register(&self, tx: oneshot::Sender<Vec<u8>>) -> io::Result<u16> {
+        let mut pending = self.pending.lock().unwrap();
+        let start: u16 = rand::random();
+        let id = (0..16)
+            .map(|_| rand::random::<u16>())
+            .chain((0..=u16::MAX).map(|i| start.wrapping_add(i)))
+            .find(|id| !pending.contains_key(id))
+            .ok_or_else(|| io::Error::other("all query IDs are in flight"))?;
+        pending.insert(id, tx);
+        Ok(id)
+    }
+
+    async fn dispatch(socket: Arc<UdpSocket>, pending: Pending) {
+        let mut buf = [0; BUF_SIZE];
+        loop {
+            let size = match socket.recv(&mut buf).await {
+                Ok(size) => size,
+                // ICMP port unreachable surfaces here on connected sockets
+                Err(e) if e.kind() == io::ErrorKind::ConnectionRefused => continue,
+                Err(_) => break,
+            };
+
+            if size < 2 {
+                continue;
+            }
+
+            let id = u16::from_be_bytes([buf[0], buf[1]]);
+            if let Some(tx) = pending.lock().unwrap().remove(&id) {
+                let _ = tx.send(buf[..size].to_vec());
+            }
+        }
+
+        // wake everyone still waiting; their receivers see the sender dropped
+        pending.lock().unwrap().clear();
+    }
+}
+
+impl Drop for Client {
+    fn drop(&mut self) {
+        self.reader.abort();
+    }
+}
+
+fn parse(bytes: Vec<u8>) -> io::Result<Response> {
+    Response::from_bytes(bytes).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+async fn tcp_exchange(server: SocketAddr, packet: &[u8]) -> io::Result<Vec<u8>> {
+    let mut stream = TcpStream::connect(server).await?;
+
+    let mut msg = (packet.len() as u16).to_be_bytes().to_vec();
+    msg.extend(packet);
+    stream.write_all(&msg).await?;
+
+    let len = stream.read_u16().await?;
+    let mut buf = vec![0; len as usize];
+    stream.read_exact(&mut buf).await?;
+
+    Ok(buf)
+}
+
+// one-shot async equivalent of Query::do_query
+pub async fn query(
+    hostname: String,
+    server: String,
+    rtype: RecordType,
+    rclass: RecordClass,
+) -> io::Result<Response> {
+    let client = Client::connect(&server).await?;
+    client.query(hostname, rtype, rclass).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // turn a query into a single A record response pointing at the qname
+    fn answer_for(query: &[u8], addr: [u8; 4], truncated: bool) -> Vec<u8> {
+        let mut resp = query.to_vec();
+        resp[2] |= 0x80; // QR
+        if truncated {
+            resp[2] |= 0x02; // TC
+            return resp;
+        }
+        resp[7] = 1; // ANCOUNT
+        resp.extend([0xc0, 0x0c, 0, 1, 0, 1, 0, 0, 0, 60, 0, 4]);
+        resp.extend(addr);
+        resp
+    }
+
+    #[tokio::test]
+    async fn test_multiplexed_out_of_order() {
+        let server = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = server.local_addr().unwrap();
+
+        // collect two queries, then answer them in reverse order
+        tokio::spawn(async move {
+            let mut buf = [0; BUF_SIZE];
+            let mut queries = Vec::new();
+            for _ in 0..2 {
+                let (size, peer) = server.recv_from(&mut buf).await.unwrap();
+                queries.push((buf[..size].to_vec(), peer));
+            }
+            for (q, peer) in queries.iter().rev() {
+                // first label byte distinguishes the two names
+                let resp = answer_for(q, [10, 0, 0, q[13]], false);
+                server.send_to(&resp, peer).await.unwrap();
+            }
+        });
+
+        let client = Client::connect(&addr.to_string()).await.unwrap();
+        let (a, b) = tokio::join!(
+            client.query("a.test".to_string(), RecordType::A, RecordClass::IN),
+            client.query("b.test".to_string(), RecordType::A, RecordClass::IN),
+        );
+
+        let (a, b) = (a.unwrap(), b.unwrap());
+        assert!(a.question.as_ref().unwrap().get_name_str() == "a.test.");
+        assert!(a.answer[0].data == [10, 0, 0, b'a']);
+        assert!(b.question.as_ref().unwrap().get_name_str() == "b.test.");
+        assert!(b.answer[0].data == [10, 0, 0, b'b']);
+        assert!(client.in_flight() == 0);
+    }
+
+    #[tokio::test]
+    async fn test_timeout_releases_id() {
+        // bound but never answers
+        let server = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = server.local_addr().unwrap();
+
+        let client = Client::connect(&addr.to_string()).await.unwrap();
+        let err = client
+            .query_timeout(
+                "a.test".to_string(),
+                RecordType::A,
+                RecordClass::IN,
+                Duration::from_millis(50),
+            )
+            .await
+            .unwrap_err();
+
+        assert!(err.kind() == io::ErrorKind::TimedOut);
+        assert!(client.in_flight() == 0);
+    }
+
+    #[tokio::test]
+    async fn test_invalid_name() {
+        // bound but never answers; nothing should be sent anyway
+        let server = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = server.local_addr().unwrap();
+
+        let client = Client::connect(&addr.to_string()).await.unwrap();
+        let err = client
+            .query("a..test".to_string(), RecordType::A, RecordClass::IN)
+            .await
+            .unwrap_err();
+
+        assert!(err.kind() == io::ErrorKind::InvalidInput);
+        assert!(client.in_flight() == 0);
+    }
+
+    #[tokio::test]
+    async fn test_ids_exhausted() {
+        let server = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = server.local_addr().unwrap();
+
+        let client = Client::connect(&addr.to_string()).await.unwrap();
+        let mut receivers = Vec::new();
+        for _ in 0..=u16::MAX {
+            let (tx, rx) = oneshot::channel();
+            client.register(tx).unwrap();
+            receivers.push(rx);
+        }
+        assert!(client.in_flight() == 65536);
+
+        let err = client
+            .query("a.test".to_string(), RecordType::A, RecordClass::IN)
+            .await
+            .unwrap_err();
+        assert!(err.kind() == io::ErrorKind::Other);
+        assert!(client.in_flight() == 65536);
+    }
+
+    // a server answering truncated over UDP, then over TCP with the ID of
+    // the query plus id_offset
+    async fn truncating_server(id_offset: u16) -> SocketAddr {
+        let udp = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = udp.local_addr().unwrap();
+        let tcp = tokio::net::TcpListener::bind(addr).await.unwrap();
+
+        tokio::spawn(async move {
+            let mut buf = [0; BUF_SIZE];
+            let (size, peer) = udp.recv_from(&mut buf).await.unwrap();
+            let resp = answer_for(&buf[..size], [0; 4], true);
+            udp.send_to(&resp, peer).await.unwrap();
+
+            let (mut stream, _) = tcp.accept().await.unwrap();
+            let len = stream.read_u16().await.unwrap();
+            let mut q = vec![0; len as usize];
+            stream.read_exact(&mut q).await.unwrap();
+            let mut resp = answer_for(&q, [192, 0, 2, 1], false);
+            let id = u16::from_be_bytes([resp[0], resp[1]]).wrapping_add(id_offset);
+            resp[..2].copy_from_slice(&id.to_be_bytes());
+            stream.write_u16(resp.len() as u16).await.unwrap();
+            stream.write_all(&resp).await.unwrap();
+        });
+
+        addr
+    }
+
+    #[tokio::test]
+    async fn test_truncated_retries_tcp() {
+        let addr = truncating_server(0).await;
+        let resp = query(
+            "a.test".to_string(),
+            addr.to_string(),
+            RecordType::A,
+            RecordClass::IN,
+        )
+        .await
+        .unwrap();
+
+        assert!(!resp.header.flags.tc());
+        assert!(resp.answer[0].data == [192, 0, 2, 1]);
+
+        // a TCP response to some other query is refused
+        let addr = truncating_server(1).await;
+        let err = query(
+            "a.test".to_string(),
+            addr.to_string(),
+            RecordType::A,
+            RecordClass::IN,
+        )
+        .await
+        .unwrap_err();
+        assert!(err.kind() == io::ErrorKind::InvalidData);
+    }
+}
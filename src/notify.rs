@@ -0,0 +1,177 @@
+// Zone change notification - RFC 1996
+//
+// A NOTIFY tells a secondary that the zone may have changed so it can
+// check the primary's SOA now rather than wait for its refresh timer. The
+// message is a query with opcode 4 for the zone's SOA, optionally carrying
+// the new SOA in the answer section as a hint.
+use std::io;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use crate::dns::{exchange_udp, rcode_str, Answer, RecordClass, RecordType, Response};
+use crate::message::Message;
+use crate::tsig::{self, Key, Session};
+
+pub const OPCODE_NOTIFY: u8 = 4;
+const TIMEOUT: Duration = Duration::from_secs(2);
+const ATTEMPTS: usize = 3;
+
+// A NOTIFY for zone with id, with soa as the answer if given
+pub fn message(zone: &str, soa: Option<&Answer>, id: u16) -> Result<Vec<u8>, String> {
+    let mut msg = Message::new();
+    msg.set_id(id)
+        .set_opcode(OPCODE_NOTIFY)
+        .set_aa(true)
+        .add_question(zone, RecordType::SOA, RecordClass::IN)?;
+    msg.answer.extend(soa.cloned());
+    msg.to_bytes()
+}
+
+// Notify server that zone changed, retrying over UDP until it is
+// acknowledged. With a key the NOTIFY is signed and the response must be.
+pub fn notify(
+    zone: &str,
+    soa: Option<&Answer>,
+    server: SocketAddr,
+    key: Option<&Key>,
+) -> Result<Response, String> {
+    let mut packet = message(zone, soa, rand::random())?;
+    let mut session = key.map(Session::new);
+    if let Some(s) = session.as_mut() {
+        s.sign(&mut packet, tsig::now())?;
+    }
+
+    let mut attempt = 0;
+    let bytes = loop {
+        attempt += 1;
+        match exchange_udp(server, &packet, TIMEOUT) {
+            Ok(bytes) => break bytes,
+            Err(e) if attempt < ATTEMPTS && timed_out(&e) => continue,
+            Err(e) if timed_out(&e) => {
+                return Err(format!(
+                    "no answer from {} after {} attempts",
+                    server, attempt
+                ))
+            }
+            Err(e) => return Err(format!("notify to {} failed: {}", server, e)),
+        }
+    };
+
+    if let Some(s) = session.as_mut() {
+        if let Err(e) = s.verify(&bytes, tsig::now()) {
+            return Err(match bytes[3] & 0x0f {
+                0 => e,
+                rcode => format!("{}: {}", rcode_str(rcode), e),
+            });
+        }
+    }
+
+    let resp = Response::from_bytes(bytes)?;
+    if resp.header.flags.opcode() != OPCODE_NOTIFY {
+        return Err(format!(
+            "{} answered with opcode {}, not NOTIFY",
+            server,
+            resp.header.flags.opcode()
+        ));
+    }
+    Ok(resp)
+}
+
+fn timed_out(e: &io::Error) -> bool {
+    e.kind() == io::ErrorKind::WouldBlock || e.kind() == io::ErrorKind::TimedOut
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rdata::RData;
+    use crate::server::{Server, Zone};
+    use crate::zone::parse_zone;
+    use std::net::{TcpListener, UdpSocket};
+    use std::sync::{mpsc, Arc};
+    use std::thread;
+
+    const ZONE: &str = "$ORIGIN example.com.
+$TTL 3600
+@        IN SOA   ns1 hostmaster 7 3600 600 86400 300
+@        IN NS    ns1
+ns1      IN A     192.0.2.1
+";
+
+    fn zone() -> Zone {
+        Zone::new("example.com.", parse_zone(ZONE, "example.com.").unwrap()).unwrap()
+    }
+
+    fn soa(serial: u32) -> Answer {
+        let mut soa = zone().soa().clone();
+        if let RData::SOA { serial: s, .. } = &mut soa.rdata {
+            *s = serial;
+        }
+        soa
+    }
+
+    #[test]
+    fn test_notify_acknowledged() {
+        let (tx, rx) = mpsc::channel();
+        let mut server = Server::new(vec![zone()]);
+        server.on_notify(move |origin, serial| {
+            tx.send((origin.to_string(), serial)).unwrap();
+            true
+        });
+
+        let resp = server.handle(&message("Example.COM", Some(&soa(8)), 1).unwrap(), 512);
+        let resp = Response::from_bytes(resp.unwrap()).unwrap();
+        assert!(resp.header.flags.opcode() == OPCODE_NOTIFY);
+        assert!(resp.header.flags.aa() && resp.header.flags.rcode() == 0);
+        assert!(rx.try_recv().unwrap() == ("example.com.".to_string(), Some(8)));
+
+        // zones we do not serve and non-SOA questions are not acknowledged
+        let resp = server.handle(&message("example.net", None, 2).unwrap(), 512);
+        assert!(
+            Response::from_bytes(resp.unwrap())
+                .unwrap()
+                .header
+                .flags
+                .rcode()
+                == 9
+        );
+        let resp = server.handle(&message("ns1.example.com", None, 3).unwrap(), 512);
+        assert!(
+            Response::from_bytes(resp.unwrap())
+                .unwrap()
+                .header
+                .flags
+                .rcode()
+                == 9
+        );
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_notify_reloads_zone_file() {
+        let path = std::env::temp_dir().join(format!("rig-notify-{}.zone", std::process::id()));
+        std::fs::write(&path, ZONE).unwrap();
+        let path = path.to_str().unwrap().to_string();
+        let server = Server::new(vec![Zone::load("example.com.", &path).unwrap()]);
+
+        std::fs::write(&path, ZONE.replace(" 7 ", " 9 ")).unwrap();
+        server
+            .handle(&message("example.com", None, 1).unwrap(), 512)
+            .unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert!(server.serial("example.com.") == Some(9));
+    }
+
+    #[test]
+    fn test_notify_send() {
+        let udp = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let tcp = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = udp.local_addr().unwrap();
+        let server = Arc::new(Server::new(vec![zone()]));
+        thread::spawn(move || server.serve(udp, tcp));
+
+        let resp = notify("example.com", Some(&soa(7)), addr, None).unwrap();
+        assert!(resp.header.flags.rcode() == 0);
+        assert!(resp.question.unwrap().rtype == RecordType::SOA);
+    }
+}
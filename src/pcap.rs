@@ -0,0 +1,1001 @@
+// Offline DNS capture decoding
+//
+// Reads classic pcap and pcapng files, unwraps the link, IP and transport
+// layers of each frame and collects the DNS messages sent to or from port
+// 53. IP fragments are put back together, and TCP streams are reassembled
+// per direction before their two byte length prefixed messages are split
+// out (RFC 1035 4.2.2). Messages are decoded with MessageRef, and queries
+// are matched to their responses to give the latency.
+use std::collections::{BTreeMap, HashMap};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::Duration;
+
+use crate::dns::{rcode_str, RecordType};
+use crate::message::MessageRef;
+use crate::name::Name;
+use crate::stats::millis;
+
+const DNS_PORT: u16 = 53;
+
+// link types - https://www.tcpdump.org/linktypes.html
+const LINKTYPE_NULL: u32 = 0;
+const LINKTYPE_ETHERNET: u32 = 1;
+const LINKTYPE_RAW: u32 = 101;
+const LINKTYPE_LOOP: u32 = 108;
+const LINKTYPE_LINUX_SLL: u32 = 113;
+const LINKTYPE_IPV4: u32 = 228;
+const LINKTYPE_IPV6: u32 = 229;
+const LINKTYPE_LINUX_SLL2: u32 = 276;
+
+const ETHERTYPE_IPV4: u16 = 0x0800;
+const ETHERTYPE_IPV6: u16 = 0x86dd;
+const ETHERTYPE_VLAN: u16 = 0x8100;
+const ETHERTYPE_QINQ: u16 = 0x88a8;
+
+const PROTO_TCP: u8 = 6;
+const PROTO_UDP: u8 = 17;
+
+const TCP_FIN: u8 = 0x01;
+const TCP_SYN: u8 = 0x02;
+const TCP_RST: u8 = 0x04;
+
+// pcapng blocks - draft-ietf-opsawg-pcapng
+const BLOCK_SECTION: u32 = 0x0a0d0d0a;
+const BLOCK_INTERFACE: u32 = 1;
+const BLOCK_SIMPLE: u32 = 3;
+const BLOCK_ENHANCED: u32 = 6;
+const OPTION_TSRESOL: u16 = 9;
+
+// A captured frame, timestamped from the epoch
+#[derive(Debug, Clone)]
+pub struct Frame {
+    pub ts: Duration,
+    pub linktype: u32,
+    pub data: Vec<u8>,
+}
+
+// A DNS message found in a capture
+#[derive(Debug, Clone)]
+pub struct DnsPacket {
+    pub ts: Duration,
+    pub src: SocketAddr,
+    pub dst: SocketAddr,
+    pub tcp: bool,
+    pub data: Vec<u8>,
+}
+
+// Which messages report shows; a name matches itself and its subdomains
+#[derive(Debug, Default)]
+pub struct Filter {
+    pub name: Option<Name>,
+    pub rtype: Option<RecordType>,
+    pub rcode: Option<u8>,
+}
+
+#[derive(Clone, Copy)]
+struct Endian {
+    big: bool,
+}
+
+impl Endian {
+    fn u16(self, b: &[u8]) -> u16 {
+        let b = [b[0], b[1]];
+        if self.big {
+            u16::from_be_bytes(b)
+        } else {
+            u16::from_le_bytes(b)
+        }
+    }
+
+    fn u32(self, b: &[u8]) -> u32 {
+        let b = [b[0], b[1], b[2], b[3]];
+        if self.big {
+            u32::from_be_bytes(b)
+        } else {
+            u32::from_le_bytes(b)
+        }
+    }
+}
+
+fn be16(b: &[u8], at: usize) -> Option<u16> {
+    Some(u16::from_be_bytes([*b.get(at)?, *b.get(at + 1)?]))
+}
+
+fn be32(b: &[u8], at: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(b.get(at..at + 4)?.try_into().ok()?))
+}
+
+fn field(b: &[u8], at: usize, len: usize) -> Result<&[u8], String> {
+    b.get(at..at + len)
+        .ok_or(format!("capture truncated at offset {}", at))
+}
+
+// Read every frame of a pcap or pcapng capture
+pub fn read_frames(bytes: &[u8]) -> Result<Vec<Frame>, String> {
+    match field(bytes, 0, 4)? {
+        [0xa1, 0xb2, 0xc3, 0xd4] | [0xa1, 0xb2, 0x3c, 0x4d] => {
+            read_pcap(bytes, Endian { big: true })
+        }
+        [0xd4, 0xc3, 0xb2, 0xa1] | [0x4d, 0x3c, 0xb2, 0xa1] => {
+            read_pcap(bytes, Endian { big: false })
+        }
+        [0x0a, 0x0d, 0x0d, 0x0a] => read_pcapng(bytes),
+        _ => Err("not a pcap or pcapng capture".to_string()),
+    }
+}
+
+// classic pcap: a 24 byte file header, then a 16 byte header per record.
+// A capture cut off mid-record keeps the frames before it.
+fn read_pcap(bytes: &[u8], e: Endian) -> Result<Vec<Frame>, String> {
+    let header = field(bytes, 0, 24)?;
+    let nanos = e.u32(&header[0..4]) == 0xa1b23c4d;
+    // the upper bits of the link type describe the FCS, if any
+    let linktype = e.u32(&header[20..24]) & 0x0fff_ffff;
+
+    let mut frames = Vec::new();
+    let mut at = 24;
+    while let Some(record) = bytes.get(at..at + 16) {
+        let secs = Duration::from_secs(e.u32(&record[0..4]) as u64);
+        let frac = e.u32(&record[4..8]) as u64;
+        let len = e.u32(&record[8..12]) as usize;
+        let Some(data) = bytes.get(at + 16..at + 16 + len) else {
+            break;
+        };
+
+        frames.push(Frame {
+            ts: secs
+                + match nanos {
+                    true => Duration::from_nanos(frac),
+                    false => Duration::from_micros(frac),
+                },
+            linktype,
+            data: data.to_vec(),
+        });
+        at += 16 + len;
+    }
+
+    Ok(frames)
+}
+
+// pcapng: a sequence of blocks, each section starting with a header block
+// that gives its byte order and resetting the interface list
+fn read_pcapng(bytes: &[u8]) -> Result<Vec<Frame>, String> {
+    let mut e = Endian { big: false };
+    // link type and timestamp units per second of each interface
+    let mut interfaces: Vec<(u32, u64)> = Vec::new();
+    let mut frames = Vec::new();
+
+    let mut at = 0;
+    while at < bytes.len() {
+        let kind = field(bytes, at, 4)?;
+        if kind == BLOCK_SECTION.to_be_bytes() {
+            e = match field(bytes, at + 8, 4)? {
+                [0x1a, 0x2b, 0x3c, 0x4d] => Endian { big: true },
+                [0x4d, 0x3c, 0x2b, 0x1a] => Endian { big: false },
+                _ => return Err(format!("bad pcapng byte order magic at offset {}", at)),
+            };
+            interfaces.clear();
+        }
+        let kind = e.u32(kind);
+        let len = e.u32(field(bytes, at + 4, 4)?) as usize;
+        if len < 12 || !len.is_multiple_of(4) {
+            return Err(format!("bad pcapng block length {} at offset {}", len, at));
+        }
+        let Some(body) = bytes.get(at + 8..at + len - 4) else {
+            break;
+        };
+
+        match kind {
+            BLOCK_INTERFACE => {
+                let linktype = e.u16(field(body, 0, 2)?) as u32;
+                let mut units = 1_000_000;
+                if let Some(v) = pcapng_option(body, 8, e, OPTION_TSRESOL) {
+                    let v = *v.first().ok_or("empty if_tsresol option")?;
+                    units = match v & 0x80 {
+                        0 => 10u64.checked_pow(v as u32),
+                        _ => 1u64.checked_shl((v & 0x7f) as u32),
+                    }
+                    .ok_or(format!("unsupported timestamp resolution {:#04x}", v))?;
+                }
+                interfaces.push((linktype, units));
+            }
+            BLOCK_ENHANCED => {
+                let header = field(body, 0, 20)?;
+                let iface = e.u32(&header[0..4]) as usize;
+                let (linktype, units) = *interfaces
+                    .get(iface)
+                    .ok_or(format!("packet for unknown interface {}", iface))?;
+                let ts = (e.u32(&header[4..8]) as u64) << 32 | e.u32(&header[8..12]) as u64;
+                let len = e.u32(&header[12..16]) as usize;
+                frames.push(Frame {
+                    ts: timestamp(ts, units),
+                    linktype,
+                    data: field(body, 20, len)?.to_vec(),
+                });
+            }
+            BLOCK_SIMPLE => {
+                // simple packets carry no timestamp and belong to interface 0
+                let (linktype, _) = *interfaces.first().ok_or("packet before any interface")?;
+                let len = e.u32(field(body, 0, 4)?) as usize;
+                let data = &body[4..];
+                frames.push(Frame {
+                    ts: Duration::ZERO,
+                    linktype,
+                    data: data[..len.min(data.len())].to_vec(),
+                });
+            }
+            _ => {}
+        }
+        at += len;
+    }
+
+    Ok(frames)
+}
+
+// the value of option code in the options list starting at offset
+fn pcapng_option(body: &[u8], mut at: usize, e: Endian, code: u16) -> Option<&[u8]> {
+    while let Some(header) = body.get(at..at + 4) {
+        let (c, len) = (e.u16(&header[0..2]), e.u16(&header[2..4]) as usize);
+        if c == 0 {
+            break;
+        }
+        if c == code {
+            return body.get(at + 4..at + 4 + len);
+        }
+        at += 4 + len.div_ceil(4) * 4;
+    }
+    None
+}
+
+fn timestamp(ts: u64, units: u64) -> Duration {
+    let nanos = (ts % units) as u128 * 1_000_000_000 / units as u128;
+    Duration::from_secs(ts / units) + Duration::from_nanos(nanos as u64)
+}
+
+// the IP packet carried in a frame, if it carries one
+fn ip_packet(linktype: u32, data: &[u8]) -> Option<&[u8]> {
+    let (ethertype, at) = match linktype {
+        LINKTYPE_ETHERNET => {
+            let (mut ethertype, mut at) = (be16(data, 12)?, 14);
+            while ethertype == ETHERTYPE_VLAN || ethertype == ETHERTYPE_QINQ {
+                ethertype = be16(data, at + 2)?;
+                at += 4;
+            }
+            (Some(ethertype), at)
+        }
+        LINKTYPE_LINUX_SLL => (Some(be16(data, 14)?), 16),
+        LINKTYPE_LINUX_SLL2 => (Some(be16(data, 0)?), 20),
+        // the address family is in the capturing host's byte order, so
+        // the IP version nibble is relied on instead
+        LINKTYPE_NULL | LINKTYPE_LOOP => (None, 4),
+        LINKTYPE_RAW | LINKTYPE_IPV4 | LINKTYPE_IPV6 => (None, 0),
+        _ => return None,
+    };
+
+    match ethertype {
+        None | Some(ETHERTYPE_IPV4) | Some(ETHERTYPE_IPV6) => data.get(at..),
+        _ => None,
+    }
+}
+
+// An IP datagram, possibly rebuilt from fragments
+struct Datagram {
+    src: IpAddr,
+    dst: IpAddr,
+    proto: u8,
+    payload: Vec<u8>,
+}
+
+type FragmentKey = (IpAddr, IpAddr, u32, u8);
+// the parts by offset, and the total length once the last part is seen
+type FragmentParts = (BTreeMap<usize, Vec<u8>>, Option<usize>);
+
+// Fragments waiting for the rest of their datagram, by offset
+#[derive(Default)]
+struct Fragments {
+    pending: HashMap<FragmentKey, FragmentParts>,
+}
+
+impl Fragments {
+    // add a fragment, returning the datagram's payload once it is whole
+    fn add(&mut self, key: FragmentKey, offset: usize, more: bool, data: &[u8]) -> Option<Vec<u8>> {
+        let (parts, total) = self.pending.entry(key).or_default();
+        parts.insert(offset, data.to_vec());
+        if !more {
+            *total = Some(offset + data.len());
+        }
+
+        let total = (*total)?;
+        let mut payload = Vec::with_capacity(total);
+        for (&offset, part) in parts.iter() {
+            if offset > payload.len() {
+                return None;
+            }
+            let skip = payload.len() - offset;
+            payload.extend(part.iter().skip(skip));
+        }
+        if payload.len() < total {
+            return None;
+        }
+
+        self.pending.remove(&key);
+        payload.truncate(total);
+        Some(payload)
+    }
+}
+
+fn ipv4(ip: &[u8], fragments: &mut Fragments) -> Option<Datagram> {
+    let header_len = (ip[0] & 0x0f) as usize * 4;
+    let total = (be16(ip, 2)? as usize).min(ip.len());
+    let src = IpAddr::V4(Ipv4Addr::from(be32(ip, 12)?));
+    let dst = IpAddr::V4(Ipv4Addr::from(be32(ip, 16)?));
+    let proto = *ip.get(9)?;
+    let payload = ip.get(header_len..total)?;
+
+    let frag = be16(ip, 6)?;
+    let (offset, more) = ((frag & 0x1fff) as usize * 8, frag & 0x2000 != 0);
+    let payload = match offset == 0 && !more {
+        true => payload.to_vec(),
+        false => {
+            let key = (src, dst, be16(ip, 4)? as u32, proto);
+            fragments.add(key, offset, more, payload)?
+        }
+    };
+
+    Some(Datagram {
+        src,
+        dst,
+        proto,
+        payload,
+    })
+}
+
+fn ipv6(ip: &[u8], fragments: &mut Fragments) -> Option<Datagram> {
+    let end = (40 + be16(ip, 4)? as usize).min(ip.len());
+    let src = IpAddr::V6(Ipv6Addr::from(<[u8; 16]>::try_from(ip.get(8..24)?).ok()?));
+    let dst = IpAddr::V6(Ipv6Addr::from(<[u8; 16]>::try_from(ip.get(24..40)?).ok()?));
+
+    // walk the extension headers to the transport
+    let mut next = *ip.get(6)?;
+    let mut at = 40;
+    let mut fragment = None;
+    loop {
+        match next {
+            // hop-by-hop, routing and destination options
+            0 | 43 | 60 => {
+                next = *ip.get(at)?;
+                at += (*ip.get(at + 1)? as usize + 1) * 8;
+            }
+            44 => {
+                let frag = be16(ip, at + 2)?;
+                fragment = Some((be32(ip, at + 4)?, (frag & 0xfff8) as usize, frag & 1 != 0));
+                next = *ip.get(at)?;
+                at += 8;
+            }
+            _ => break,
+        }
+    }
+
+    let payload = ip.get(at..end)?;
+    let payload = match fragment {
+        Some((id, offset, more)) => fragments.add((src, dst, id, next), offset, more, payload)?,
+        None => payload.to_vec(),
+    };
+
+    Some(Datagram {
+        src,
+        dst,
+        proto: next,
+        payload,
+    })
+}
+
+// One direction of a TCP connection
+#[derive(Default)]
+struct Stream {
+    next: Option<u32>,
+    // segments that arrived ahead of the next expected sequence number
+    ahead: BTreeMap<u32, Vec<u8>>,
+    buf: Vec<u8>,
+}
+
+impl Stream {
+    fn add(&mut self, seq: u32, data: &[u8]) {
+        let next = *self.next.get_or_insert(seq);
+        let rel = seq.wrapping_sub(next) as i32;
+        if rel > 0 {
+            self.ahead.insert(seq, data.to_vec());
+            return;
+        }
+
+        // retransmitted bytes we already have are dropped
+        let skip = rel.unsigned_abs() as usize;
+        if skip >= data.len() {
+            return;
+        }
+        self.buf.extend(&data[skip..]);
+        let mut next = next.wrapping_add((data.len() - skip) as u32);
+
+        while let Some((&seq, _)) = self.ahead.first_key_value() {
+            let rel = seq.wrapping_sub(next) as i32;
+            if rel > 0 {
+                break;
+            }
+            let data = self.ahead.remove(&seq).unwrap();
+            let skip = rel.unsigned_abs() as usize;
+            if skip < data.len() {
+                self.buf.extend(&data[skip..]);
+                next = next.wrapping_add((data.len() - skip) as u32);
+            }
+        }
+        self.next = Some(next);
+    }
+
+    // the complete length prefixed messages read so far
+    fn messages(&mut self) -> Vec<Vec<u8>> {
+        let mut messages = Vec::new();
+        while let Some(len) = be16(&self.buf, 0) {
+            let len = len as usize;
+            if self.buf.len() < 2 + len {
+                break;
+            }
+            messages.push(self.buf[2..2 + len].to_vec());
+            self.buf.drain(..2 + len);
+        }
+        messages
+    }
+}
+
+// Find the DNS messages in frames, in capture order
+pub fn dns_packets(frames: &[Frame]) -> Vec<DnsPacket> {
+    let mut fragments = Fragments::default();
+    let mut streams: HashMap<(SocketAddr, SocketAddr), Stream> = HashMap::new();
+    let mut packets = Vec::new();
+
+    for frame in frames {
+        let Some(ip) = ip_packet(frame.linktype, &frame.data) else {
+            continue;
+        };
+        let datagram = match ip.first().map(|b| b >> 4) {
+            Some(4) => ipv4(ip, &mut fragments),
+            Some(6) => ipv6(ip, &mut fragments),
+            _ => None,
+        };
+        let Some(d) = datagram else {
+            continue;
+        };
+        let p = &d.payload;
+        let (Some(sport), Some(dport)) = (be16(p, 0), be16(p, 2)) else {
+            continue;
+        };
+        if sport != DNS_PORT && dport != DNS_PORT {
+            continue;
+        }
+        let src = SocketAddr::new(d.src, sport);
+        let dst = SocketAddr::new(d.dst, dport);
+
+        match d.proto {
+            PROTO_UDP => {
+                let end = (be16(p, 4).unwrap_or(0) as usize).clamp(8, p.len().max(8));
+                if let Some(data) = p.get(8..end) {
+                    packets.push(DnsPacket {
+                        ts: frame.ts,
+                        src,
+                        dst,
+                        tcp: false,
+                        data: data.to_vec(),
+                    });
+                }
+            }
+            PROTO_TCP => {
+                let (Some(seq), Some(&offset), Some(&flags)) = (be32(p, 4), p.get(12), p.get(13))
+                else {
+                    continue;
+                };
+                let stream = streams.entry((src, dst)).or_default();
+                if flags & TCP_SYN != 0 {
+                    *stream = Stream {
+                        next: Some(seq.wrapping_add(1)),
+                        ..Default::default()
+                    };
+                }
+                if let Some(data) = p.get((offset >> 4) as usize * 4..) {
+                    if !data.is_empty() {
+                        stream.add(seq, data);
+                    }
+                }
+                for data in stream.messages() {
+                    packets.push(DnsPacket {
+                        ts: frame.ts,
+                        src,
+                        dst,
+                        tcp: true,
+                        data,
+                    });
+                }
+                if flags & (TCP_FIN | TCP_RST) != 0 {
+                    streams.remove(&(src, dst));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    packets
+}
+
+// time of day in UTC with microseconds, as tcpdump shows it
+fn time_of_day(ts: Duration) -> String {
+    let secs = ts.as_secs() % 86400;
+    format!(
+        "{:02}:{:02}:{:02}.{:06}",
+        secs / 3600,
+        secs / 60 % 60,
+        secs % 60,
+        ts.subsec_micros()
+    )
+}
+
+// client, server, transport, id and lowercased question
+type MatchKey = (SocketAddr, SocketAddr, bool, u16, Name, RecordType);
+
+// Render the messages that pass filter, one line each with the answers of
+// responses beneath, followed by a summary. Responses show how long after
+// their query they were seen.
+pub fn report(packets: &[DnsPacket], filter: &Filter) -> String {
+    let mut out = Vec::new();
+    let mut waiting: HashMap<MatchKey, Duration> = HashMap::new();
+    let (mut queries, mut responses, mut malformed) = (0, 0, 0);
+    let mut latencies = Vec::new();
+
+    for p in packets {
+        let transport = if p.tcp { "TCP" } else { "UDP" };
+        let prefix = format!("{} {} > {} {}", time_of_day(p.ts), p.src, p.dst, transport);
+
+        let msg = match MessageRef::parse(&p.data) {
+            Ok(m) => m,
+            Err(e) => {
+                if filter.name.is_none() && filter.rtype.is_none() && filter.rcode.is_none() {
+                    malformed += 1;
+                    out.push(format!("{} malformed: {}", prefix, e));
+                }
+                continue;
+            }
+        };
+
+        let flags = msg.flags();
+        let question = msg.questions().next();
+        let qname = question
+            .as_ref()
+            .and_then(|q| Name::from_labels(q.name.labels().map(|l| l.to_vec()).collect()).ok());
+        let qtype = question.as_ref().map(|q| q.rtype);
+
+        if let Some(n) = &filter.name {
+            if !qname.as_ref().is_some_and(|q| q.is_subdomain_of(n)) {
+                continue;
+            }
+        }
+        if filter.rtype.is_some() && filter.rtype != qtype {
+            continue;
+        }
+        let kind = match flags.opcode() {
+            0 => "",
+            4 => "notify ",
+            5 => "update ",
+            _ => "opcode ",
+        };
+        let question_str = match &question {
+            Some(q) => format!(" {} {}", q.name, q.rtype),
+            None => String::new(),
+        };
+        let key = qname.zip(qtype).map(|(n, t)| (n.to_lowercase(), t));
+
+        // queries are tracked even when an rcode filter hides them so the
+        // responses shown still get their latency
+        if !flags.qr() {
+            if let Some((n, t)) = key {
+                waiting.insert((p.src, p.dst, p.tcp, msg.id(), n, t), p.ts);
+            }
+            if filter.rcode.is_some() {
+                continue;
+            }
+            queries += 1;
+            out.push(format!(
+                "{} {}query #{}{}",
+                prefix,
+                kind,
+                msg.id(),
+                question_str
+            ));
+            continue;
+        }
+
+        let sent = key.and_then(|(n, t)| waiting.remove(&(p.dst, p.src, p.tcp, msg.id(), n, t)));
+        if filter.rcode.is_some_and(|r| r != flags.rcode()) {
+            continue;
+        }
+        responses += 1;
+        let latency = match sent {
+            Some(sent) => {
+                let d = p.ts.saturating_sub(sent);
+                latencies.push(d);
+                format!(" {}", millis(d))
+            }
+            None => String::new(),
+        };
+        let h = msg.header();
+        out.push(format!(
+            "{} {}response #{} {}{} {}/{}/{}{}",
+            prefix,
+            kind,
+            msg.id(),
+            rcode_str(flags.rcode()),
+            question_str,
+            h.an_count,
+            h.ns_count,
+            h.ar_count,
+            latency
+        ));
+        for r in msg.answer() {
+            match r.to_answer() {
+                Ok(a) => out.push(format!("    {}", a)),
+                Err(e) => out.push(format!("    {} {}: {}", r.name, r.rtype, e)),
+            }
+        }
+    }
+
+    let mut summary = format!(
+        "; {} queries, {} responses, {} unanswered",
+        queries,
+        responses,
+        if filter.rcode.is_some() {
+            0
+        } else {
+            waiting.len()
+        }
+    );
+    if malformed > 0 {
+        summary.push_str(&format!(", {} malformed", malformed));
+    }
+    if !latencies.is_empty() {
+        let total: Duration = latencies.iter().sum();
+        summary.push_str(&format!(
+            "; latency avg {} max {}",
+            millis(total / latencies.len() as u32),
+            millis(*latencies.iter().max().unwrap())
+        ));
+    }
+    out.push(summary);
+
+    out.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dns::{Answer, RecordClass};
+    use crate::message::Message;
+    use crate::rdata::RData;
+
+    const CLIENT: [u8; 4] = [192, 0, 2, 10];
+    const SERVER: [u8; 4] = [192, 0, 2, 53];
+
+    fn query(id: u16, name: &str) -> Vec<u8> {
+        let mut msg = Message::query(name, RecordType::A, RecordClass::IN).unwrap();
+        msg.set_id(id);
+        msg.to_bytes().unwrap()
+    }
+
+    fn response(id: u16, name: &str, rcode: u8) -> Vec<u8> {
+        let mut msg = Message::query(name, RecordType::A, RecordClass::IN).unwrap();
+        msg.set_id(id).set_qr(true).set_rcode(rcode);
+        if rcode == 0 {
+            msg.add_answer(Answer::new(
+                format!("{}.", name),
+                RecordType::A,
+                RecordClass::IN,
+                300,
+                RData::A([192, 0, 2, 1].into()),
+            ));
+        }
+        msg.to_bytes().unwrap()
+    }
+
+    fn ipv4(src: [u8; 4], dst: [u8; 4], proto: u8, id: u16, frag: u16, payload: &[u8]) -> Vec<u8> {
+        let mut ip = vec![0x45, 0];
+        ip.extend(((20 + payload.len()) as u16).to_be_bytes());
+        ip.extend(id.to_be_bytes());
+        ip.extend(frag.to_be_bytes());
+        ip.extend([64, proto, 0, 0]);
+        ip.extend(src);
+        ip.extend(dst);
+        ip.extend(payload);
+        ip
+    }
+
+    fn udp(sport: u16, dport: u16, data: &[u8]) -> Vec<u8> {
+        let mut udp = Vec::new();
+        udp.extend(sport.to_be_bytes());
+        udp.extend(dport.to_be_bytes());
+        udp.extend(((8 + data.len()) as u16).to_be_bytes());
+        udp.extend([0, 0]);
+        udp.extend(data);
+        udp
+    }
+
+    fn tcp(sport: u16, dport: u16, seq: u32, flags: u8, data: &[u8]) -> Vec<u8> {
+        let mut tcp = Vec::new();
+        tcp.extend(sport.to_be_bytes());
+        tcp.extend(dport.to_be_bytes());
+        tcp.extend(seq.to_be_bytes());
+        tcp.extend([0, 0, 0, 0, 0x50, flags, 0xff, 0xff, 0, 0, 0, 0]);
+        tcp.extend(data);
+        tcp
+    }
+
+    // an Ethernet frame, with a VLAN tag if vlan is set
+    fn ethernet(ip: &[u8], vlan: bool) -> Vec<u8> {
+        let mut frame = vec![0x02; 12];
+        if vlan {
+            frame.extend([0x81, 0x00, 0x00, 0x64]);
+        }
+        let ethertype = if ip[0] >> 4 == 6 {
+            ETHERTYPE_IPV6
+        } else {
+            ETHERTYPE_IPV4
+        };
+        frame.extend(ethertype.to_be_bytes());
+        frame.extend(ip);
+        frame
+    }
+
+    fn udp_frame(src: [u8; 4], sport: u16, dst: [u8; 4], dport: u16, data: &[u8]) -> Vec<u8> {
+        ethernet(
+            &ipv4(src, dst, PROTO_UDP, 1, 0, &udp(sport, dport, data)),
+            false,
+        )
+    }
+
+    // a little endian, microsecond pcap of Ethernet frames at ts µs
+    fn pcap(frames: &[(u64, Vec<u8>)]) -> Vec<u8> {
+        let mut file = vec![0xd4, 0xc3, 0xb2, 0xa1, 2, 0, 4, 0];
+        file.extend([0; 8]);
+        file.extend(65535u32.to_le_bytes());
+        file.extend(LINKTYPE_ETHERNET.to_le_bytes());
+        for (ts, data) in frames {
+            file.extend(((ts / 1_000_000) as u32).to_le_bytes());
+            file.extend(((ts % 1_000_000) as u32).to_le_bytes());
+            file.extend((data.len() as u32).to_le_bytes());
+            file.extend((data.len() as u32).to_le_bytes());
+            file.extend(data);
+        }
+        file
+    }
+
+    fn block(kind: u32, body: &[u8]) -> Vec<u8> {
+        let mut body = body.to_vec();
+        body.resize(body.len().div_ceil(4) * 4, 0);
+        let len = (body.len() + 12) as u32;
+        let mut block = kind.to_be_bytes().to_vec();
+        block.extend(len.to_be_bytes());
+        block.extend(body);
+        block.extend(len.to_be_bytes());
+        block
+    }
+
+    #[test]
+    fn test_read_pcap() {
+        let q = query(0x1234, "www.example.com");
+        let file = pcap(&[
+            (1_000_000, udp_frame(CLIENT, 40000, SERVER, 53, &q)),
+            (1_000_100, udp_frame(CLIENT, 40000, SERVER, 443, b"not dns")),
+        ]);
+
+        let frames = read_frames(&file).unwrap();
+        assert!(frames.len() == 2);
+        assert!(frames[0].ts == Duration::from_micros(1_000_000));
+
+        let packets = dns_packets(&frames);
+        assert!(packets.len() == 1);
+        assert!(packets[0].src == "192.0.2.10:40000".parse().unwrap());
+        assert!(packets[0].data == q);
+
+        // a record cut off at the end of the file is dropped
+        assert!(read_frames(&file[..file.len() - 3]).unwrap().len() == 1);
+        assert!(read_frames(b"\x00\x00\x00\x00").is_err());
+    }
+
+    #[test]
+    fn test_read_pcapng() {
+        // big endian section, nanosecond resolution, IPv6 over a VLAN
+        let mut shb = vec![0x1a, 0x2b, 0x3c, 0x4d, 0, 1, 0, 0];
+        shb.extend([0xff; 8]);
+        let mut idb = vec![0, 1, 0, 0, 0, 0, 0xff, 0xff];
+        idb.extend([0, 9, 0, 1, 9, 0, 0, 0, 0, 0, 0, 0]);
+
+        let q = query(7, "example.com");
+        let mut ip = vec![0x60, 0, 0, 0];
+        let segment = udp(5353, 53, &q);
+        ip.extend((segment.len() as u16).to_be_bytes());
+        ip.extend([PROTO_UDP, 64]);
+        ip.extend("2001:db8::1".parse::<Ipv6Addr>().unwrap().octets());
+        ip.extend("2001:db8::53".parse::<Ipv6Addr>().unwrap().octets());
+        ip.extend(segment);
+        let frame = ethernet(&ip, true);
+
+        let ts: u64 = 1_500_000_000_250;
+        let mut epb = 0u32.to_be_bytes().to_vec();
+        epb.extend(((ts >> 32) as u32).to_be_bytes());
+        epb.extend((ts as u32).to_be_bytes());
+        epb.extend((frame.len() as u32).to_be_bytes());
+        epb.extend((frame.len() as u32).to_be_bytes());
+        epb.extend(&frame);
+
+        let mut file = block(BLOCK_SECTION, &shb);
+        file.extend(block(BLOCK_INTERFACE, &idb));
+        file.extend(block(BLOCK_ENHANCED, &epb));
+        file.extend(block(0x0bad, b"skipped"));
+
+        let frames = read_frames(&file).unwrap();
+        assert!(frames.len() == 1);
+        assert!(frames[0].ts == Duration::new(1500, 250));
+
+        let packets = dns_packets(&frames);
+        assert!(packets.len() == 1);
+        assert!(packets[0].dst == "[2001:db8::53]:53".parse().unwrap());
+        assert!(packets[0].data == q);
+
+        // packets must name an interface that has been described
+        let mut file = block(BLOCK_SECTION, &shb);
+        file.extend(block(BLOCK_ENHANCED, &epb));
+        assert!(read_frames(&file).is_err());
+    }
+
+    #[test]
+    fn test_tcp_reassembly() {
+        let mut stream = Vec::new();
+        for (id, name) in [(1, "a.example.com"), (2, "b.example.com")] {
+            let q = query(id, name);
+            stream.extend((q.len() as u16).to_be_bytes());
+            stream.extend(q);
+        }
+
+        // SYN, then the stream in three segments: the last arrives before
+        // the middle, and the first is retransmitted
+        let isn = u32::MAX - 5;
+        let data = isn.wrapping_add(1);
+        let (a, b) = (10, 40);
+        let segments = [
+            tcp(40000, 53, isn, TCP_SYN, &[]),
+            tcp(40000, 53, data, 0, &stream[..a]),
+            tcp(40000, 53, data.wrapping_add(b as u32), 0, &stream[b..]),
+            tcp(40000, 53, data, 0, &stream[..a]),
+            tcp(40000, 53, data.wrapping_add(a as u32), 0, &stream[a..b]),
+        ];
+        let frames: Vec<Frame> = segments
+            .iter()
+            .enumerate()
+            .map(|(i, s)| Frame {
+                ts: Duration::from_millis(i as u64),
+                linktype: LINKTYPE_RAW,
+                data: ipv4(CLIENT, SERVER, PROTO_TCP, i as u16, 0, s),
+            })
+            .collect();
+
+        let packets = dns_packets(&frames);
+        assert!(packets.len() == 2);
+        assert!(packets
+            .iter()
+            .all(|p| p.tcp && p.ts == Duration::from_millis(4)));
+        assert!(packets[0].data == query(1, "a.example.com"));
+        assert!(packets[1].data == query(2, "b.example.com"));
+    }
+
+    #[test]
+    fn test_ip_fragments() {
+        let r = response(9, "example.com", 0);
+        let segment = udp(53, 40000, &r);
+        let (first, rest) = segment.split_at(16);
+        // the second fragment arrives first
+        let frames = [
+            ipv4(SERVER, CLIENT, PROTO_UDP, 77, 2, rest),
+            ipv4(SERVER, CLIENT, PROTO_UDP, 77, 0x2000, first),
+        ]
+        .into_iter()
+        .map(|ip| Frame {
+            ts: Duration::ZERO,
+            linktype: LINKTYPE_RAW,
+            data: ip,
+        })
+        .collect::<Vec<Frame>>();
+
+        let packets = dns_packets(&frames);
+        assert!(packets.len() == 1);
+        assert!(packets[0].data == r);
+    }
+
+    #[test]
+    fn test_report() {
+        let frames = [
+            (
+                0,
+                udp_frame(CLIENT, 40000, SERVER, 53, &query(1, "www.example.com")),
+            ),
+            (
+                100,
+                udp_frame(CLIENT, 40001, SERVER, 53, &query(2, "nope.example.com")),
+            ),
+            (
+                1_500,
+                udp_frame(
+                    SERVER,
+                    53,
+                    CLIENT,
+                    40000,
+                    &response(1, "www.example.com", 0),
+                ),
+            ),
+            (
+                2_000,
+                udp_frame(
+                    SERVER,
+                    53,
+                    CLIENT,
+                    40001,
+                    &response(2, "nope.example.com", 3),
+                ),
+            ),
+            (
+                3_000,
+                udp_frame(CLIENT, 40002, SERVER, 53, &query(3, "example.net")),
+            ),
+            (4_000, udp_frame(CLIENT, 40003, SERVER, 53, b"\x00\x01")),
+        ];
+        let file = pcap(&frames);
+        let packets = dns_packets(&read_frames(&file).unwrap());
+
+        let out = report(&packets, &Filter::default());
+        let lines: Vec<&str> = out.lines().collect();
+        assert!(
+            lines[0] == "00:00:00.000000 192.0.2.10:40000 > 192.0.2.53:53 UDP query #1 www.example.com. A"
+        );
+        assert!(
+            lines[2]
+                == "00:00:00.001500 192.0.2.53:53 > 192.0.2.10:40000 UDP response #1 NOERROR www.example.com. A 1/0/0 1.500ms"
+        );
+        assert!(lines[3] == "    www.example.com.\t300\tIN\tA\t192.0.2.1");
+        assert!(lines[4].ends_with("response #2 NXDOMAIN nope.example.com. A 0/0/0 1.900ms"));
+        assert!(lines[6].ends_with("malformed: message too short; only 2 bytes"));
+        assert!(
+            *lines.last().unwrap()
+                == "; 3 queries, 2 responses, 1 unanswered, 1 malformed; latency avg 1.700ms max 1.900ms"
+        );
+
+        let filter = Filter {
+            rcode: Some(3),
+            ..Default::default()
+        };
+        let out = report(&packets, &filter);
+        assert!(
+            out.lines().count() == 2 && out.contains("NXDOMAIN nope.example.com. A 0/0/0 1.900ms")
+        );
+        assert!(out
+            .ends_with("; 0 queries, 1 responses, 0 unanswered; latency avg 1.900ms max 1.900ms"));
+
+        let filter = Filter {
+            name: Some("EXAMPLE.com".parse().unwrap()),
+            rtype: Some(RecordType::A),
+            ..Default::default()
+        };
+        let out = report(&packets, &filter);
+        assert!(!out.contains("example.net") && !out.contains("malformed"));
+        assert!(out
+            .ends_with("; 2 queries, 2 responses, 0 unanswered; latency avg 1.700ms max 1.900ms"));
+
+        let filter = Filter {
+            rtype: Some(RecordType::MX),
+            ..Default::default()
+        };
+        assert!(report(&packets, &filter) == "; 0 queries, 0 responses, 0 unanswered");
+    }
+}
@@ -0,0 +1,560 @@
+// Caching forwarder
+//
+// Client queries are answered from a TTL cache when possible and otherwise
+// forwarded upstream under a fresh ID, over UDP with a TCP retry when the
+// upstream truncates. Each client query is handled on its own thread.
+// Queries and upstream responses are read in place with MessageRef; only
+// responses that go into the cache are copied.
+use std::collections::HashMap;
+use std::io;
+use std::net::{SocketAddr, TcpListener, TcpStream, UdpSocket};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::dns::{
+    exchange_tcp, exchange_udp, rcode_str, read_tcp_message, write_tcp_message, Answer, Question,
+    RecordType,
+};
+use crate::message::{Message, MessageRef};
+use crate::rdata::RData;
+
+const UDP_SIZE: usize = 512;
+const BUF_SIZE: usize = 65535;
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5);
+const TCP_IDLE: Duration = Duration::from_secs(10);
+const MAX_ENTRIES: usize = 10000;
+
+const SERVFAIL: u8 = 2;
+const NXDOMAIN: u8 = 3;
+
+// lowercased name, type and class of a question
+type CacheKey = (String, u16, u16);
+
+struct Entry {
+    response: Message,
+    stored: Instant,
+    ttl: u32,
+}
+
+// Responses keyed by question, each kept for the smallest TTL it carries
+#[derive(Default)]
+pub struct Cache {
+    entries: HashMap<CacheKey, Entry>,
+}
+
+fn cache_key(msg: &MessageRef) -> Option<CacheKey> {
+    let q = msg.questions().next()?;
+    Some((
+        q.name.to_string().to_ascii_lowercase(),
+        q.rtype.to_u16(),
+        q.rclass.to_u16(),
+    ))
+}
+
+// How long a response may be cached: the lowest TTL of its records, or
+// for negative answers the SOA's negative TTL - RFC 2308 5. None for
+// responses that must not be cached.
+fn cache_ttl(resp: &MessageRef) -> Option<u32> {
+    let rcode = resp.flags().rcode();
+    if resp.flags().tc() || (rcode != 0 && rcode != NXDOMAIN) {
+        return None;
+    }
+
+    if resp.answer().next().is_none() {
+        return resp.authority().find_map(|r| match r.rdata() {
+            Ok(RData::SOA { minimum, .. }) => Some(r.ttl.min(minimum)),
+            _ => None,
+        });
+    }
+
+    resp.answer()
+        .chain(resp.authority())
+        .chain(resp.additional())
+        .filter(|r| r.rtype != RecordType::OPT)
+        .map(|r| r.ttl)
+        .min()
+}
+
+impl Cache {
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    // store resp as the answer to query if it is cacheable, dropping
+    // expired entries to make room
+    pub fn insert(&mut self, query: &MessageRef, resp: &MessageRef, now: Instant) {
+        let (key, ttl) = match (cache_key(query), cache_ttl(resp)) {
+            (Some(k), Some(t)) if t > 0 => (k, t),
+            _ => return,
+        };
+        let response = match resp.to_message() {
+            Ok(m) => m,
+            Err(_) => return,
+        };
+
+        if self.entries.len() >= MAX_ENTRIES {
+            self.entries.retain(|_, e| age(e, now) < e.ttl);
+            if self.entries.len() >= MAX_ENTRIES {
+                return;
+            }
+        }
+
+        self.entries.insert(
+            key,
+            Entry {
+                response,
+                stored: now,
+                ttl,
+            },
+        );
+    }
+
+    // A cached answer to query with its TTLs counted down to now
+    pub fn get(&mut self, query: &MessageRef, now: Instant) -> Option<Message> {
+        let key = cache_key(query)?;
+        let entry = self.entries.get(&key)?;
+
+        let age = age(entry, now);
+        if age >= entry.ttl {
+            self.entries.remove(&key);
+            return None;
+        }
+
+        let mut resp = entry.response.clone();
+        let count_down = |r: &mut Answer| {
+            if r.rtype != RecordType::OPT {
+                r.ttl = r.ttl.saturating_sub(age);
+            }
+        };
+        resp.answer.iter_mut().for_each(count_down);
+        resp.authority.iter_mut().for_each(count_down);
+        resp.additional.iter_mut().for_each(count_down);
+
+        resp.header.id = query.id();
+        resp.questions = query
+            .questions()
+            .map(|q| Ok(Question::for_name(&q.name.to_name()?, q.rtype, q.rclass)))
+            .collect::<Result<_, String>>()
+            .ok()?;
+        Some(resp)
+    }
+}
+
+fn age(entry: &Entry, now: Instant) -> u32 {
+    now.saturating_duration_since(entry.stored).as_secs() as u32
+}
+
+// What happened to one client query, for logging
+pub struct Outcome {
+    pub response: Vec<u8>,
+    pub question: String,
+    pub rcode: u8,
+    pub cached: bool,
+    pub elapsed: Duration,
+}
+
+impl Outcome {
+    pub fn summary(&self) -> String {
+        let source = if self.cached {
+            "cached".to_string()
+        } else {
+            format!("{}ms", self.elapsed.as_millis())
+        };
+        format!("{} {} {}", self.question, rcode_str(self.rcode), source)
+    }
+}
+
+pub struct Proxy {
+    upstream: SocketAddr,
+    timeout: Duration,
+    cache: Mutex<Cache>,
+}
+
+impl Proxy {
+    pub fn new(upstream: SocketAddr) -> Self {
+        Self {
+            upstream,
+            timeout: DEFAULT_TIMEOUT,
+            cache: Mutex::default(),
+        }
+    }
+
+    pub fn upstream(&self) -> SocketAddr {
+        self.upstream
+    }
+
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    pub fn cache_len(&self) -> usize {
+        self.cache.lock().unwrap().len()
+    }
+
+    // Answer one client query, limiting the response to max_len bytes.
+    // None for messages that cannot be answered, such as responses.
+    pub fn handle(&self, msg: &[u8], max_len: usize) -> Option<Outcome> {
+        let start = Instant::now();
+        let query = MessageRef::parse(msg).ok()?;
+        if query.flags().qr() {
+            return None;
+        }
+        let question = match query.questions().next() {
+            Some(q) => format!("{} {} {}", q.name, q.rclass, q.rtype),
+            None => "<no question>".to_string(),
+        };
+
+        // a UDP client may advertise a larger buffer with EDNS
+        let max_len = query
+            .additional()
+            .find(|r| r.rtype == RecordType::OPT)
+            .map_or(max_len, |opt| max_len.max(opt.rclass.to_u16() as usize));
+
+        // an entry that cannot be written out again is treated as a miss
+        let cached = self
+            .cache
+            .lock()
+            .unwrap()
+            .get(&query, Instant::now())
+            .and_then(|resp| Some((resp.to_bytes().ok()?, resp.header.flags.rcode())));
+        let (response, rcode, cached) = match cached {
+            Some((bytes, rcode)) => (bytes, rcode, true),
+            None => match self.forward(msg) {
+                Ok(bytes) => {
+                    let rcode = bytes.get(3).map_or(0, |b| b & 0x0f);
+                    if let Ok(resp) = MessageRef::parse(&bytes) {
+                        let mut cache = self.cache.lock().unwrap();
+                        cache.insert(&query, &resp, Instant::now());
+                    }
+                    (bytes, rcode, false)
+                }
+                Err(e) => {
+                    eprintln!("; {}: {}", question, e);
+                    (empty_reply(&query, SERVFAIL, false), SERVFAIL, false)
+                }
+            },
+        };
+
+        let response = if response.len() > max_len {
+            empty_reply(&query, rcode, true)
+        } else {
+            response
+        };
+
+        Some(Outcome {
+            response,
+            question,
+            rcode,
+            cached,
+            elapsed: start.elapsed(),
+        })
+    }
+
+    // Send the query upstream under a new ID, returning the response with
+    // the client's ID restored. Responses to another question are refused
+    // so that they cannot end up in the cache.
+    fn forward(&self, msg: &[u8]) -> Result<Vec<u8>, String> {
+        let client_id = [msg[0], msg[1]];
+        let id: u16 = rand::random();
+        let mut packet = msg.to_vec();
+        packet[..2].copy_from_slice(&id.to_be_bytes());
+
+        let mut resp = exchange_udp(self.upstream, &packet, self.timeout)
+            .map_err(|e| format!("upstream {} over UDP: {}", self.upstream, e))?;
+        if resp[2] & 0x02 != 0 {
+            resp = exchange_tcp(self.upstream, &packet, self.timeout)
+                .map_err(|e| format!("upstream {} over TCP: {}", self.upstream, e))?;
+        }
+
+        if !same_question(msg, &resp) {
+            return Err(format!(
+                "upstream {} answered another question",
+                self.upstream
+            ));
+        }
+        resp[..2].copy_from_slice(&client_id);
+        Ok(resp)
+    }
+
+    // Serve clients on already bound sockets, logging each query to stdout
+    pub fn serve(self: Arc<Self>, udp: UdpSocket, tcp: TcpListener) -> io::Result<()> {
+        let proxy = self.clone();
+        thread::spawn(move || {
+            for stream in tcp.incoming().flatten() {
+                let proxy = proxy.clone();
+                thread::spawn(move || proxy.serve_tcp(stream));
+            }
+        });
+
+        let mut buf = [0; BUF_SIZE];
+        loop {
+            let (size, peer) = udp.recv_from(&mut buf)?;
+            let msg = buf[..size].to_vec();
+            let socket = udp.try_clone()?;
+            let proxy = self.clone();
+            thread::spawn(move || {
+                if let Some(outcome) = proxy.handle(&msg, UDP_SIZE) {
+                    println!("{} {}", peer, outcome.summary());
+                    let _ = socket.send_to(&outcome.response, peer);
+                }
+            });
+        }
+    }
+
+    fn serve_tcp(&self, mut stream: TcpStream) {
+        let _ = stream.set_read_timeout(Some(TCP_IDLE));
+        let peer = match stream.peer_addr() {
+            Ok(p) => p,
+            Err(_) => return,
+        };
+        while let Ok(Some(msg)) = read_tcp_message(&mut stream) {
+            if let Some(outcome) = self.handle(&msg, u16::MAX as usize) {
+                println!("{} {} (tcp)", peer, outcome.summary());
+                if write_tcp_message(&mut stream, &outcome.response).is_err() {
+                    break;
+                }
+            }
+        }
+    }
+
+    // bind UDP and TCP on addr (<IP:port>) and serve until an error
+    pub fn listen(self, addr: &str) -> io::Result<()> {
+        let udp = UdpSocket::bind(addr)?;
+        let tcp = TcpListener::bind(udp.local_addr()?)?;
+        Arc::new(self).serve(udp, tcp)
+    }
+}
+
+// true if resp asks the same questions as query, ignoring name case
+fn same_question(query: &[u8], resp: &[u8]) -> bool {
+    let (query, resp) = match (MessageRef::parse(query), MessageRef::parse(resp)) {
+        (Ok(q), Ok(r)) => (q, r),
+        _ => return false,
+    };
+    query.questions().count() == resp.questions().count()
+        && query.questions().zip(resp.questions()).all(|(q, r)| {
+            q.rtype == r.rtype && q.rclass == r.rclass && q.name.eq_ignore_ascii_case(&r.name)
+        })
+}
+
+// An empty response carrying only the query's question; rcode reports a
+// failure and truncated tells the client to retry over TCP. Built from the
+// query as received, since it may hold records the full parser refuses.
+fn empty_reply(query: &MessageRef, rcode: u8, truncated: bool) -> Vec<u8> {
+    let mut header = query.header();
+    let rd = header.flags.rd();
+    header.flags.0 = [0];
+    header.flags.set_qr(true);
+    header.flags.set_opcode(query.flags().opcode());
+    header.flags.set_rd(rd);
+    header.flags.set_tc(truncated);
+    header.flags.set_rcode(rcode);
+    (header.an_count, header.ns_count, header.ar_count) = (0, 0, 0);
+
+    let mut resp = header.to_bytes();
+    for q in query.questions() {
+        for label in q.name.labels() {
+            resp.push(label.len() as u8);
+            resp.extend(label);
+        }
+        resp.push(0);
+        resp.extend(q.rtype.to_u16().to_be_bytes());
+        resp.extend(q.rclass.to_u16().to_be_bytes());
+    }
+    resp
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dns::{Query, RecordClass, Response};
+    use crate::server::{Server, Zone};
+    use crate::zone::parse_zone;
+
+    const ZONE: &str = "$ORIGIN example.com.
+@        300 IN SOA   ns1 hostmaster 1 3600 600 86400 60
+@        300 IN NS    ns1
+ns1      300 IN A     192.0.2.1
+www      120 IN A     192.0.2.80
+";
+
+    // an authoritative upstream for ZONE plus extra lines
+    fn upstream(extra: &str) -> SocketAddr {
+        let records = parse_zone(&format!("{}{}", ZONE, extra), "example.com.").unwrap();
+        let server = Server::new(vec![Zone::new("example.com.", records).unwrap()]);
+
+        let udp = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = udp.local_addr().unwrap();
+        let tcp = TcpListener::bind(addr).unwrap();
+        thread::spawn(move || Arc::new(server).serve(udp, tcp));
+        addr
+    }
+
+    fn query(name: &str) -> (u16, Vec<u8>) {
+        let mut q = Query::new(name.to_string(), RecordType::A, RecordClass::IN).unwrap();
+        (q.header.id, q.query_serialize().unwrap())
+    }
+
+    #[test]
+    fn test_cache_ttl() {
+        let proxy = Proxy::new(upstream(""));
+
+        let (id, msg) = query("www.example.com");
+        let first = proxy.handle(&msg, UDP_SIZE).unwrap();
+        assert!(!first.cached);
+        let resp = Response::from_bytes(first.response).unwrap();
+        assert!(resp.header.id == id);
+        assert!(resp.answer[0].ttl == 120);
+
+        // a repeat, under another ID, comes from the cache
+        let (id, msg) = query("WWW.example.com");
+        let second = proxy.handle(&msg, UDP_SIZE).unwrap();
+        assert!(second.cached);
+        let resp = Response::from_bytes(second.response).unwrap();
+        assert!(resp.header.id == id);
+        assert!(resp.question.unwrap().get_name_str() == "WWW.example.com.");
+        assert!(resp.answer[0].rdata == RData::A([192, 0, 2, 80].into()));
+    }
+
+    #[test]
+    fn test_cache_expiry() {
+        let mut cache = Cache::default();
+        let now = Instant::now();
+
+        let (_, msg) = query("www.example.com");
+        let q = MessageRef::parse(&msg).unwrap();
+        let mut resp = q.to_message().unwrap();
+        resp.set_qr(true).add_answer(Answer::new(
+            "www.example.com.".to_string(),
+            RecordType::A,
+            RecordClass::IN,
+            120,
+            RData::A([192, 0, 2, 80].into()),
+        ));
+        cache.insert(
+            &q,
+            &MessageRef::parse(&resp.to_bytes().unwrap()).unwrap(),
+            now,
+        );
+
+        let hit = cache.get(&q, now + Duration::from_secs(100)).unwrap();
+        assert!(hit.answer[0].ttl == 20);
+        assert!(cache.get(&q, now + Duration::from_secs(120)).is_none());
+        assert!(cache.is_empty());
+
+        // negative answers last for the SOA minimum
+        let mut nx = q.to_message().unwrap();
+        nx.set_qr(true).set_rcode(NXDOMAIN);
+        nx.add_authority(crate::xfr::tests::soa("example.com.", 1));
+        assert!(cache_ttl(&MessageRef::parse(&nx.to_bytes().unwrap()).unwrap()) == Some(300));
+
+        // failures are not cached
+        nx.set_rcode(SERVFAIL);
+        assert!(cache_ttl(&MessageRef::parse(&nx.to_bytes().unwrap()).unwrap()).is_none());
+    }
+
+    #[test]
+    fn test_negative_cached() {
+        let proxy = Proxy::new(upstream(""));
+
+        let (_, msg) = query("nope.example.com");
+        let first = proxy.handle(&msg, UDP_SIZE).unwrap();
+        assert!(first.rcode == NXDOMAIN && !first.cached);
+        let second = proxy.handle(&msg, UDP_SIZE).unwrap();
+        assert!(second.rcode == NXDOMAIN && second.cached);
+    }
+
+    #[test]
+    fn test_tcp_fallback() {
+        let mut big = String::new();
+        for i in 0..40 {
+            big.push_str(&format!("big 300 IN A 10.0.0.{}\n", i));
+        }
+        let proxy = Proxy::new(upstream(&big));
+
+        // too big for a plain UDP client, so it is told to use TCP
+        let (_, msg) = query("big.example.com");
+        let udp = proxy.handle(&msg, UDP_SIZE).unwrap();
+        let resp = Response::from_bytes(udp.response).unwrap();
+        assert!(resp.header.flags.tc() && resp.answer.is_empty());
+
+        // while the proxy itself fetched everything over TCP
+        let tcp = proxy.handle(&msg, u16::MAX as usize).unwrap();
+        assert!(tcp.cached);
+        let resp = Response::from_bytes(tcp.response).unwrap();
+        assert!(resp.answer.len() == 40);
+    }
+
+    #[test]
+    fn test_wrong_question() {
+        // answers every query for www with a record for another name
+        let spoof = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = spoof.local_addr().unwrap();
+        thread::spawn(move || {
+            let mut buf = [0; 512];
+            while let Ok((size, peer)) = spoof.recv_from(&mut buf) {
+                let query = Message::from_bytes(&buf[..size]).unwrap();
+                let mut resp =
+                    Message::query("evil.example.com", RecordType::A, RecordClass::IN).unwrap();
+                resp.set_id(query.header.id).set_qr(true);
+                resp.add_answer(Answer::new(
+                    "evil.example.com.".to_string(),
+                    RecordType::A,
+                    RecordClass::IN,
+                    300,
+                    RData::A([203, 0, 113, 1].into()),
+                ));
+                let _ = spoof.send_to(&resp.to_bytes().unwrap(), peer);
+            }
+        });
+        let proxy = Proxy::new(addr);
+
+        let (_, msg) = query("www.example.com");
+        let outcome = proxy.handle(&msg, UDP_SIZE).unwrap();
+        assert!(outcome.rcode == SERVFAIL && !outcome.cached);
+        let resp = Response::from_bytes(outcome.response).unwrap();
+        assert!(resp.answer.is_empty());
+        assert!(proxy.cache_len() == 0);
+    }
+
+    #[test]
+    fn test_upstream_failure() {
+        // bound but never answers
+        let silent = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let mut proxy = Proxy::new(silent.local_addr().unwrap());
+        proxy.set_timeout(Duration::from_millis(50));
+
+        let (id, msg) = query("www.example.com");
+        let outcome = proxy.handle(&msg, UDP_SIZE).unwrap();
+        assert!(outcome.rcode == SERVFAIL);
+        let resp = Response::from_bytes(outcome.response).unwrap();
+        assert!(resp.header.id == id && resp.header.flags.rcode() == SERVFAIL);
+        assert!(proxy.cache_len() == 0);
+
+        // a query the full parser refuses, here for a HINFO with one string,
+        // still gets its SERVFAIL
+        let mut query = Message::query("www.example.com", RecordType::A, RecordClass::IN).unwrap();
+        query.add_additional(Answer::new(
+            "www.example.com.".to_string(),
+            RecordType::HINFO,
+            RecordClass::IN,
+            0,
+            RData::Unknown(b"\x03cpu".to_vec()),
+        ));
+        let msg = query.to_bytes().unwrap();
+        assert!(Message::from_bytes(&msg).is_err());
+
+        let outcome = proxy.handle(&msg, UDP_SIZE).unwrap();
+        let resp = MessageRef::parse(&outcome.response).unwrap();
+        assert!(resp.id() == query.header.id && resp.flags().rcode() == SERVFAIL);
+        assert!(resp.flags().qr() && resp.flags().rd());
+        let q = resp.questions().next().unwrap();
+        assert!(q.name.to_string() == "www.example.com." && q.rtype == RecordType::A);
+        assert!(resp.additional().next().is_none());
+    }
+}
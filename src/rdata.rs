@@ -0,0 +1,384 @@
+// Typed RDATA for the record types rig understands
+//
+// Names inside RDATA may be compressed, so records are decoded against the
+// whole message. Anything not handled here is kept as raw bytes and shown
+// in the RFC 3597 generic format.
+use byteorder::{BigEndian, ByteOrder};
+use std::fmt;
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+use serde::{Deserialize, Serialize};
+
+use crate::dns::{encode_name, read_name, RecordType};
+use crate::message::Compressor;
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub enum RData {
+    A(Ipv4Addr),
+    AAAA(Ipv6Addr),
+    NS(String),
+    CNAME(String),
+    PTR(String),
+    MX {
+        preference: u16,
+        exchange: String,
+    },
+    SOA {
+        mname: String,
+        rname: String,
+        serial: u32,
+        refresh: u32,
+        retry: u32,
+        expire: u32,
+        minimum: u32,
+    },
+    TXT(Vec<Vec<u8>>),
+    SRV {
+        priority: u16,
+        weight: u16,
+        port: u16,
+        target: String,
+    },
+    HINFO {
+        cpu: Vec<u8>,
+        os: Vec<u8>,
+    },
+    Unknown(Vec<u8>),
+}
+
+// a name inside rdata, which must not run past the end of the rdata
+fn name_at(msg: &[u8], offset: usize, end: usize) -> Result<(String, usize), String> {
+    let (name, len) = read_name(msg, offset)?;
+    if offset + len > end {
+        return Err("name runs past end of record data".to_string());
+    }
+    Ok((name, offset + len))
+}
+
+// <character-string>s filling data - RFC 1035 3.3
+fn char_strings(data: &[u8]) -> Result<Vec<Vec<u8>>, String> {
+    let mut strings = Vec::new();
+    let mut ptr = 0;
+    while ptr < data.len() {
+        let len = data[ptr] as usize;
+        let s = data
+            .get(ptr + 1..ptr + 1 + len)
+            .ok_or("character string runs past end of record data")?;
+        strings.push(s.to_vec());
+        ptr += len + 1;
+    }
+    Ok(strings)
+}
+
+fn push_char_string(bytes: &mut Vec<u8>, s: &[u8]) {
+    bytes.push(s.len() as u8);
+    bytes.extend(s);
+}
+
+// quoted <character-string> in presentation format - RFC 1035 5.1
+pub(crate) fn quote(s: &[u8]) -> String {
+    let mut out = String::from("\"");
+    for &b in s {
+        match b {
+            b'"' | b'\\' => {
+                out.push('\\');
+                out.push(b as char);
+            }
+            0x20..=0x7e => out.push(b as char),
+            _ => out.push_str(&format!("\\{:03}", b)),
+        }
+    }
+    out.push('"');
+    out
+}
+
+impl RData {
+    // decode len bytes of rdata at offset in msg
+    pub(crate) fn from_bytes(
+        rtype: RecordType,
+        msg: &[u8],
+        offset: usize,
+        len: usize,
+    ) -> Result<Self, String> {
+        let end = offset + len;
+        let data = msg
+            .get(offset..end)
+            .ok_or("record data runs past end of message")?;
+
+        let rdata = match rtype {
+            // empty rdata marks prerequisites and deletions - RFC 2136 2.4
+            _ if len == 0 => RData::Unknown(Vec::new()),
+            RecordType::A if len == 4 => {
+                RData::A(Ipv4Addr::new(data[0], data[1], data[2], data[3]))
+            }
+            RecordType::AAAA if len == 16 => {
+                let mut octets = [0; 16];
+                octets.copy_from_slice(data);
+                RData::AAAA(Ipv6Addr::from(octets))
+            }
+            RecordType::NS => RData::NS(name_at(msg, offset, end)?.0),
+            RecordType::CNAME => RData::CNAME(name_at(msg, offset, end)?.0),
+            RecordType::PTR => RData::PTR(name_at(msg, offset, end)?.0),
+            RecordType::MX if len > 2 => RData::MX {
+                preference: BigEndian::read_u16(data),
+                exchange: name_at(msg, offset + 2, end)?.0,
+            },
+            RecordType::SOA => {
+                let (mname, ptr) = name_at(msg, offset, end)?;
+                let (rname, ptr) = name_at(msg, ptr, end)?;
+                let times = msg
+                    .get(ptr..ptr + 20)
+                    .filter(|_| ptr + 20 <= end)
+                    .ok_or("SOA record too short")?;
+                RData::SOA {
+                    mname,
+                    rname,
+                    serial: BigEndian::read_u32(&times[0..]),
+                    refresh: BigEndian::read_u32(&times[4..]),
+                    retry: BigEndian::read_u32(&times[8..]),
+                    expire: BigEndian::read_u32(&times[12..]),
+                    minimum: BigEndian::read_u32(&times[16..]),
+                }
+            }
+            RecordType::TXT => RData::TXT(char_strings(data)?),
+            RecordType::SRV if len > 6 => RData::SRV {
+                priority: BigEndian::read_u16(&data[0..]),
+                weight: BigEndian::read_u16(&data[2..]),
+                port: BigEndian::read_u16(&data[4..]),
+                target: name_at(msg, offset + 6, end)?.0,
+            },
+            RecordType::HINFO => {
+                let mut strings = char_strings(data)?;
+                if strings.len() != 2 {
+                    return Err("HINFO record needs two strings".to_string());
+                }
+                let os = strings.pop().unwrap();
+                let cpu = strings.pop().unwrap();
+                RData::HINFO { cpu, os }
+            }
+            _ => RData::Unknown(data.to_vec()),
+        };
+
+        Ok(rdata)
+    }
+
+    // a copy with every domain name in the rdata passed through f
+    pub fn map_names(&self, f: impl Fn(&str) -> String) -> RData {
+        match self {
+            RData::NS(name) => RData::NS(f(name)),
+            RData::CNAME(name) => RData::CNAME(f(name)),
+            RData::PTR(name) => RData::PTR(f(name)),
+            RData::MX {
+                preference,
+                exchange,
+            } => RData::MX {
+                preference: *preference,
+                exchange: f(exchange),
+            },
+            RData::SOA {
+                mname,
+                rname,
+                serial,
+                refresh,
+                retry,
+                expire,
+                minimum,
+            } => RData::SOA {
+                mname: f(mname),
+                rname: f(rname),
+                serial: *serial,
+                refresh: *refresh,
+                retry: *retry,
+                expire: *expire,
+                minimum: *minimum,
+            },
+            RData::SRV {
+                priority,
+                weight,
+                port,
+                target,
+            } => RData::SRV {
+                priority: *priority,
+                weight: *weight,
+                port: *port,
+                target: f(target),
+            },
+            other => other.clone(),
+        }
+    }
+
+    // uncompressed wire format
+    pub fn to_bytes(&self) -> Result<Vec<u8>, String> {
+        let mut bytes = Vec::new();
+        self.write(&mut bytes, &mut Compressor::disabled())?;
+        Ok(bytes)
+    }
+
+    // Append the wire format to bytes. Only the names in the RFC 1035 types
+    // are compressed; later types must not be - RFC 3597 4
+    pub(crate) fn write(&self, bytes: &mut Vec<u8>, names: &mut Compressor) -> Result<(), String> {
+        match self {
+            RData::A(ip) => bytes.extend(ip.octets()),
+            RData::AAAA(ip) => bytes.extend(ip.octets()),
+            RData::NS(name) | RData::CNAME(name) | RData::PTR(name) => names.write(bytes, name)?,
+            RData::MX {
+                preference,
+                exchange,
+            } => {
+                bytes.extend(preference.to_be_bytes());
+                names.write(bytes, exchange)?;
+            }
+            RData::SOA {
+                mname,
+                rname,
+                serial,
+                refresh,
+                retry,
+                expire,
+                minimum,
+            } => {
+                names.write(bytes, mname)?;
+                names.write(bytes, rname)?;
+                for v in [serial, refresh, retry, expire, minimum] {
+                    bytes.extend(v.to_be_bytes());
+                }
+            }
+            RData::TXT(strings) => {
+                for s in strings {
+                    push_char_string(bytes, s);
+                }
+            }
+            RData::SRV {
+                priority,
+                weight,
+                port,
+                target,
+            } => {
+                for v in [priority, weight, port] {
+                    bytes.extend(v.to_be_bytes());
+                }
+                bytes.extend(encode_name(target)?);
+            }
+            RData::HINFO { cpu, os } => {
+                push_char_string(bytes, cpu);
+                push_char_string(bytes, os);
+            }
+            RData::Unknown(data) => bytes.extend(data),
+        }
+        Ok(())
+    }
+}
+
+// presentation format - RFC 1035 5.1, RFC 3597 5
+impl fmt::Display for RData {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RData::A(ip) => write!(f, "{}", ip),
+            RData::AAAA(ip) => write!(f, "{}", ip),
+            RData::NS(name) | RData::CNAME(name) | RData::PTR(name) => f.write_str(name),
+            RData::MX {
+                preference,
+                exchange,
+            } => write!(f, "{} {}", preference, exchange),
+            RData::SOA {
+                mname,
+                rname,
+                serial,
+                refresh,
+                retry,
+                expire,
+                minimum,
+            } => write!(
+                f,
+                "{} {} {} {} {} {} {}",
+                mname, rname, serial, refresh, retry, expire, minimum
+            ),
+            RData::TXT(strings) => {
+                let quoted: Vec<String> = strings.iter().map(|s| quote(s)).collect();
+                f.write_str(&quoted.join(" "))
+            }
+            RData::SRV {
+                priority,
+                weight,
+                port,
+                target,
+            } => write!(f, "{} {} {} {}", priority, weight, port, target),
+            RData::HINFO { cpu, os } => write!(f, "{} {}", quote(cpu), quote(os)),
+            RData::Unknown(data) if data.is_empty() => f.write_str("\\# 0"),
+            RData::Unknown(data) => {
+                write!(f, "\\# {} ", data.len())?;
+                for b in data {
+                    write!(f, "{:02x}", b)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compressed_rdata() {
+        // example.com at offset 0, then an MX rdata pointing back at it
+        let mut msg = vec![
+            7, b'e', b'x', b'a', b'm', b'p', b'l', b'e', 3, b'c', b'o', b'm', 0,
+        ];
+        let offset = msg.len();
+        msg.extend([0, 10, 4, b'm', b'a', b'i', b'l', 0xc0, 0]);
+
+        let rdata = RData::from_bytes(RecordType::MX, &msg, offset, 9).unwrap();
+        assert!(
+            rdata
+                == RData::MX {
+                    preference: 10,
+                    exchange: "mail.example.com.".to_string()
+                }
+        );
+        assert!(rdata.to_string() == "10 mail.example.com.");
+
+        // a name may not spill past the rdata length
+        assert!(RData::from_bytes(RecordType::NS, &msg, offset + 2, 3).is_err());
+    }
+
+    #[test]
+    fn test_rdata_round_trip() {
+        let records = [
+            RData::A(Ipv4Addr::new(192, 0, 2, 1)),
+            RData::AAAA("2001:db8::1".parse().unwrap()),
+            RData::SOA {
+                mname: "ns1.example.com.".to_string(),
+                rname: "hostmaster.example.com.".to_string(),
+                serial: 2022010101,
+                refresh: 3600,
+                retry: 600,
+                expire: 86400,
+                minimum: 300,
+            },
+            RData::TXT(vec![b"v=spf1 -all".to_vec(), b"say \"hi\"".to_vec()]),
+            RData::SRV {
+                priority: 0,
+                weight: 5,
+                port: 5060,
+                target: "sip.example.com.".to_string(),
+            },
+        ];
+        let types = [
+            RecordType::A,
+            RecordType::AAAA,
+            RecordType::SOA,
+            RecordType::TXT,
+            RecordType::SRV,
+        ];
+
+        for (rdata, rtype) in records.iter().zip(types) {
+            let bytes = rdata.to_bytes().unwrap();
+            assert!(RData::from_bytes(rtype, &bytes, 0, bytes.len()).unwrap() == *rdata);
+        }
+
+        assert!(records[3].to_string() == "\"v=spf1 -all\" \"say \\\"hi\\\"\"");
+        assert!(RData::Unknown(vec![0xde, 0xad]).to_string() == "\\# 2 dead");
+    }
+}
@@ -1,7 +1,34 @@
+use std::collections::BTreeMap;
 use std::env;
+use std::fs::{self, File};
+use std::io::{self, BufReader, Read};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::Path;
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
 use structopt::StructOpt;
 
 use librig;
+use librig::bench::Bench;
+use librig::compare;
+use librig::dns::{self, RecordClass, RecordType};
+use librig::message::Message;
+use librig::notify;
+use librig::pcap;
+use librig::proxy::Proxy;
+use librig::rdata::RData;
+use librig::secondary::Secondary;
+use librig::server::{Server, Zone};
+use librig::spec::{self, QuerySpec};
+use librig::stats;
+use librig::tsig::Key;
+use librig::update;
+use librig::wire;
+use librig::zone::{self, TtlStyle, WriteOptions};
+
+// how long rig --count and --compare wait for each reply
+const REPLY_TIMEOUT: Duration = Duration::from_secs(2);
 
 #[derive(Debug, StructOpt)]
 #[structopt(
@@ -17,43 +44,1113 @@ struct Args {
         help = "server to perform lookups against <IP:port> (53 assumed if not set)"
     )]
     server: Option<String>,
+    #[structopt(
+        short = "j",
+        long = "jobs",
+        default_value = "1",
+        help = "number of lookups to run concurrently"
+    )]
+    jobs: usize,
+    #[structopt(
+        long = "unordered",
+        help = "print results as lookups complete rather than in input order"
+    )]
+    unordered: bool,
+    #[structopt(
+        short = "f",
+        long = "file",
+        help = "read query specs (name [type] [class] [@server] [+short]) from a file, - for stdin"
+    )]
+    file: Option<String>,
+    #[structopt(
+        long = "axfr",
+        help = "transfer each hostname as a zone over TCP and print it in master file format"
+    )]
+    axfr: bool,
+    #[structopt(
+        long = "ixfr",
+        name = "SERIAL",
+        help = "show what changed in each hostname's zone since SERIAL (IXFR over TCP)"
+    )]
+    ixfr: Option<u32>,
+    #[structopt(
+        short = "o",
+        long = "output",
+        help = "save a transferred zone to this file instead of printing it"
+    )]
+    output: Option<String>,
+    #[structopt(
+        short = "k",
+        long = "keyfile",
+        help = "sign transfers and updates with the TSIG key in this BIND key file"
+    )]
+    keyfile: Option<String>,
+    #[structopt(
+        short = "y",
+        long = "key",
+        help = "sign transfers and updates with this TSIG key, given as [algorithm:]name:secret"
+    )]
+    key: Option<String>,
+    #[structopt(
+        long = "idn",
+        overrides_with = "no-idn",
+        help = "show Unicode forms alongside xn-- names (the default)"
+    )]
+    idn: bool,
+    #[structopt(
+        long = "no-idn",
+        overrides_with = "idn",
+        help = "show xn-- names as they are on the wire"
+    )]
+    no_idn: bool,
+    #[structopt(
+        long = "wire",
+        help = "print the bytes of each query and response as an annotated hexdump"
+    )]
+    wire: bool,
+    #[structopt(
+        short = "c",
+        long = "count",
+        conflicts_with = "wire",
+        help = "send each query N times, then report round trip times, loss, answer changes and TTLs"
+    )]
+    count: Option<usize>,
+    #[structopt(
+        long = "interval",
+        default_value = "1s",
+        parse(try_from_str = stats::parse_duration),
+        help = "time between repeated queries, e.g. 100ms"
+    )]
+    interval: Duration,
+    #[structopt(
+        long = "compare",
+        conflicts_with_all = &["count", "wire"],
+        help = "ask every @server each query at once and tabulate where their answers differ"
+    )]
+    compare: bool,
+    #[structopt(subcommand)]
+    cmd: Option<Command>,
+    #[structopt(
+        help = "hostnames to look up; @server (IP or name) and +options may appear anywhere"
+    )]
     hostnames: Vec<String>,
 }
 
+#[derive(Debug, StructOpt)]
+enum Command {
+    #[structopt(about = "format and lint a zone file")]
+    Fmt {
+        file: String,
+        #[structopt(
+            long = "origin",
+            help = "origin for relative names (default: the SOA owner)"
+        )]
+        origin: Option<String>,
+        #[structopt(long = "ttl-units", help = "write TTLs as 1h30m rather than seconds")]
+        ttl_units: bool,
+        #[structopt(
+            long = "no-align",
+            help = "separate fields with tabs instead of columns"
+        )]
+        no_align: bool,
+        #[structopt(long = "canonical", help = "sort records into DNSSEC canonical order")]
+        canonical: bool,
+        #[structopt(short = "w", long = "write", help = "rewrite the file in place")]
+        write: bool,
+        #[structopt(
+            long = "check",
+            help = "exit non-zero if the file has warnings or is not formatted"
+        )]
+        check: bool,
+    },
+    #[structopt(about = "serve zones authoritatively over UDP and TCP")]
+    Serve {
+        #[structopt(
+            long = "zone",
+            required_unless = "secondaries",
+            number_of_values = 1,
+            help = "zone to serve, as ORIGIN=FILE; may be repeated"
+        )]
+        zones: Vec<String>,
+        #[structopt(
+            long = "secondary",
+            number_of_values = 1,
+            help = "zone to transfer and keep up to date, as ORIGIN=PRIMARY; may be repeated"
+        )]
+        secondaries: Vec<String>,
+        #[structopt(
+            long = "dir",
+            help = "directory to keep copies of secondary zones in across restarts"
+        )]
+        dir: Option<String>,
+        #[structopt(
+            long = "listen",
+            default_value = "127.0.0.1:53",
+            help = "address to listen on"
+        )]
+        listen: String,
+    },
+    #[structopt(about = "run a caching forwarder, logging each query")]
+    Proxy {
+        #[structopt(
+            long = "listen",
+            default_value = "127.0.0.1:53",
+            help = "address to listen on"
+        )]
+        listen: String,
+        #[structopt(
+            long = "upstream",
+            help = "server to forward to (default: first resolv.conf nameserver)"
+        )]
+        upstream: Option<String>,
+    },
+    #[structopt(about = "send dynamic updates from an nsupdate style script")]
+    Update {
+        #[structopt(help = "script to read (default: stdin)")]
+        file: Option<String>,
+        #[structopt(
+            short = "s",
+            long = "server",
+            help = "server for updates whose script names none (default: first resolv.conf nameserver)"
+        )]
+        server: Option<String>,
+    },
+    #[structopt(about = "tell secondaries a zone has changed (NOTIFY)")]
+    Notify {
+        zone: String,
+        #[structopt(required = true, help = "secondaries to notify, as @server[:port]")]
+        servers: Vec<String>,
+        #[structopt(
+            long = "primary",
+            help = "server to read the current SOA from (default: first resolv.conf nameserver)"
+        )]
+        primary: Option<String>,
+    },
+    #[structopt(about = "decode the DNS messages in a pcap or pcapng capture")]
+    Pcap {
+        file: String,
+        #[structopt(long = "name", help = "only show questions for this name or below it")]
+        name: Option<String>,
+        #[structopt(long = "type", help = "only show questions of this type")]
+        rtype: Option<RecordType>,
+        #[structopt(
+            long = "rcode",
+            help = "only show responses with this rcode, e.g. NXDOMAIN"
+        )]
+        rcode: Option<String>,
+    },
+    #[structopt(about = "annotate a DNS message given as hex, base64 or binary")]
+    Decode {
+        #[structopt(help = "file to read (default: stdin)")]
+        file: Option<String>,
+        #[structopt(long = "format", help = "hex, base64 or binary (default: guessed)")]
+        format: Option<wire::InputFormat>,
+    },
+    #[structopt(about = "send queries at a target rate and report throughput and latency")]
+    Bench {
+        #[structopt(
+            short = "f",
+            long = "file",
+            help = "queries to send round robin, one per line as in batch mode; - reads stdin"
+        )]
+        file: String,
+        #[structopt(
+            long = "qps",
+            help = "queries per second to aim for (default: as many as --concurrency allows)"
+        )]
+        qps: Option<u32>,
+        #[structopt(
+            long = "duration",
+            default_value = "10s",
+            parse(try_from_str = stats::parse_duration),
+            help = "how long to send queries for"
+        )]
+        duration: Duration,
+        #[structopt(
+            long = "concurrency",
+            default_value = "100",
+            help = "most queries to have waiting for a reply at once"
+        )]
+        concurrency: usize,
+        #[structopt(
+            long = "timeout",
+            default_value = "5s",
+            parse(try_from_str = stats::parse_duration),
+            help = "how long to wait for each reply"
+        )]
+        timeout: Duration,
+        #[structopt(
+            help = "server to benchmark, as @server[:port] (default: first resolv.conf nameserver)"
+        )]
+        server: Option<String>,
+    },
+}
+
+// split an ORIGIN=VALUE argument, exiting if it is not one
+fn origin_pair<'a>(arg: &'a str, value: &str) -> (&'a str, &'a str) {
+    match arg.split_once('=') {
+        Some(pair) => pair,
+        None => {
+            eprintln!("Zone {} should be given as ORIGIN={}", arg, value);
+            std::process::exit(1);
+        }
+    }
+}
+
+// Print the DNS messages in a capture file that pass the filters
+fn read_capture(
+    file: &str,
+    name: Option<String>,
+    rtype: Option<RecordType>,
+    rcode: Option<String>,
+) {
+    let fail = |e: String| -> ! {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    };
+
+    let mut filter = pcap::Filter {
+        rtype,
+        ..Default::default()
+    };
+    if let Some(n) = name {
+        filter.name = Some(n.parse().unwrap_or_else(|e| fail(format!("{}: {}", n, e))));
+    }
+    if let Some(r) = rcode {
+        let code = r
+            .parse::<u8>()
+            .ok()
+            .or_else(|| (0..=15).find(|&c| dns::rcode_str(c).eq_ignore_ascii_case(&r)));
+        filter.rcode = Some(code.unwrap_or_else(|| fail(format!("unknown rcode {}", r))));
+    }
+
+    let bytes = fs::read(file).unwrap_or_else(|e| fail(format!("Failed to read {}: {}", file, e)));
+    let frames = pcap::read_frames(&bytes).unwrap_or_else(|e| fail(format!("{}: {}", file, e)));
+    println!("{}", pcap::report(&pcap::dns_packets(&frames), &filter));
+}
+
+// Print the annotated breakdown of a message read from file or stdin,
+// exiting 2 if it does not parse
+fn decode(file: Option<String>, format: Option<wire::InputFormat>) {
+    let input = match &file {
+        Some(f) => fs::read(f),
+        None => {
+            let mut buf = Vec::new();
+            io::stdin().read_to_end(&mut buf).map(|_| buf)
+        }
+    };
+    let label = file.as_deref().unwrap_or("stdin");
+    let bytes = match input
+        .map_err(|e| e.to_string())
+        .and_then(|i| wire::decode_input(&i, format))
+    {
+        Ok(b) => b,
+        Err(e) => {
+            eprintln!("Failed to read {}: {}", label, e);
+            std::process::exit(1);
+        }
+    };
+
+    println!("{}", wire::annotate(&bytes));
+    if Message::from_bytes(&bytes).is_err() {
+        std::process::exit(2);
+    }
+}
+
+// Load each ORIGIN=FILE zone, keep each ORIGIN=PRIMARY zone transferred
+// from its primary, and answer queries until killed
+fn serve_zones(
+    zones: &[String],
+    secondaries: &[String],
+    dir: Option<String>,
+    listen: &str,
+    key: Option<Key>,
+) {
+    let mut loaded = Vec::new();
+    for z in zones {
+        let (origin, file) = origin_pair(z, "FILE");
+        match Zone::load(origin, file) {
+            Ok(zone) => {
+                eprintln!(
+                    "; loaded {} from {} ({} records)",
+                    zone.origin(),
+                    file,
+                    zone.records().count()
+                );
+                loaded.push(zone);
+            }
+            Err(e) => {
+                eprintln!("Failed to load {}: {}", file, e);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    let mut runners = Vec::new();
+    for s in secondaries {
+        let (origin, primary) = origin_pair(s, "PRIMARY");
+        let primary = match librig::resolve_server(primary) {
+            Ok(a) => a,
+            Err(e) => {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+        };
+        let mut secondary = Secondary::new(origin, primary);
+        if let Some(k) = &key {
+            secondary.set_key(k.clone());
+        }
+        if let Some(d) = &dir {
+            let file = format!("{}.zone", secondary.origin().trim_end_matches('.'));
+            secondary.set_file(&Path::new(d).join(file).to_string_lossy());
+        }
+        runners.push(secondary);
+    }
+
+    // a NOTIFY for a secondary zone wakes its refresh thread
+    let mut server = Server::new(loaded);
+    let mut wake = BTreeMap::new();
+    let mut receivers = Vec::new();
+    for secondary in &runners {
+        let (tx, rx) = mpsc::channel();
+        wake.insert(secondary.origin().to_string(), Mutex::new(tx));
+        receivers.push(rx);
+    }
+    server.on_notify(
+        move |origin, _| match wake.get(&origin.to_ascii_lowercase()) {
+            Some(tx) => tx.lock().unwrap().send(()).is_ok(),
+            None => false,
+        },
+    );
+
+    let server = Arc::new(server);
+    for (secondary, rx) in runners.into_iter().zip(receivers) {
+        eprintln!(
+            "; secondary for {} from {}",
+            secondary.origin(),
+            secondary.primary()
+        );
+        let server = server.clone();
+        thread::spawn(move || secondary.run(server, rx));
+    }
+
+    eprintln!("; listening on {} (UDP and TCP)", listen);
+    if let Err(e) = server.listen(listen) {
+        eprintln!("Failed to serve on {}: {}", listen, e);
+        std::process::exit(1);
+    }
+}
+
+// Reformat a zone file, reporting lint warnings on stderr. Comments and
+// $INCLUDE structure are not preserved; included records are inlined.
+fn fmt_zone(file: &str, origin: Option<String>, opts: WriteOptions, write: bool, check: bool) {
+    let records = match zone::parse_zone_file(file, origin.as_deref().unwrap_or(".")) {
+        Ok(r) => r,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+
+    let origin = origin.or_else(|| {
+        records
+            .iter()
+            .find(|r| r.rtype == librig::dns::RecordType::SOA)
+            .map(|r| r.name.clone())
+    });
+
+    let warnings = match &origin {
+        Some(o) => zone::lint_zone(&records, o),
+        None => vec!["no SOA record and no --origin given".to_string()],
+    };
+    for w in &warnings {
+        eprintln!("{}: warning: {}", file, w);
+    }
+
+    let formatted = zone::write_zone(&records, &WriteOptions { origin, ..opts });
+
+    if check {
+        let current = fs::read_to_string(file).unwrap_or_default();
+        if current != formatted {
+            eprintln!("{}: not formatted", file);
+        }
+        if current != formatted || !warnings.is_empty() {
+            std::process::exit(1);
+        }
+    } else if write {
+        if let Err(e) = fs::write(file, formatted) {
+            eprintln!("Failed to write {}: {}", file, e);
+            std::process::exit(1);
+        }
+    } else {
+        print!("{}", formatted);
+    }
+}
+
+// Forward queries from listen to upstream until killed
+fn proxy(listen: &str, upstream: Option<String>) {
+    let upstream = upstream.unwrap_or_else(|| librig::parse_resolvconf_nameserver(None));
+    let upstream = match librig::resolve_server(&upstream) {
+        Ok(a) => a,
+        Err(e) => {
+            eprintln!("Failed to use upstream: {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    eprintln!("; forwarding {} to {}", listen, upstream);
+    if let Err(e) = Proxy::new(upstream).listen(listen) {
+        eprintln!("Failed to serve on {}: {}", listen, e);
+        std::process::exit(1);
+    }
+}
+
+// Send a NOTIFY for zone to each server, carrying the SOA the primary
+// currently serves. Exits 2 if any server did not acknowledge it.
+fn send_notify(zone: &str, servers: &[String], primary: Option<String>, key: Option<Key>) {
+    let primary = primary.unwrap_or_else(|| librig::parse_resolvconf_nameserver(None));
+    let soa = librig::resolve_server(&primary).ok().and_then(|addr| {
+        let resp = dns::Query::do_query(
+            zone.to_string(),
+            addr.to_string(),
+            RecordType::SOA,
+            RecordClass::IN,
+        )?;
+        resp.answer
+            .into_iter()
+            .find(|rr| rr.rtype == RecordType::SOA)
+    });
+    let serial = match soa.as_ref().map(|rr| &rr.rdata) {
+        Some(RData::SOA { serial, .. }) => format!(" serial {}", serial),
+        _ => {
+            eprintln!(
+                "; no SOA for {} from {}, notifying without one",
+                zone, primary
+            );
+            String::new()
+        }
+    };
+
+    let mut status = 0;
+    for server in servers {
+        let server = server.strip_prefix('@').unwrap_or(server);
+        let addr = match librig::resolve_server(server) {
+            Ok(a) => a,
+            Err(e) => {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+        };
+
+        let summary = format!("; notify {}{} to {}", zone, serial, addr);
+        match notify::notify(zone, soa.as_ref(), addr, key.as_ref()) {
+            Ok(resp) if resp.header.flags.rcode() == 0 => {
+                println!("{}: NOERROR (acknowledged)", summary)
+            }
+            Ok(resp) => {
+                println!("{}: {}", summary, dns::rcode_str(resp.header.flags.rcode()));
+                status = 2;
+            }
+            Err(e) => {
+                eprintln!("{}: {}", summary, e);
+                status = 2;
+            }
+        }
+    }
+    std::process::exit(status);
+}
+
+// Send each update in a script, stopping at the first that fails since
+// later updates usually depend on it
+fn send_updates(file: Option<String>, server: Option<String>, key: Option<Key>) {
+    let script = match &file {
+        Some(f) => fs::read_to_string(f),
+        None => io::read_to_string(io::stdin()),
+    };
+    let label = file.as_deref().unwrap_or("stdin");
+    let script = match script {
+        Ok(s) => s,
+        Err(e) => {
+            eprintln!("Failed to read {}: {}", label, e);
+            std::process::exit(1);
+        }
+    };
+
+    let updates = match update::parse_script(&script) {
+        Ok(u) => u,
+        Err(e) => {
+            eprintln!("{}: {}", label, e);
+            std::process::exit(1);
+        }
+    };
+
+    let default = server.unwrap_or_else(|| librig::parse_resolvconf_nameserver(None));
+    for entry in updates {
+        let server = entry.server.as_deref().unwrap_or(&default);
+        let addr = match librig::resolve_server(server) {
+            Ok(a) => a,
+            Err(e) => {
+                eprintln!("{}:{}: {}", label, entry.line, e);
+                std::process::exit(1);
+            }
+        };
+
+        let u = &entry.update;
+        let summary = format!(
+            "; update {} ({} prerequisites, {} changes) to {}",
+            u.zone(),
+            u.prerequisites().len(),
+            u.updates().len(),
+            addr
+        );
+        match u.send(addr, entry.key.as_ref().or(key.as_ref())) {
+            Ok(resp) if resp.header.flags.rcode() == 0 => println!("{}: NOERROR", summary),
+            Ok(resp) => {
+                let rcode = resp.header.flags.rcode();
+                println!(
+                    "{}: {} ({})",
+                    summary,
+                    dns::rcode_str(rcode),
+                    update::rcode_meaning(rcode)
+                );
+                std::process::exit(2);
+            }
+            Err(e) => {
+                eprintln!("{}: {}", summary, e);
+                std::process::exit(1);
+            }
+        }
+    }
+}
+
+// read a batch file, or stdin for -, exiting if it cannot be opened.
+// Errors carry the file and line.
+fn read_batch(file: &str) -> Vec<Result<QuerySpec, String>> {
+    let batch = if file == "-" {
+        spec::parse_batch(io::stdin().lock())
+    } else {
+        match File::open(file) {
+            Ok(f) => spec::parse_batch(BufReader::new(f)),
+            Err(e) => {
+                eprintln!("Failed to open {}: {}", file, e);
+                std::process::exit(1);
+            }
+        }
+    };
+
+    let source = if file == "-" { "stdin" } else { file };
+    batch
+        .into_iter()
+        .map(|(line, s)| s.map_err(|e| format!("{}:{}: {}", source, line, e)))
+        .collect()
+}
+
+fn run_bench(
+    file: &str,
+    server: Option<String>,
+    qps: Option<u32>,
+    duration: Duration,
+    concurrency: usize,
+    timeout: Duration,
+) {
+    let mut queries = Vec::new();
+    for spec in read_batch(file) {
+        match spec {
+            Ok(s) => queries.push(s),
+            Err(e) => eprintln!("{}", e),
+        }
+    }
+
+    let server = server.unwrap_or_else(|| librig::parse_resolvconf_nameserver(None));
+    let server = server.strip_prefix('@').unwrap_or(&server);
+    let addr = librig::resolve_server(server).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    });
+
+    let mut bench = Bench::new(addr, &queries).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    });
+    bench
+        .set_duration(duration)
+        .set_concurrency(concurrency)
+        .set_timeout(timeout);
+    if let Some(q) = qps {
+        bench.set_qps(q);
+    }
+    match bench.run() {
+        Ok(report) => println!("{}", report),
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+// Send each query count times, ping style, one job after another
+fn repeat_lookups(
+    jobs: Vec<(Option<String>, Result<QuerySpec, String>)>,
+    count: usize,
+    interval: Duration,
+    idn: bool,
+    nameserver: &str,
+) {
+    for (label, spec) in jobs {
+        let spec = match spec {
+            Ok(s) => s,
+            Err(e) => {
+                eprintln!("{}", e);
+                continue;
+            }
+        };
+        let server = spec.server.as_deref().unwrap_or(nameserver);
+        if let Some(l) = label {
+            println!("{}", l);
+        }
+        println!(
+            "; {} {} {} to {}",
+            spec.name, spec.rclass, spec.rtype, server
+        );
+
+        let mut repeat = stats::Repeat::new();
+        repeat.set_idn(idn);
+        for seq in 1..=count {
+            let start = Instant::now();
+            let reply = librig::time_lookup(&spec, nameserver, REPLY_TIMEOUT);
+            println!("{}", repeat.add(seq, reply));
+            if seq < count {
+                thread::sleep(interval.saturating_sub(start.elapsed()));
+            }
+        }
+        println!("; {} statistics\n{}\n", spec.name, repeat.summary());
+    }
+}
+
+// Run f over every job on up to `workers` threads, printing each result
+// separated by a blank line and each error to stderr. Output follows input order unless unordered
+// is set, in which case results are printed as soon as they complete. A job that panics counts
+// as an error. Returns false if any job failed.
+fn run_jobs<T, F>(jobs: Vec<T>, workers: usize, unordered: bool, f: F) -> bool
+where
+    T: Send + 'static,
+    F: Fn(T) -> Result<String, String> + Send + Sync + 'static,
+{
+    let (mut printed, mut failed) = (0, false);
+    each_result(jobs, workers, unordered, f, |out| match out {
+        Ok(o) => {
+            if printed > 0 {
+                println!();
+            }
+            println!("{}", o);
+            printed += 1;
+        }
+        Err(e) => {
+            eprintln!("{}", e);
+            failed = true;
+        }
+    });
+    !failed
+}
+
+// the work of run_jobs: hand each result to emit, in input order unless unordered
+fn each_result<T, F, E>(jobs: Vec<T>, workers: usize, unordered: bool, f: F, mut emit: E)
+where
+    T: Send + 'static,
+    F: Fn(T) -> Result<String, String> + Send + Sync + 'static,
+    E: FnMut(Result<String, String>),
+{
+    let workers = workers.clamp(1, jobs.len().max(1));
+    let queue = Arc::new(Mutex::new(jobs.into_iter().enumerate()));
+    let f = Arc::new(f);
+    let (tx, rx) = mpsc::channel();
+
+    let handles: Vec<_> = (0..workers)
+        .map(|_| {
+            let queue = queue.clone();
+            let f = f.clone();
+            let tx = tx.clone();
+            thread::spawn(move || loop {
+                let job = queue.lock().unwrap().next();
+                match job {
+                    Some((i, j)) => {
+                        let out = panic::catch_unwind(AssertUnwindSafe(|| f(j)))
+                            .unwrap_or_else(|_| Err("lookup failed unexpectedly".to_string()));
+                        if tx.send((i, out)).is_err() {
+                            break;
+                        }
+                    }
+                    None => break,
+                }
+            })
+        })
+        .collect();
+    drop(tx);
+
+    // results that arrived ahead of their turn
+    let mut held = BTreeMap::new();
+    let mut next = 0;
+    for (i, out) in rx {
+        if unordered {
+            emit(out);
+            continue;
+        }
+
+        held.insert(i, out);
+        while let Some(out) = held.remove(&next) {
+            emit(out);
+            next += 1;
+        }
+    }
+
+    for h in handles {
+        let _ = h.join();
+    }
+}
+
+// AXFR (or IXFR from a serial) every zone from every server, either
+// printing the results or saving a single transfer to output
+fn transfer_zones(
+    zones: Vec<String>,
+    servers: &[(String, String)],
+    ixfr: Option<u32>,
+    output: Option<String>,
+    key: Option<Key>,
+) {
+    if zones.is_empty() {
+        eprintln!("No zone provided");
+        std::process::exit(1);
+    }
+
+    let jobs: Vec<(String, String)> = zones
+        .iter()
+        .flat_map(|z| {
+            servers
+                .iter()
+                .map(move |(_, addr)| (z.clone(), addr.clone()))
+        })
+        .collect();
+
+    let transfer = move |zone: &str, server: &str| match ixfr {
+        Some(serial) => librig::zone_diff(zone, server, serial, key.as_ref()),
+        None => librig::zone_transfer(zone, server, key.as_ref()),
+    };
+
+    let output = match output {
+        Some(o) if jobs.len() == 1 => o,
+        Some(_) => {
+            eprintln!("--output needs exactly one zone and server");
+            std::process::exit(1);
+        }
+        None => {
+            if !run_jobs(jobs, 1, false, move |(zone, server)| {
+                transfer(&zone, &server)
+            }) {
+                std::process::exit(1);
+            }
+            return;
+        }
+    };
+
+    let (zone, server) = &jobs[0];
+    let result = transfer(zone, server)
+        .and_then(|z| fs::write(&output, z + "\n").map_err(|e| e.to_string()));
+    if let Err(e) = result {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+}
+
+// the TSIG key given by -k or -y, if any
+fn load_key(keyfile: Option<String>, spec: Option<String>) -> Option<Key> {
+    let key = match (keyfile, spec) {
+        (Some(_), Some(_)) => Err("-k and -y cannot be used together".to_string()),
+        (Some(file), None) => Key::load(&file),
+        (None, Some(spec)) => Key::from_spec(&spec),
+        (None, None) => return None,
+    };
+    match key {
+        Ok(k) => Some(k),
+        Err(e) => {
+            eprintln!("Failed to load TSIG key: {}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
 fn main() {
     let args = Args::from_args();
+    let key = load_key(args.keyfile, args.key);
+
+    match args.cmd {
+        Some(Command::Fmt {
+            file,
+            origin,
+            ttl_units,
+            no_align,
+            canonical,
+            write,
+            check,
+        }) => {
+            let opts = WriteOptions {
+                origin: None,
+                ttl_style: if ttl_units {
+                    TtlStyle::Units
+                } else {
+                    TtlStyle::Seconds
+                },
+                align: !no_align,
+                canonical,
+            };
+            fmt_zone(&file, origin, opts, write, check);
+            return;
+        }
+        Some(Command::Serve {
+            zones,
+            secondaries,
+            dir,
+            listen,
+        }) => {
+            serve_zones(&zones, &secondaries, dir, &listen, key);
+            return;
+        }
+        Some(Command::Proxy { listen, upstream }) => {
+            proxy(&listen, upstream);
+            return;
+        }
+        Some(Command::Update { file, server }) => {
+            send_updates(file, server, key);
+            return;
+        }
+        Some(Command::Notify {
+            zone,
+            servers,
+            primary,
+        }) => {
+            send_notify(&zone, &servers, primary, key);
+            return;
+        }
+        Some(Command::Pcap {
+            file,
+            name,
+            rtype,
+            rcode,
+        }) => {
+            read_capture(&file, name, rtype, rcode);
+            return;
+        }
+        Some(Command::Decode { file, format }) => {
+            decode(file, format);
+            return;
+        }
+        Some(Command::Bench {
+            file,
+            qps,
+            duration,
+            concurrency,
+            timeout,
+            server,
+        }) => {
+            run_bench(&file, server, qps, duration, concurrency, timeout);
+            return;
+        }
+        None => {}
+    }
 
     if env::args().len() < 2 {
         eprintln!("No hostname provided");
         std::process::exit(1);
     }
 
-    // parse DNS server
-    // if set in args, use that one
-    // otherwise, parse /etc/resolv.conf and find the nameserver
-    // append ports in either case
-    let mut nameserver: String = match args.server {
-        Some(ns) => ns,
-        None => librig::parse_resolvconf_nameserver(None),
-    };
+    // dig style @server arguments may appear anywhere among the hostnames
+    let (servers, hostnames): (Vec<String>, Vec<String>) =
+        args.hostnames.into_iter().partition(|h| h.starts_with('@'));
+    let mut servers: Vec<String> = servers.into_iter().map(|s| s[1..].to_string()).collect();
+    // as may +options, which apply to every hostname on the command line
+    let (options, hostnames): (Vec<String>, Vec<String>) =
+        hostnames.into_iter().partition(|h| h.starts_with('+'));
 
-    // add specified port to the namserver
-    if !nameserver.contains(":") {
-        nameserver.push_str(":53");
+    // parse DNS servers
+    // -s and any @servers are all used
+    // otherwise, parse /etc/resolv.conf and find the nameserver
+    if let Some(ns) = args.server {
+        servers.insert(0, ns);
+    }
+    if servers.is_empty() {
+        servers.push(librig::parse_resolvconf_nameserver(None));
     }
 
-    let num_domains = args.hostnames.len();
-    let mut done_domains = 0;
+    // resolve names and append ports up front so failures are reported once
+    let servers: Vec<(String, String)> = servers
+        .into_iter()
+        .map(|s| match librig::resolve_server(&s) {
+            Ok(addr) => (s, addr.to_string()),
+            Err(e) => {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+        })
+        .collect();
+    let nameserver = servers[0].1.clone();
 
-    if args.hostnames.len() >= 1 {
-        for d in args.hostnames {
-            librig::do_lookup(d, nameserver.clone());
-            done_domains += 1;
-            if done_domains < num_domains {
-                println!("");
+    if args.axfr || args.ixfr.is_some() {
+        transfer_zones(hostnames, &servers, args.ixfr, args.output, key);
+        return;
+    }
+
+    let mut specs: Vec<Result<QuerySpec, String>> = Vec::new();
+    for h in hostnames {
+        let mut spec = QuerySpec::new(h);
+        for opt in &options {
+            if let Err(e) = spec.set_option(&opt[1..]) {
+                eprintln!("{}", e);
+                std::process::exit(1);
             }
         }
-    } else {
+        specs.push(Ok(spec));
+    }
+
+    if let Some(file) = args.file {
+        specs.extend(read_batch(&file));
+    }
+
+    if specs.is_empty() {
         eprintln!("No hostname provided");
+        return;
+    }
+
+    let (idn, wire) = (args.idn || !args.no_idn, args.wire);
+    if args.compare {
+        if servers.len() < 2 {
+            eprintln!("--compare needs at least two servers");
+            std::process::exit(1);
+        }
+        let ok = run_jobs(specs, args.jobs, args.unordered, move |s| {
+            let mut s = s?;
+            s.idn = idn;
+            let addrs: Vec<String> = servers.iter().map(|(_, a)| a.clone()).collect();
+            let labels: Vec<String> = servers.iter().map(|(n, _)| format!("@{}", n)).collect();
+            let replies = compare::query_all(&s, &addrs, REPLY_TIMEOUT);
+            Ok(compare::render(&s, &labels, &replies))
+        });
+        if !ok {
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    // specs without their own server are asked of every server, labelled
+    // with the server when there is more than one to compare
+    let mut jobs = Vec::new();
+    for spec in specs {
+        match spec {
+            Ok(s) if s.server.is_none() && servers.len() > 1 => {
+                for (name, addr) in &servers {
+                    let mut s = s.clone();
+                    s.server = Some(addr.clone());
+                    jobs.push((Some(format!("@{} ({})", name, addr)), Ok(s)));
+                }
+            }
+            s => jobs.push((None, s)),
+        }
+    }
+
+    if let Some(count) = args.count {
+        repeat_lookups(jobs, count, args.interval, idn, &nameserver);
+        return;
+    }
+    let ok = run_jobs(jobs, args.jobs, args.unordered, move |(label, s)| {
+        let mut s = s?;
+        s.idn = idn;
+        s.wire = wire;
+        let out = librig::lookup_spec(&s, &nameserver)?;
+        Ok(match label {
+            Some(l) => format!("{}\n{}", l, out),
+            None => out,
+        })
+    });
+    if !ok {
+        std::process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Jobs are labelled by their value and finish in the order of their
+    // indices in finish, each held until the one before it is done. Ordered
+    // output does not depend on when results arrive, so there a job lets the
+    // next go itself; unordered, the next is let go once this result is
+    // emitted.
+    fn results(
+        values: Vec<u64>,
+        finish: &[usize],
+        workers: usize,
+        unordered: bool,
+    ) -> Vec<Result<String, String>> {
+        let (gates, waits): (Vec<_>, Vec<_>) = values.iter().map(|_| mpsc::channel()).unzip();
+        let mut after = vec![None; values.len()];
+        for w in finish.windows(2) {
+            after[w[0]] = Some(gates[w[1]].clone());
+        }
+        gates[finish[0]].send(()).unwrap();
+        let jobs: Vec<_> = values.into_iter().zip(waits).zip(after).collect();
+
+        let (mut out, mut emitted) = (Vec::new(), 0);
+        each_result(
+            jobs,
+            workers,
+            unordered,
+            move |((v, wait), next): ((u64, mpsc::Receiver<()>), Option<mpsc::Sender<()>>)| {
+                wait.recv().unwrap();
+                if let Some(next) = next.filter(|_| !unordered) {
+                    next.send(()).unwrap();
+                }
+                match v {
+                    0 => Err("zero".to_string()),
+                    1 => panic!("one"),
+                    _ => Ok(v.to_string()),
+                }
+            },
+            |r| {
+                out.push(r);
+                emitted += 1;
+                if let Some(&j) = finish.get(emitted).filter(|_| unordered) {
+                    gates[j].send(()).unwrap();
+                }
+            },
+        );
+        out
+    }
+
+    #[test]
+    fn test_jobs_in_order() {
+        let out = results(vec![10, 20, 30], &[2, 1, 0], 3, false);
+        assert!(out == [Ok("10".into()), Ok("20".into()), Ok("30".into())]);
+
+        let out = results(vec![10, 20, 30], &[0, 1, 2], 1, false);
+        assert!(out == [Ok("10".into()), Ok("20".into()), Ok("30".into())]);
+    }
+
+    #[test]
+    fn test_jobs_unordered() {
+        let out = results(vec![10, 20, 30], &[2, 1, 0], 3, true);
+        assert!(out == [Ok("30".into()), Ok("20".into()), Ok("10".into())]);
+    }
+
+    #[test]
+    fn test_jobs_errors() {
+        // a failed or panicking job takes its turn without holding up the rest
+        let out = results(vec![10, 0, 1, 2], &[1, 2, 3, 0], 2, false);
+        assert!(out[0] == Ok("10".into()));
+        assert!(out[1] == Err("zero".into()));
+        assert!(out[2] == Err("lookup failed unexpectedly".into()));
+        assert!(out[3] == Ok("2".into()));
+
+        assert!(!run_jobs(vec![0], 1, false, |_: u64| Err(
+            "failed".to_string()
+        )));
+        assert!(run_jobs(vec![2], 1, false, |ms: u64| Ok(ms.to_string())));
     }
 }
@@ -0,0 +1,373 @@
+// Secondary zones - RFC 1034 4.3.5, RFC 1996
+//
+// A secondary keeps a copy of a zone from its primary. Every SOA refresh
+// interval it compares serials and, when the primary's is newer, transfers
+// the changes with IXFR, falling back to AXFR. Failed checks are retried
+// every retry interval, and a zone that could not be refreshed for the
+// expire interval is no longer served. A NOTIFY starts a check at once.
+use std::fs;
+use std::net::SocketAddr;
+use std::sync::mpsc::{Receiver, RecvTimeoutError};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::dns::{exchange_udp, Answer, Query, RecordClass, RecordType, Response};
+use crate::rdata::RData;
+use crate::server::{Server, Zone};
+use crate::tsig::{self, Key, Session};
+use crate::xfr::{self, serial_newer, Ixfr};
+use crate::zone::{write_zone, WriteOptions};
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+// how often to try a zone that has never been transferred
+const INITIAL_RETRY: Duration = Duration::from_secs(30);
+
+// refresh, retry and expire from a zone's SOA
+fn timers(zone: &Zone) -> (Duration, Duration, Duration) {
+    match zone.soa().rdata {
+        RData::SOA {
+            refresh,
+            retry,
+            expire,
+            ..
+        } => (
+            Duration::from_secs(refresh as u64),
+            Duration::from_secs(retry as u64),
+            Duration::from_secs(expire as u64),
+        ),
+        _ => (INITIAL_RETRY, INITIAL_RETRY, Duration::MAX),
+    }
+}
+
+// two records are the same record if all but the TTL match
+fn same_record(a: &Answer, b: &Answer) -> bool {
+    a.name.eq_ignore_ascii_case(&b.name)
+        && a.rtype == b.rtype
+        && a.rclass == b.rclass
+        && a.rdata == b.rdata
+}
+
+// the outcome of a refresh check
+pub enum Refresh {
+    // the primary has nothing newer than serial
+    Current(u32),
+    // a new copy of the zone, and the transfer type that produced it
+    Transferred(Zone, RecordType),
+}
+
+pub struct Secondary {
+    origin: String,
+    primary: SocketAddr,
+    key: Option<Key>,
+    file: Option<String>,
+}
+
+impl Secondary {
+    pub fn new(origin: &str, primary: SocketAddr) -> Self {
+        Self {
+            origin: format!("{}.", origin.trim_end_matches('.').to_ascii_lowercase()),
+            primary,
+            key: None,
+            file: None,
+        }
+    }
+
+    // sign SOA queries and transfers with key
+    pub fn set_key(&mut self, key: Key) -> &mut Self {
+        self.key = Some(key);
+        self
+    }
+
+    // keep a copy of the zone in a master file at path
+    pub fn set_file(&mut self, path: &str) -> &mut Self {
+        self.file = Some(path.to_string());
+        self
+    }
+
+    pub fn origin(&self) -> &str {
+        &self.origin
+    }
+
+    pub fn primary(&self) -> SocketAddr {
+        self.primary
+    }
+
+    // the copy saved by an earlier run, if there is a usable one
+    pub fn load_saved(&self) -> Option<Zone> {
+        let path = self.file.as_ref()?;
+        if fs::metadata(path).is_err() {
+            return None;
+        }
+        match Zone::load(&self.origin, path) {
+            Ok(zone) => Some(zone),
+            Err(e) => {
+                eprintln!("; ignoring saved copy of {}: {}", self.origin, e);
+                None
+            }
+        }
+    }
+
+    // write zone to the file, replacing it only once fully written
+    fn save(&self, zone: &Zone) -> Result<(), String> {
+        let path = match &self.file {
+            Some(p) => p,
+            None => return Ok(()),
+        };
+        let records: Vec<Answer> = zone.records().cloned().collect();
+        let opts = WriteOptions {
+            origin: Some(zone.origin().to_string()),
+            ..Default::default()
+        };
+
+        let tmp = format!("{}.tmp", path);
+        fs::write(&tmp, write_zone(&records, &opts))
+            .and_then(|_| fs::rename(&tmp, path))
+            .map_err(|e| format!("failed to save {}: {}", path, e))
+    }
+
+    // the serial the primary is serving
+    fn primary_serial(&self) -> Result<u32, String> {
+        let mut query = Query::new(self.origin.clone(), RecordType::SOA, RecordClass::IN)?;
+        query.header.flags.set_rd(false);
+        query.header.flags.set_ad(false);
+        let mut packet = query.query_serialize()?;
+
+        let mut session = self.key.as_ref().map(Session::new);
+        if let Some(s) = session.as_mut() {
+            s.sign(&mut packet, tsig::now())?;
+        }
+        let bytes = exchange_udp(self.primary, &packet, TIMEOUT)
+            .map_err(|e| format!("SOA query to {} failed: {}", self.primary, e))?;
+        if let Some(s) = session.as_mut() {
+            s.verify(&bytes, tsig::now())?;
+        }
+
+        let resp = Response::from_bytes(bytes)?;
+        if resp.header.flags.rcode() != 0 || !resp.header.flags.aa() {
+            return Err(format!(
+                "{} is not authoritative for {}",
+                self.primary, self.origin
+            ));
+        }
+        resp.answer
+            .iter()
+            .find_map(|rr| match rr.rdata {
+                RData::SOA { serial, .. } => Some(serial),
+                _ => None,
+            })
+            .ok_or(format!("{} sent no SOA for {}", self.primary, self.origin))
+    }
+
+    // apply an incremental transfer to current
+    fn apply(&self, current: &Zone, diffs: &[xfr::IxfrDiff]) -> Result<Zone, String> {
+        let mut records: Vec<Answer> = current.records().cloned().collect();
+        for diff in diffs {
+            if diff.from != serial(&records[0]) {
+                return Err(format!(
+                    "IXFR step from serial {} does not follow {}",
+                    diff.from,
+                    serial(&records[0])
+                ));
+            }
+            // the step's SOA replaces ours whole, timers and all
+            records.remove(0);
+            records.retain(|rr| !diff.removed.iter().any(|d| same_record(rr, d)));
+            records.extend(diff.added.iter().cloned());
+            records.insert(0, diff.soa.clone());
+        }
+        Zone::new(&self.origin, records)
+    }
+
+    // Check the primary for a newer zone than current and transfer it
+    pub fn refresh(&self, current: Option<&Zone>) -> Result<Refresh, String> {
+        let server = self.primary.to_string();
+        let key = self.key.as_ref();
+
+        let current = match current {
+            Some(zone) => zone,
+            None => {
+                let records = xfr::axfr(&self.origin, &server, key)?;
+                let zone = Zone::new(&self.origin, records)?;
+                self.save(&zone)?;
+                return Ok(Refresh::Transferred(zone, RecordType::AXFR));
+            }
+        };
+
+        let serial = current.serial();
+        if !serial_newer(self.primary_serial()?, serial) {
+            return Ok(Refresh::Current(serial));
+        }
+
+        let (zone, rtype) = match xfr::ixfr(&self.origin, &server, serial, key) {
+            Ok(Ixfr::UpToDate(_)) => return Ok(Refresh::Current(serial)),
+            Ok(Ixfr::Full(records)) => (Zone::new(&self.origin, records)?, RecordType::AXFR),
+            Ok(Ixfr::Incremental(diffs)) => match self.apply(current, &diffs) {
+                Ok(zone) => (zone, RecordType::IXFR),
+                Err(e) => {
+                    eprintln!("; {}: {}, trying AXFR", self.origin, e);
+                    let records = xfr::axfr(&self.origin, &server, key)?;
+                    (Zone::new(&self.origin, records)?, RecordType::AXFR)
+                }
+            },
+            // primaries without IXFR support refuse it or answer NOTIMP
+            Err(_) => {
+                let records = xfr::axfr(&self.origin, &server, key)?;
+                (Zone::new(&self.origin, records)?, RecordType::AXFR)
+            }
+        };
+        self.save(&zone)?;
+        Ok(Refresh::Transferred(zone, rtype))
+    }
+
+    // Keep server's copy of the zone up to date until wake is closed. A
+    // message on wake, such as from a NOTIFY, checks the primary at once.
+    pub fn run(self, server: Arc<Server>, wake: Receiver<()>) {
+        let mut zone = self.load_saved();
+        if let Some(z) = &zone {
+            eprintln!("; loaded saved {} serial {}", self.origin, z.serial());
+            server.replace_zone(z.clone());
+        }
+        let mut last_ok = Instant::now();
+
+        loop {
+            let wait = match self.refresh(zone.as_ref()) {
+                Ok(Refresh::Current(_)) => {
+                    last_ok = Instant::now();
+                    timers(zone.as_ref().unwrap()).0
+                }
+                Ok(Refresh::Transferred(z, rtype)) => {
+                    eprintln!(
+                        "; transferred {} serial {} from {} ({})",
+                        self.origin,
+                        z.serial(),
+                        self.primary,
+                        rtype
+                    );
+                    server.replace_zone(z.clone());
+                    last_ok = Instant::now();
+                    let refresh = timers(&z).0;
+                    zone = Some(z);
+                    refresh
+                }
+                Err(e) => {
+                    eprintln!("; refresh of {} failed: {}", self.origin, e);
+                    match &zone {
+                        Some(z) if last_ok.elapsed() >= timers(z).2 => {
+                            eprintln!("; {} expired, no longer serving it", self.origin);
+                            server.remove_zone(&self.origin);
+                            zone = None;
+                            INITIAL_RETRY
+                        }
+                        Some(z) => timers(z).1,
+                        None => INITIAL_RETRY,
+                    }
+                }
+            };
+
+            match wake.recv_timeout(wait) {
+                Ok(()) | Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => return,
+            }
+        }
+    }
+}
+
+fn serial(rr: &Answer) -> u32 {
+    match rr.rdata {
+        RData::SOA { serial, .. } => serial,
+        _ => 0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::xfr::tests::{a, soa, stand_in};
+    use std::net::UdpSocket;
+    use std::thread;
+
+    const ZONE: &str = "example.com.";
+
+    // answer SOA queries over UDP on the stand-in's port with serial
+    fn soa_responder(addr: &str, serial: u32) {
+        let socket = UdpSocket::bind(addr).unwrap();
+        let server = Server::new(vec![Zone::new(ZONE, vec![soa(ZONE, serial)]).unwrap()]);
+        thread::spawn(move || {
+            let mut buf = [0; 512];
+            while let Ok((size, peer)) = socket.recv_from(&mut buf) {
+                let resp = server.handle(&buf[..size], 512).unwrap();
+                socket.send_to(&resp, peer).unwrap();
+            }
+        });
+    }
+
+    fn names(zone: &Zone) -> Vec<String> {
+        zone.records().map(|r| r.to_string()).collect()
+    }
+
+    #[test]
+    fn test_initial_transfer_saved() {
+        let www = a("www.example.com.", [192, 0, 2, 1]);
+        let addr = stand_in(vec![vec![soa(ZONE, 1), www.clone(), soa(ZONE, 1)]], 0);
+        let path = std::env::temp_dir().join(format!("rig-secondary-{}.zone", std::process::id()));
+        let path = path.to_str().unwrap();
+
+        let mut secondary = Secondary::new(ZONE, addr.parse().unwrap());
+        secondary.set_file(path);
+        assert!(secondary.load_saved().is_none());
+
+        let zone = match secondary.refresh(None).unwrap() {
+            Refresh::Transferred(z, RecordType::AXFR) => z,
+            _ => panic!("expected an AXFR"),
+        };
+        assert!(zone.serial() == 1);
+
+        let saved = secondary.load_saved().unwrap();
+        fs::remove_file(path).unwrap();
+        assert!(names(&saved) == names(&zone));
+    }
+
+    #[test]
+    fn test_incremental_refresh() {
+        let www1 = a("www.example.com.", [192, 0, 2, 1]);
+        let www2 = a("WWW.example.com.", [192, 0, 2, 2]);
+        let current = Zone::new(ZONE, vec![soa(ZONE, 1), www1.clone()]).unwrap();
+        // the new SOA changes more than the serial
+        let mut soa2 = soa(ZONE, 2);
+        if let RData::SOA { refresh, .. } = &mut soa2.rdata {
+            *refresh = 7200;
+        }
+
+        let addr = stand_in(
+            vec![vec![
+                soa2.clone(),
+                soa(ZONE, 1),
+                www1,
+                soa2.clone(),
+                www2,
+                soa2,
+            ]],
+            0,
+        );
+        soa_responder(&addr, 2);
+        let secondary = Secondary::new(ZONE, addr.parse().unwrap());
+
+        let zone = match secondary.refresh(Some(&current)).unwrap() {
+            Refresh::Transferred(z, RecordType::IXFR) => z,
+            _ => panic!("expected an IXFR"),
+        };
+        assert!(zone.serial() == 2);
+        assert!(timers(&zone).0 == Duration::from_secs(7200));
+        assert!(zone.records().count() == 2);
+        assert!(zone
+            .records()
+            .any(|r| r.rdata == RData::A([192, 0, 2, 2].into())));
+
+        // nothing newer: no transfer is attempted
+        let secondary = Secondary::new(ZONE, addr.parse().unwrap());
+        assert!(matches!(
+            secondary.refresh(Some(&zone)).unwrap(),
+            Refresh::Current(2)
+        ));
+    }
+}
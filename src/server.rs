@@ -0,0 +1,861 @@
+// Authoritative name server for zones loaded from master files
+//
+// Answers follow the lookup algorithm of RFC 1034 4.3.2 within each zone:
+// delegations become referrals with glue, CNAMEs are chased inside the
+// zone, wildcards are synthesised per RFC 4592 and negative answers carry
+// the zone's SOA per RFC 2308. Queries are served over UDP and TCP, zone
+// transfers over TCP only, and NOTIFY messages (RFC 1996) for served zones
+// are acknowledged.
+use std::collections::BTreeMap;
+use std::io;
+use std::net::{TcpListener, TcpStream, UdpSocket};
+use std::sync::{Arc, RwLock};
+use std::thread;
+use std::time::Duration;
+
+use crate::dns::{
+    read_name, read_tcp_message, write_tcp_message, Answer, Header, Question, RecordClass,
+    RecordType, Response,
+};
+use crate::message::{Message, MessageRef};
+use crate::rdata::RData;
+use crate::zone::{parse_labels, parse_zone_file};
+
+const UDP_SIZE: usize = 512;
+const TCP_IDLE: Duration = Duration::from_secs(10);
+const MAX_CHAIN: usize = 16;
+// room left for records in each zone transfer message
+const XFR_SIZE: usize = 16384;
+const OPCODE_NOTIFY: u8 = 4;
+
+const FORMERR: u8 = 1;
+const SERVFAIL: u8 = 2;
+const NXDOMAIN: u8 = 3;
+const NOTIMP: u8 = 4;
+const REFUSED: u8 = 5;
+const NOTAUTH: u8 = 9;
+
+// lowercased labels, root first, so a BTreeMap keeps each name next to
+// the names below it
+type Key = Vec<Vec<u8>>;
+
+fn key(name: &str) -> Key {
+    let mut labels = parse_labels(name).map(|(l, _)| l).unwrap_or_default();
+    labels.reverse();
+    for l in labels.iter_mut() {
+        l.make_ascii_lowercase();
+    }
+    labels
+}
+
+#[derive(Clone)]
+pub struct Zone {
+    origin: String,
+    source: Option<String>,
+    apex: Key,
+    rclass: RecordClass,
+    nodes: BTreeMap<Key, Vec<Answer>>,
+}
+
+impl Zone {
+    // Build a zone from its records, which must include exactly one SOA at
+    // origin and nothing outside it
+    pub fn new(origin: &str, records: Vec<Answer>) -> Result<Self, String> {
+        let apex = key(origin);
+        let mut nodes: BTreeMap<Key, Vec<Answer>> = BTreeMap::new();
+
+        for rr in records {
+            let k = key(&rr.name);
+            if !k.starts_with(&apex) {
+                return Err(format!("{} is outside zone {}", rr.name, origin));
+            }
+            nodes.entry(k).or_default().push(rr);
+        }
+
+        let soas: Vec<&Answer> = nodes
+            .get(&apex)
+            .into_iter()
+            .flatten()
+            .filter(|r| r.rtype == RecordType::SOA)
+            .collect();
+        if soas.len() != 1 {
+            return Err(format!("zone {} needs exactly one SOA at its apex", origin));
+        }
+        let rclass = soas[0].rclass;
+
+        Ok(Self {
+            origin: soas[0].name.clone(),
+            source: None,
+            apex,
+            rclass,
+            nodes,
+        })
+    }
+
+    // load origin from a master file
+    pub fn load(origin: &str, path: &str) -> Result<Self, String> {
+        let records = parse_zone_file(path, origin).map_err(|e| e.to_string())?;
+        let mut zone = Self::new(origin, records)?;
+        zone.source = Some(path.to_string());
+        Ok(zone)
+    }
+
+    pub fn origin(&self) -> &str {
+        &self.origin
+    }
+
+    // the master file the zone was loaded from, if any
+    pub fn source(&self) -> Option<&str> {
+        self.source.as_deref()
+    }
+
+    pub fn serial(&self) -> u32 {
+        match self.soa().rdata {
+            RData::SOA { serial, .. } => serial,
+            _ => 0,
+        }
+    }
+
+    // every record, starting with the SOA
+    pub fn records(&self) -> impl Iterator<Item = &Answer> {
+        let soa = self.soa();
+        std::iter::once(soa).chain(
+            self.nodes
+                .values()
+                .flatten()
+                .filter(move |r| !std::ptr::eq(*r, soa)),
+        )
+    }
+
+    pub fn soa(&self) -> &Answer {
+        self.nodes[&self.apex]
+            .iter()
+            .find(|r| r.rtype == RecordType::SOA)
+            .expect("zone has an SOA")
+    }
+
+    // the SOA for a negative answer, with the TTL capped by its minimum
+    fn negative_soa(&self) -> Answer {
+        let mut soa = self.soa().clone();
+        if let RData::SOA { minimum, .. } = soa.rdata {
+            soa.ttl = soa.ttl.min(minimum);
+        }
+        soa
+    }
+
+    // a name exists if it owns records or has names below it
+    fn exists(&self, k: &Key) -> bool {
+        self.nodes
+            .range(k.clone()..)
+            .next()
+            .is_some_and(|(n, _)| n.starts_with(k))
+    }
+
+    fn node(&self, k: &Key) -> &[Answer] {
+        self.nodes.get(k).map(|v| v.as_slice()).unwrap_or(&[])
+    }
+
+    // the highest zone cut between the apex and k, if any. The parent side
+    // answers DS queries for the cut itself.
+    fn cut(&self, k: &Key, rtype: RecordType) -> Option<&[Answer]> {
+        for depth in self.apex.len() + 1..=k.len() {
+            if depth == k.len() && rtype == RecordType::DS {
+                break;
+            }
+            let node = self.node(&k[..depth].to_vec());
+            if node.iter().any(|r| r.rtype == RecordType::NS) {
+                return Some(node);
+            }
+        }
+        None
+    }
+
+    // the records to answer k from: its own, or those of the wildcard at
+    // its closest encloser. None if the name does not exist.
+    fn lookup(&self, k: &Key) -> Option<(&[Answer], bool)> {
+        if self.exists(k) {
+            return Some((self.node(k), false));
+        }
+
+        let encloser = (self.apex.len()..k.len())
+            .rev()
+            .map(|depth| k[..depth].to_vec())
+            .find(|e| self.exists(e))?;
+        let mut wild = encloser;
+        wild.push(b"*".to_vec());
+
+        self.nodes.get(&wild).map(|n| (n.as_slice(), true))
+    }
+}
+
+#[derive(Default)]
+struct Lookup {
+    rcode: u8,
+    aa: bool,
+    answer: Vec<Answer>,
+    authority: Vec<Answer>,
+    additional: Vec<Answer>,
+}
+
+fn failure(rcode: u8) -> Lookup {
+    Lookup {
+        rcode,
+        ..Default::default()
+    }
+}
+
+fn target(rr: &Answer) -> Option<&str> {
+    match &rr.rdata {
+        RData::NS(name) => Some(name),
+        RData::MX { exchange, .. } => Some(exchange),
+        RData::SRV { target, .. } => Some(target),
+        _ => None,
+    }
+}
+
+fn class_matches(rr: &Answer, rclass: RecordClass) -> bool {
+    rclass == RecordClass::ANY || rr.rclass == rclass
+}
+
+// the most specific zone containing k
+fn zone_for<'z>(zones: &'z [Zone], k: &Key, rclass: RecordClass) -> Option<&'z Zone> {
+    zones
+        .iter()
+        .filter(|z| k.starts_with(&z.apex))
+        .filter(|z| rclass == RecordClass::ANY || z.rclass == rclass)
+        .max_by_key(|z| z.apex.len())
+}
+
+// addresses for the names in NS, MX and SRV records - RFC 1034 4.3.2
+// step 6; glue below a zone cut is included for referrals
+fn add_additional(zones: &[Zone], lookup: &mut Lookup, rclass: RecordClass) {
+    let names: Vec<String> = lookup
+        .answer
+        .iter()
+        .chain(&lookup.authority)
+        .filter_map(target)
+        .map(|n| n.to_string())
+        .collect();
+
+    for name in names {
+        let k = key(&name);
+        let zone = match zone_for(zones, &k, rclass) {
+            Some(z) => z,
+            None => continue,
+        };
+        for rr in zone.node(&k) {
+            let address = rr.rtype == RecordType::A || rr.rtype == RecordType::AAAA;
+            if address
+                && class_matches(rr, rclass)
+                && !lookup.answer.contains(rr)
+                && !lookup.additional.contains(rr)
+            {
+                lookup.additional.push(rr.clone());
+            }
+        }
+    }
+}
+
+// called with a zone's origin and the serial a NOTIFY carried, if any;
+// false leaves the NOTIFY to the server
+type NotifyHandler = Box<dyn Fn(&str, Option<u32>) -> bool + Send + Sync>;
+
+pub struct Server {
+    zones: RwLock<Vec<Zone>>,
+    notify: Option<NotifyHandler>,
+}
+
+impl Server {
+    pub fn new(zones: Vec<Zone>) -> Self {
+        Self {
+            zones: RwLock::new(zones),
+            notify: None,
+        }
+    }
+
+    // Run f when a NOTIFY arrives for a served zone. Zones f does not
+    // handle are reloaded if they came from a master file.
+    pub fn on_notify(&mut self, f: impl Fn(&str, Option<u32>) -> bool + Send + Sync + 'static) {
+        self.notify = Some(Box::new(f));
+    }
+
+    pub fn origins(&self) -> Vec<String> {
+        let zones = self.zones.read().unwrap();
+        zones.iter().map(|z| z.origin.clone()).collect()
+    }
+
+    pub fn serial(&self, origin: &str) -> Option<u32> {
+        let zones = self.zones.read().unwrap();
+        zones
+            .iter()
+            .find(|z| z.apex == key(origin))
+            .map(|z| z.serial())
+    }
+
+    // serve zone in place of any zone with the same origin
+    pub fn replace_zone(&self, zone: Zone) {
+        let mut zones = self.zones.write().unwrap();
+        zones.retain(|z| z.apex != zone.apex);
+        zones.push(zone);
+    }
+
+    pub fn remove_zone(&self, origin: &str) {
+        let mut zones = self.zones.write().unwrap();
+        zones.retain(|z| z.apex != key(origin));
+    }
+
+    fn resolve(&self, qname: &str, rtype: RecordType, rclass: RecordClass) -> Lookup {
+        let zones = self.zones.read().unwrap();
+        let mut lookup = Lookup::default();
+        let mut k = key(qname);
+
+        let zone = match zone_for(&zones, &k, rclass) {
+            Some(z) => z,
+            None => {
+                lookup.rcode = REFUSED;
+                return lookup;
+            }
+        };
+        lookup.aa = true;
+
+        let mut owner = qname.to_string();
+        for _ in 0..MAX_CHAIN {
+            if let Some(ns) = zone.cut(&k, rtype) {
+                // referral; only the part of the chain so far is ours
+                lookup.aa = !lookup.answer.is_empty();
+                lookup.authority.extend(
+                    ns.iter()
+                        .filter(|r| r.rtype == RecordType::NS && class_matches(r, rclass))
+                        .cloned(),
+                );
+                break;
+            }
+
+            let (node, wildcard) = match zone.lookup(&k) {
+                Some(n) => n,
+                None => {
+                    lookup.rcode = NXDOMAIN;
+                    lookup.authority.push(zone.negative_soa());
+                    break;
+                }
+            };
+
+            let synthesise = |rr: &Answer| {
+                let mut rr = rr.clone();
+                if wildcard {
+                    rr.name = owner.clone();
+                }
+                rr
+            };
+
+            let matching: Vec<Answer> = node
+                .iter()
+                .filter(|r| class_matches(r, rclass))
+                .filter(|r| rtype == RecordType::ANY || r.rtype == rtype)
+                .map(synthesise)
+                .collect();
+            if !matching.is_empty() {
+                lookup.answer.extend(matching);
+                break;
+            }
+
+            let cname = node
+                .iter()
+                .find(|r| r.rtype == RecordType::CNAME && class_matches(r, rclass));
+            match cname.map(|r| (synthesise(r), &r.rdata)) {
+                Some((rr, RData::CNAME(next))) => {
+                    let next = next.clone();
+                    lookup.answer.push(rr);
+                    k = key(&next);
+                    owner = next;
+                    let looped = lookup.answer.iter().any(|r| key(&r.name) == k);
+                    // the rest of the chain is someone else's to answer
+                    if looped || !k.starts_with(&zone.apex) {
+                        break;
+                    }
+                }
+                _ => {
+                    // NODATA
+                    lookup.authority.push(zone.negative_soa());
+                    break;
+                }
+            }
+        }
+
+        add_additional(&zones, &mut lookup, rclass);
+        lookup
+    }
+
+    // Acknowledge a NOTIFY for a zone we serve and hand it on - RFC 1996
+    fn notified(&self, origin: &str, rclass: RecordClass, msg: &[u8]) -> Lookup {
+        let zone = {
+            let zones = self.zones.read().unwrap();
+            zones
+                .iter()
+                .find(|z| z.apex == key(origin) && class_matches(z.soa(), rclass))
+                .map(|z| (z.origin.clone(), z.source.clone(), z.serial()))
+        };
+        let (origin, source, serial) = match zone {
+            Some(z) => z,
+            None => return failure(NOTAUTH),
+        };
+
+        // the SOA in the answer section is only a hint
+        let hint = Response::from_bytes(msg.to_vec()).ok().and_then(|r| {
+            r.answer.iter().find_map(|rr| match rr.rdata {
+                RData::SOA { serial, .. } => Some(serial),
+                _ => None,
+            })
+        });
+
+        let handled = self.notify.as_ref().is_some_and(|f| f(&origin, hint));
+        match source {
+            _ if handled => {}
+            Some(path) => match Zone::load(&origin, &path) {
+                Ok(zone) if zone.serial() != serial => {
+                    eprintln!(
+                        "; reloaded {} from {}, serial {}",
+                        origin,
+                        path,
+                        zone.serial()
+                    );
+                    self.replace_zone(zone);
+                }
+                Ok(_) => eprintln!("; {} unchanged at serial {}", origin, serial),
+                Err(e) => eprintln!("; failed to reload {}: {}", origin, e),
+            },
+            None => {}
+        }
+
+        Lookup {
+            aa: true,
+            ..Default::default()
+        }
+    }
+
+    // Answer one query message, limiting the response to max_len bytes.
+    // None for messages that should be ignored, such as responses.
+    pub fn handle(&self, msg: &[u8], max_len: usize) -> Option<Vec<u8>> {
+        let query = Header::from_bytes(msg).ok()?;
+        if query.flags.qr() {
+            return None;
+        }
+
+        let parsed = Question::from_bytes(msg, 12)
+            .and_then(|(q, _)| read_name(msg, 12).map(|(name, _)| (q, name)));
+        let (question, qname) = match parsed {
+            Ok(q) if query.qd_count == 1 => q,
+            _ => return Some(reply(&query, None, &failure(FORMERR), max_len)),
+        };
+
+        // a UDP client may advertise a larger buffer with EDNS
+        let max_len = MessageRef::parse(msg)
+            .ok()
+            .and_then(|m| m.additional().find(|r| r.rtype == RecordType::OPT))
+            .map_or(max_len, |opt| max_len.max(opt.rclass.to_u16() as usize));
+
+        let lookup = match (query.flags.opcode(), question.rtype) {
+            (OPCODE_NOTIFY, RecordType::SOA) => self.notified(&qname, question.rclass, msg),
+            (OPCODE_NOTIFY, _) => failure(FORMERR),
+            (0, _) => self.lookup(&qname, question.rtype, question.rclass),
+            _ => failure(NOTIMP),
+        };
+
+        Some(reply(&query, Some(&question), &lookup, max_len))
+    }
+
+    fn lookup(&self, qname: &str, rtype: RecordType, rclass: RecordClass) -> Lookup {
+        match rtype {
+            RecordType::AXFR | RecordType::IXFR | RecordType::OPT => failure(REFUSED),
+            _ => self.resolve(qname, rtype, rclass),
+        }
+    }
+
+    // Serve queries on already bound sockets: TCP connections each get a
+    // thread while UDP is answered on the calling thread
+    pub fn serve(self: Arc<Self>, udp: UdpSocket, tcp: TcpListener) -> io::Result<()> {
+        let server = self.clone();
+        thread::spawn(move || {
+            for stream in tcp.incoming().flatten() {
+                let server = server.clone();
+                thread::spawn(move || server.serve_tcp(stream));
+            }
+        });
+
+        let mut buf = [0; 65535];
+        loop {
+            let (size, peer) = udp.recv_from(&mut buf)?;
+            if let Some(resp) = self.handle(&buf[..size], UDP_SIZE) {
+                let _ = udp.send_to(&resp, peer);
+            }
+        }
+    }
+
+    fn serve_tcp(&self, mut stream: TcpStream) {
+        let _ = stream.set_read_timeout(Some(TCP_IDLE));
+        while let Ok(Some(msg)) = read_tcp_message(&mut stream) {
+            let responses = match self.transfer(&msg) {
+                Some(messages) => messages,
+                None => self.handle(&msg, u16::MAX as usize).into_iter().collect(),
+            };
+            for resp in responses {
+                if write_tcp_message(&mut stream, &resp).is_err() {
+                    return;
+                }
+            }
+        }
+    }
+
+    // Answer an AXFR or IXFR query with the whole zone, or with just the
+    // SOA when an IXFR client is up to date - RFC 5936, RFC 1995 4. None
+    // for other messages.
+    fn transfer(&self, msg: &[u8]) -> Option<Vec<Vec<u8>>> {
+        let query = Header::from_bytes(msg).ok()?;
+        let (question, _) = Question::from_bytes(msg, 12).ok()?;
+        let ixfr = match question.rtype {
+            RecordType::AXFR => false,
+            RecordType::IXFR => true,
+            _ => return None,
+        };
+        if query.flags.qr() || query.flags.opcode() != 0 || query.qd_count != 1 {
+            return None;
+        }
+
+        let (qname, _) = read_name(msg, 12).ok()?;
+        let zones = self.zones.read().unwrap();
+        let zone = match zones.iter().find(|z| z.apex == key(&qname)) {
+            Some(z) => z,
+            None => {
+                return Some(vec![reply(
+                    &query,
+                    Some(&question),
+                    &failure(NOTAUTH),
+                    XFR_SIZE,
+                )])
+            }
+        };
+
+        // the client's serial is in the SOA in the authority section
+        let client = Response::from_bytes(msg.to_vec()).ok().and_then(|r| {
+            r.authority.iter().find_map(|rr| match rr.rdata {
+                RData::SOA { serial, .. } => Some(serial),
+                _ => None,
+            })
+        });
+        let current = ixfr && client.is_some_and(|s| s.wrapping_sub(zone.serial()) as i32 >= 0);
+
+        let mut records: Vec<&Answer> = vec![zone.soa()];
+        if !current {
+            records.extend(zone.records().skip(1));
+            records.push(zone.soa());
+        }
+
+        let mut messages = Vec::new();
+        let mut lookup = Lookup {
+            aa: true,
+            ..Default::default()
+        };
+        let mut size = 0;
+        for rr in records {
+            // a record that cannot be encoded fails the message holding it
+            let len = rr.to_bytes().map_or(0, |b| b.len());
+            if size + len > XFR_SIZE && !lookup.answer.is_empty() {
+                messages.push(reply(&query, Some(&question), &lookup, u16::MAX as usize));
+                lookup.answer.clear();
+                size = 0;
+            }
+            lookup.answer.push(rr.clone());
+            size += len;
+        }
+        messages.push(reply(&query, Some(&question), &lookup, u16::MAX as usize));
+        Some(messages)
+    }
+
+    // bind UDP and TCP on addr (<IP:port>) and serve until an error
+    pub fn listen(self: Arc<Self>, addr: &str) -> io::Result<()> {
+        let udp = UdpSocket::bind(addr)?;
+        let tcp = TcpListener::bind(udp.local_addr()?)?;
+        self.serve(udp, tcp)
+    }
+}
+
+// Build a response to query. Additional records are dropped first if it
+// does not fit, then everything but the question with TC set.
+fn reply(query: &Header, question: Option<&Question>, lookup: &Lookup, max_len: usize) -> Vec<u8> {
+    let empty: &[Answer] = &[];
+    let attempts = [
+        (
+            false,
+            [&lookup.answer[..], &lookup.authority, &lookup.additional],
+        ),
+        (false, [&lookup.answer[..], &lookup.authority, empty]),
+        (true, [empty, empty, empty]),
+    ];
+
+    let mut msg = Vec::new();
+    for (truncated, sections) in attempts {
+        let mut resp = Message::new();
+        resp.set_id(query.id)
+            .set_qr(true)
+            .set_opcode(query.flags.opcode())
+            .set_rd(query.flags.rd())
+            .set_aa(lookup.aa)
+            .set_tc(truncated)
+            .set_rcode(lookup.rcode);
+        resp.questions.extend(question.cloned());
+        resp.answer = sections[0].to_vec();
+        resp.authority = sections[1].to_vec();
+        resp.additional = sections[2].to_vec();
+
+        msg = match resp.to_bytes() {
+            Ok(m) => m,
+            // records whose names cannot be encoded
+            Err(_) => return reply(query, question, &failure(SERVFAIL), max_len),
+        };
+        if msg.len() <= max_len {
+            break;
+        }
+    }
+    msg
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dns::{Query, Response};
+    use crate::xfr;
+    use crate::zone::parse_zone;
+
+    const ZONE: &str = "$ORIGIN example.com.
+$TTL 3600
+@        IN SOA   ns1 hostmaster 1 3600 600 86400 300
+@        IN NS    ns1
+@        IN MX    10 mail
+ns1      IN A     192.0.2.1
+mail     IN A     192.0.2.25
+www      IN CNAME web
+web      IN A     192.0.2.80
+ext      IN CNAME www.example.net.
+loop     IN CNAME loop
+*.dyn    IN A     192.0.2.99
+*.dyn    IN TXT   \"wild\"
+a.b      IN A     192.0.2.2
+sub      IN NS    ns.sub
+ns.sub   IN A     192.0.2.53
+";
+
+    fn server_zone() -> Zone {
+        let records = parse_zone(ZONE, "example.com.").unwrap();
+        Zone::new("example.com.", records).unwrap()
+    }
+
+    fn server() -> Server {
+        Server::new(vec![server_zone()])
+    }
+
+    fn ask(server: &Server, name: &str, rtype: RecordType) -> Response {
+        let mut query = Query::new(name.to_string(), rtype, RecordClass::IN).unwrap();
+        let resp = server
+            .handle(&query.query_serialize().unwrap(), UDP_SIZE)
+            .unwrap();
+        Response::from_bytes(resp).unwrap()
+    }
+
+    fn names(records: &[Answer]) -> Vec<String> {
+        records
+            .iter()
+            .map(|r| format!("{} {}", r.name, r.rtype))
+            .collect()
+    }
+
+    #[test]
+    fn test_answer_and_additional() {
+        let resp = ask(&server(), "example.com", RecordType::MX);
+
+        assert!(resp.header.flags.aa() && resp.header.flags.qr());
+        assert!(resp.header.flags.rcode() == 0);
+        assert!(names(&resp.answer) == ["example.com. MX"]);
+        assert!(resp.additional[0].rdata == RData::A([192, 0, 2, 25].into()));
+    }
+
+    #[test]
+    fn test_nxdomain_and_nodata() {
+        let server = server();
+
+        let resp = ask(&server, "nope.example.com", RecordType::A);
+        assert!(resp.header.flags.rcode() == NXDOMAIN);
+        assert!(resp.answer.is_empty());
+        assert!(names(&resp.authority) == ["example.com. SOA"]);
+        // negative TTL is the SOA minimum
+        assert!(resp.authority[0].ttl == 300);
+
+        let resp = ask(&server, "web.example.com", RecordType::AAAA);
+        assert!(resp.header.flags.rcode() == 0);
+        assert!(resp.answer.is_empty());
+        assert!(names(&resp.authority) == ["example.com. SOA"]);
+
+        // an empty non-terminal exists
+        let resp = ask(&server, "b.example.com", RecordType::A);
+        assert!(resp.header.flags.rcode() == 0);
+    }
+
+    #[test]
+    fn test_cname_chasing() {
+        let server = server();
+
+        // the owner is compressed against the question, so takes its case
+        let resp = ask(&server, "WWW.example.com", RecordType::A);
+        assert!(names(&resp.answer) == ["WWW.example.com. CNAME", "web.example.com. A"]);
+
+        // out of zone targets are left to the client
+        let resp = ask(&server, "ext.example.com", RecordType::A);
+        assert!(names(&resp.answer) == ["ext.example.com. CNAME"]);
+
+        let resp = ask(&server, "loop.example.com", RecordType::A);
+        assert!(resp.answer.len() == 1);
+
+        let resp = ask(&server, "www.example.com", RecordType::CNAME);
+        assert!(names(&resp.answer) == ["www.example.com. CNAME"]);
+    }
+
+    #[test]
+    fn test_wildcard() {
+        let server = server();
+
+        let resp = ask(&server, "host.dyn.example.com", RecordType::A);
+        assert!(names(&resp.answer) == ["host.dyn.example.com. A"]);
+
+        let resp = ask(&server, "host.dyn.example.com", RecordType::MX);
+        assert!(resp.answer.is_empty() && resp.header.flags.rcode() == 0);
+
+        // wildcards do not match below existing names
+        let resp = ask(&server, "x.a.b.example.com", RecordType::A);
+        assert!(resp.header.flags.rcode() == NXDOMAIN);
+    }
+
+    #[test]
+    fn test_delegation() {
+        let resp = ask(&server(), "www.sub.example.com", RecordType::A);
+
+        assert!(!resp.header.flags.aa());
+        assert!(resp.answer.is_empty());
+        assert!(names(&resp.authority) == ["sub.example.com. NS"]);
+        assert!(names(&resp.additional) == ["ns.sub.example.com. A"]);
+    }
+
+    #[test]
+    fn test_refused_and_errors() {
+        let server = server();
+
+        let resp = ask(&server, "example.org", RecordType::A);
+        assert!(resp.header.flags.rcode() == REFUSED && !resp.header.flags.aa());
+
+        let mut query =
+            Query::new("example.com".to_string(), RecordType::A, RecordClass::IN).unwrap();
+        query.header.flags.set_opcode(2);
+        let resp = server
+            .handle(&query.query_serialize().unwrap(), UDP_SIZE)
+            .unwrap();
+        assert!(Response::from_bytes(resp).unwrap().header.flags.rcode() == NOTIMP);
+
+        let mut bad = query.query_serialize().unwrap();
+        bad.truncate(14);
+        let resp = Response::from_bytes(server.handle(&bad, UDP_SIZE).unwrap()).unwrap();
+        assert!(resp.header.flags.rcode() == FORMERR);
+
+        // responses are never answered
+        bad[2] |= 0x80;
+        assert!(server.handle(&bad, UDP_SIZE).is_none());
+    }
+
+    #[test]
+    fn test_truncation() {
+        let mut text = ZONE.to_string();
+        for i in 0..40 {
+            text.push_str(&format!("big IN A 10.0.0.{}\n", i));
+        }
+        let records = parse_zone(&text, "example.com.").unwrap();
+        let server = Server::new(vec![Zone::new("example.com.", records).unwrap()]);
+
+        let mut query = Query::new(
+            "big.example.com".to_string(),
+            RecordType::A,
+            RecordClass::IN,
+        )
+        .unwrap();
+        let packet = query.query_serialize().unwrap();
+
+        let udp = Response::from_bytes(server.handle(&packet, UDP_SIZE).unwrap()).unwrap();
+        assert!(udp.header.flags.tc() && udp.answer.is_empty());
+
+        let tcp = Response::from_bytes(server.handle(&packet, 65535).unwrap()).unwrap();
+        assert!(!tcp.header.flags.tc() && tcp.answer.len() == 40);
+
+        // an EDNS client gets as much as its buffer holds
+        let mut msg = Message::query("big.example.com", RecordType::A, RecordClass::IN).unwrap();
+        msg.add_additional(Answer::new(
+            ".".to_string(),
+            RecordType::OPT,
+            RecordClass::from_u16(1232),
+            0,
+            RData::Unknown(vec![]),
+        ));
+        let edns = server.handle(&msg.to_bytes().unwrap(), UDP_SIZE).unwrap();
+        let edns = Response::from_bytes(edns).unwrap();
+        assert!(!edns.header.flags.tc() && edns.answer.len() == 40);
+    }
+
+    #[test]
+    fn test_serve_udp_and_tcp() {
+        let udp = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = udp.local_addr().unwrap().to_string();
+        let tcp = TcpListener::bind(&addr).unwrap();
+        let server = Arc::new(server());
+        thread::spawn(move || server.serve(udp, tcp));
+
+        let resp = Query::do_query(
+            "web.example.com".to_string(),
+            addr.clone(),
+            RecordType::A,
+            RecordClass::IN,
+        )
+        .unwrap();
+        assert!(resp.answer[0].rdata == RData::A([192, 0, 2, 80].into()));
+
+        let mut query = Query::new(
+            "web.example.com".to_string(),
+            RecordType::A,
+            RecordClass::IN,
+        )
+        .unwrap();
+        let mut stream = query.send_tcp(&addr, None).unwrap();
+        let resp = Response::from_stream(&mut stream).unwrap().unwrap();
+        assert!(resp.header.id == query.header.id);
+        assert!(resp.answer.len() == 1 && resp.header.flags.aa());
+    }
+
+    #[test]
+    fn test_zone_transfer() {
+        let udp = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = udp.local_addr().unwrap().to_string();
+        let tcp = TcpListener::bind(&addr).unwrap();
+        let server = Arc::new(server());
+        thread::spawn(move || server.serve(udp, tcp));
+
+        let records = xfr::axfr("example.com", &addr, None).unwrap();
+        let zone = server_zone();
+        assert!(records.len() == zone.records().count());
+        assert!(records[0].rtype == RecordType::SOA);
+
+        // up to date IXFR clients get the SOA alone, others the whole zone
+        assert!(matches!(
+            xfr::ixfr("example.com", &addr, 1, None).unwrap(),
+            xfr::Ixfr::UpToDate(_)
+        ));
+        assert!(matches!(
+            xfr::ixfr("example.com", &addr, 0, None).unwrap(),
+            xfr::Ixfr::Full(_)
+        ));
+        assert!(xfr::axfr("example.net", &addr, None).is_err());
+    }
+}
@@ -0,0 +1,402 @@
+// dig-like query specs, as used by batch mode
+//
+// A spec is `name [type] [class] [@server] [+option ...]`; everything after
+// the name may appear in any order. Besides output options, +options can
+// craft unusual queries for testing servers: any header bit, the opcode,
+// ID and section counts, extra questions, EDNS options and raw RDATA.
+use std::io::BufRead;
+
+use crate::dns::{opcode_str, rcode_str, Answer, RecordClass, RecordType};
+use crate::message::Message;
+use crate::name::Name;
+use crate::rdata::RData;
+use crate::wire::{decode_input, InputFormat};
+
+const EDNS_SIZE: u16 = 1232;
+
+type SetFlag = fn(&mut Message, bool) -> &mut Message;
+
+// Changes to the query rig would normally send; None keeps the default.
+// Counts replace those in the header without changing the sections.
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct Craft {
+    pub id: Option<u16>,
+    pub opcode: Option<u8>,
+    pub rcode: Option<u8>,
+    pub qr: Option<bool>,
+    pub aa: Option<bool>,
+    pub tc: Option<bool>,
+    pub rd: Option<bool>,
+    pub ra: Option<bool>,
+    pub z: Option<bool>,
+    pub ad: Option<bool>,
+    pub cd: Option<bool>,
+    pub counts: [Option<u16>; 4],
+    pub questions: Vec<(String, RecordType, RecordClass)>,
+    pub edns: Option<u16>, // UDP payload size of an OPT record
+    pub edns_options: Vec<(u16, Vec<u8>)>,
+    pub rdata: Option<Vec<u8>>, // sent as a record for the question
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct QuerySpec {
+    pub name: String,
+    pub rtype: RecordType,
+    pub rclass: RecordClass,
+    pub server: Option<String>,
+    pub short: bool,
+    pub idn: bool,  // show U-labels alongside A-labels in the output
+    pub wire: bool, // show the annotated bytes sent and received
+    pub craft: Craft,
+}
+
+impl QuerySpec {
+    pub fn new(name: String) -> Self {
+        Self {
+            name,
+            rtype: RecordType::A,
+            rclass: RecordClass::IN,
+            server: None,
+            short: false,
+            idn: true,
+            wire: false,
+            craft: Craft::default(),
+        }
+    }
+
+    pub fn parse(line: &str) -> Result<Self, String> {
+        let mut tokens = line.split_ascii_whitespace();
+
+        let name = match tokens.next() {
+            Some(n) if !n.starts_with('@') && !n.starts_with('+') => n,
+            _ => return Err("missing query name".to_string()),
+        };
+        name.parse::<Name>()
+            .map_err(|e| format!("{}: {}", name, e))?;
+        let mut spec = Self::new(name.to_string());
+
+        for t in tokens {
+            if let Some(server) = t.strip_prefix('@') {
+                if server.is_empty() {
+                    return Err("empty server after @".to_string());
+                }
+                spec.server = Some(server.to_string());
+            } else if let Some(opt) = t.strip_prefix('+') {
+                spec.set_option(opt)?;
+            } else if let Ok(rtype) = t.parse::<RecordType>() {
+                spec.rtype = rtype;
+            } else if let Ok(rclass) = t.parse::<RecordClass>() {
+                spec.rclass = rclass;
+            } else {
+                return Err(format!("unknown record type or class {}", t));
+            }
+        }
+
+        Ok(spec)
+    }
+
+    // Apply one +option, given without its +
+    pub fn set_option(&mut self, opt: &str) -> Result<(), String> {
+        let (key, value) = match opt.split_once('=') {
+            Some((k, v)) => (k, Some(v)),
+            None => (opt, None),
+        };
+        let bad = |e: String| format!("+{}: {}", opt, e);
+        let number = |v: Option<&str>| -> Result<u16, String> {
+            let v = v.ok_or(bad("missing value".to_string()))?;
+            v.parse::<u16>().map_err(|e| bad(e.to_string()))
+        };
+        let c = &mut self.craft;
+
+        match (key, value) {
+            ("short", None) => self.short = true,
+            ("noshort", None) => self.short = false,
+            ("id", v) => c.id = Some(number(v)?),
+            ("opcode", Some(v)) => c.opcode = Some(code(v, 15, opcode_str).map_err(bad)?),
+            ("rcode", Some(v)) => c.rcode = Some(code(v, 15, rcode_str).map_err(bad)?),
+            // the three bits after RA, once all Z - RFC 1035 4.1.1, RFC 2535 6.1
+            ("z", Some(_)) => {
+                let z = number(value)?;
+                if z > 7 {
+                    return Err(bad("z is three bits, 0 to 7".to_string()));
+                }
+                (c.z, c.ad, c.cd) = (Some(z & 4 != 0), Some(z & 2 != 0), Some(z & 1 != 0));
+            }
+            ("qdcount", v) => c.counts[0] = Some(number(v)?),
+            ("ancount", v) => c.counts[1] = Some(number(v)?),
+            ("nscount", v) => c.counts[2] = Some(number(v)?),
+            ("arcount", v) => c.counts[3] = Some(number(v)?),
+            ("question", Some(v)) => c.questions.push(question(v).map_err(bad)?),
+            ("edns", None) => c.edns = Some(c.edns.unwrap_or(EDNS_SIZE)),
+            ("noedns", None) => {
+                c.edns = None;
+                c.edns_options.clear();
+            }
+            ("bufsize", v) => c.edns = Some(number(v)?),
+            ("ednsopt", Some(v)) => {
+                let (code, data) = v.split_once(':').unwrap_or((v, ""));
+                let code = code.parse::<u16>().map_err(|e| bad(e.to_string()))?;
+                c.edns_options.push((code, hex(data).map_err(bad)?));
+                c.edns = Some(c.edns.unwrap_or(EDNS_SIZE));
+            }
+            ("rdata", Some(v)) => c.rdata = Some(hex(v).map_err(bad)?),
+            (flag, None) => {
+                // +flag, +flagflag and +noflag, as dig spells them
+                let (on, flag) = match flag.strip_prefix("no") {
+                    Some(f) => (false, f),
+                    None => (true, flag),
+                };
+                let bit = match flag.strip_suffix("flag").unwrap_or(flag) {
+                    "qr" => &mut c.qr,
+                    "aa" => &mut c.aa,
+                    "tc" => &mut c.tc,
+                    "rd" | "rec" | "recurse" => &mut c.rd,
+                    "ra" => &mut c.ra,
+                    "z" => &mut c.z,
+                    "ad" => &mut c.ad,
+                    "cd" => &mut c.cd,
+                    _ => return Err(format!("unknown option +{}", opt)),
+                };
+                *bit = Some(on);
+            }
+            _ => return Err(format!("unknown option +{}", opt)),
+        }
+        Ok(())
+    }
+
+    // true if the spec changes the query rig would normally send
+    pub fn is_crafted(&self) -> bool {
+        self.craft != Craft::default()
+    }
+
+    // The query for this spec: rig's usual one with RD and AD set, changed
+    // as its +options say. Fails if a question name is invalid.
+    pub fn to_bytes(&self) -> Result<Vec<u8>, String> {
+        let c = &self.craft;
+        let mut msg = Message::query(&self.name, self.rtype, self.rclass)?;
+        msg.set_ad(true);
+
+        if let Some(id) = c.id {
+            msg.set_id(id);
+        }
+        if let Some(opcode) = c.opcode {
+            msg.set_opcode(opcode);
+        }
+        if let Some(rcode) = c.rcode {
+            msg.set_rcode(rcode);
+        }
+        let flags: [(Option<bool>, SetFlag); 8] = [
+            (c.qr, Message::set_qr),
+            (c.aa, Message::set_aa),
+            (c.tc, Message::set_tc),
+            (c.rd, Message::set_rd),
+            (c.ra, Message::set_ra),
+            (c.z, Message::set_z),
+            (c.ad, Message::set_ad),
+            (c.cd, Message::set_cd),
+        ];
+        for (value, set) in flags {
+            if let Some(v) = value {
+                set(&mut msg, v);
+            }
+        }
+
+        for (name, rtype, rclass) in &c.questions {
+            msg.add_question(name, *rtype, *rclass)?;
+        }
+        if let Some(rdata) = &c.rdata {
+            msg.add_answer(Answer::new(
+                self.name.clone(),
+                self.rtype,
+                self.rclass,
+                0,
+                RData::Unknown(rdata.clone()),
+            ));
+        }
+        if let Some(size) = c.edns {
+            let mut options = Vec::new();
+            for (code, data) in &c.edns_options {
+                options.extend(code.to_be_bytes());
+                options.extend((data.len() as u16).to_be_bytes());
+                options.extend(data);
+            }
+            msg.add_additional(Answer::new(
+                ".".to_string(),
+                RecordType::OPT,
+                RecordClass::from_u16(size),
+                0,
+                RData::Unknown(options),
+            ));
+        }
+
+        let mut bytes = msg.to_bytes()?;
+        for (i, count) in c.counts.iter().enumerate() {
+            if let Some(n) = count {
+                bytes[4 + i * 2..6 + i * 2].copy_from_slice(&n.to_be_bytes());
+            }
+        }
+        Ok(bytes)
+    }
+}
+
+// an opcode or rcode given by number or by its name in names
+fn code(text: &str, max: u8, names: fn(u8) -> &'static str) -> Result<u8, String> {
+    let code = text
+        .parse::<u8>()
+        .ok()
+        .or_else(|| (0..=max).find(|&c| names(c).eq_ignore_ascii_case(text)))
+        .ok_or(format!("unknown code {}", text))?;
+    if code > max {
+        return Err(format!("{} is more than {}", code, max));
+    }
+    Ok(code)
+}
+
+// NAME[/TYPE[/CLASS]]
+fn question(text: &str) -> Result<(String, RecordType, RecordClass), String> {
+    let mut parts = text.split('/');
+    let name = parts.next().unwrap_or_default();
+    name.parse::<Name>()?;
+    let rtype = match parts.next() {
+        Some(t) => t.parse()?,
+        None => RecordType::A,
+    };
+    let rclass = match parts.next() {
+        Some(c) => c.parse()?,
+        None => RecordClass::IN,
+    };
+    Ok((name.to_string(), rtype, rclass))
+}
+
+fn hex(text: &str) -> Result<Vec<u8>, String> {
+    if text.is_empty() {
+        return Ok(Vec::new());
+    }
+    decode_input(text.as_bytes(), Some(InputFormat::Hex))
+}
+
+// Parse a batch of specs, one per line. Blank lines and lines starting with
+// `;` or `#` are skipped; each remaining line is returned with its 1-based
+// line number so errors can be reported without stopping the batch.
+pub fn parse_batch<R: BufRead>(reader: R) -> Vec<(usize, Result<QuerySpec, String>)> {
+    let mut specs = Vec::new();
+
+    for (i, line) in reader.lines().enumerate() {
+        let line = match line {
+            Ok(l) => l,
+            Err(e) => {
+                specs.push((i + 1, Err(format!("read failed: {}", e))));
+                break;
+            }
+        };
+
+        let line = line.trim();
+        if line.is_empty() || line.starts_with(';') || line.starts_with('#') {
+            continue;
+        }
+
+        specs.push((i + 1, QuerySpec::parse(line)));
+    }
+
+    specs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_spec() {
+        let spec = QuerySpec::parse("example.com MX @1.1.1.1 +short").unwrap();
+        assert!(spec.name == "example.com");
+        assert!(spec.rtype == RecordType::MX);
+        assert!(spec.rclass == RecordClass::IN);
+        assert!(spec.server == Some("1.1.1.1".to_string()));
+        assert!(spec.short);
+
+        let spec = QuerySpec::parse("version.bind CH TXT").unwrap();
+        assert!(spec.rtype == RecordType::TXT);
+        assert!(spec.rclass == RecordClass::CH);
+
+        assert!(QuerySpec::parse("example.com") == Ok(QuerySpec::new("example.com".to_string())));
+        assert!(QuerySpec::parse("example.com BOGUS").is_err());
+        assert!(QuerySpec::parse("example.com +bogus").is_err());
+        assert!(QuerySpec::parse("a..example.com").is_err());
+        assert!(QuerySpec::parse("@1.1.1.1").is_err());
+    }
+
+    #[test]
+    fn test_craft_options() {
+        let spec = QuerySpec::parse("example.com +norec +cdflag +aa +opcode=NOTIFY +id=7").unwrap();
+        assert!(spec.craft.rd == Some(false));
+        assert!(spec.craft.cd == Some(true));
+        assert!(spec.craft.aa == Some(true));
+        assert!(spec.craft.opcode == Some(4));
+        assert!(spec.craft.id == Some(7));
+        assert!(spec.is_crafted());
+        assert!(!QuerySpec::parse("example.com +short").unwrap().is_crafted());
+
+        let spec = QuerySpec::parse("example.com +z=5 +ednsopt=10:0a0b +ednsopt=12").unwrap();
+        assert!(spec.craft.z == Some(true));
+        assert!(spec.craft.ad == Some(false));
+        assert!(spec.craft.cd == Some(true));
+        assert!(spec.craft.edns == Some(1232));
+        assert!(spec.craft.edns_options == vec![(10, vec![10, 11]), (12, vec![])]);
+
+        let spec =
+            QuerySpec::parse("example.com +question=example.net/MX/CH +rdata=c0000201").unwrap();
+        assert!(
+            spec.craft.questions
+                == vec![("example.net".to_string(), RecordType::MX, RecordClass::CH)]
+        );
+        assert!(spec.craft.rdata == Some(vec![192, 0, 2, 1]));
+
+        assert!(QuerySpec::parse("example.com +z=8").is_err());
+        assert!(QuerySpec::parse("example.com +opcode=16").is_err());
+        assert!(QuerySpec::parse("example.com +opcode=BOGUS").is_err());
+        assert!(QuerySpec::parse("example.com +qdcount=x").is_err());
+        assert!(QuerySpec::parse("example.com +rdata=zz").is_err());
+        assert!(QuerySpec::parse("example.com +question=a..b").is_err());
+        assert!(QuerySpec::parse("example.com +nobogus").is_err());
+    }
+
+    #[test]
+    fn test_craft_bytes() {
+        let spec = QuerySpec::parse("example.com +norec +cd +id=4660 +opcode=5").unwrap();
+        let bytes = spec.to_bytes().unwrap();
+        assert!(bytes[..4] == [0x12, 0x34, 0x28, 0x30]);
+        assert!(bytes[4..12] == [0, 1, 0, 0, 0, 0, 0, 0]);
+
+        // counts are written as given, whatever the sections hold
+        let spec = QuerySpec::parse("example.com +qdcount=0 +arcount=3 +bufsize=4096").unwrap();
+        let bytes = spec.to_bytes().unwrap();
+        assert!(bytes[4..12] == [0, 0, 0, 0, 0, 0, 0, 3]);
+        assert!(bytes.ends_with(&[0, 0, 41, 16, 0, 0, 0, 0, 0, 0, 0]));
+
+        let spec = QuerySpec::parse(
+            "example.com +question=example.net/AAAA +ednsopt=8:00010000 +rdata=c0000201",
+        )
+        .unwrap();
+        let msg = Message::from_bytes(&spec.to_bytes().unwrap()).unwrap();
+        assert!(msg.questions.len() == 2);
+        assert!(msg.questions[1].rtype == RecordType::AAAA);
+        assert!(msg.answer.len() == 1);
+        assert!(msg.answer[0].rdata == RData::A([192, 0, 2, 1].into()));
+        let opt = &msg.additional[0];
+        assert!(opt.rtype == RecordType::OPT);
+        assert!(opt.rdata == RData::Unknown(vec![0, 8, 0, 4, 0, 1, 0, 0]));
+    }
+
+    #[test]
+    fn test_parse_batch() {
+        let input =
+            "; audit list\n\nexample.com\n# other comment\nexample.net NOPE\n  example.org NS\n";
+        let specs = parse_batch(input.as_bytes());
+
+        assert!(specs.len() == 3);
+        assert!(specs[0].0 == 3 && specs[0].1.is_ok());
+        assert!(specs[1].0 == 5 && specs[1].1.is_err());
+        assert!(specs[2].0 == 6);
+        assert!(specs[2].1.as_ref().unwrap().rtype == RecordType::NS);
+    }
+}
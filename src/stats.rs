@@ -0,0 +1,292 @@
+// Statistics for repeated lookups
+//
+// rig --count sends the same query again and again, like ping. Each reply
+// is reported as it arrives with its rcode, round trip time and minimum
+// TTL, and with any change in the answer set since the reply before it.
+// The closing summary gives the loss, the spread of round trip times, how
+// often the answers changed and how the TTL behaved: a constant TTL comes
+// from an authoritative server, one counting down from a cache, and a jump
+// back up means the cache refetched the records.
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use crate::dns::{rcode_str, Response};
+
+// Parse a duration such as 100ms, 1.5s, 2m or 250us; a bare number is seconds
+pub fn parse_duration(text: &str) -> Result<Duration, String> {
+    let split = text
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(text.len());
+    let (value, unit) = text.split_at(split);
+    let value = value
+        .parse::<f64>()
+        .map_err(|_| format!("bad duration {}", text))?;
+    let scale = match unit {
+        "us" => 1e-6,
+        "ms" => 1e-3,
+        "" | "s" => 1.0,
+        "m" => 60.0,
+        "h" => 3600.0,
+        _ => return Err(format!("bad duration {}: unknown unit {}", text, unit)),
+    };
+    Duration::try_from_secs_f64(value * scale).map_err(|e| format!("bad duration {}: {}", text, e))
+}
+
+pub(crate) fn millis(d: Duration) -> String {
+    format!("{:.3}ms", d.as_secs_f64() * 1000.0)
+}
+
+// the nearest rank percentile of sorted, which must not be empty
+pub fn percentile(sorted: &[Duration], pct: f64) -> Duration {
+    let rank = (pct / 100.0 * sorted.len() as f64).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
+// Round trip times in summary
+#[derive(Debug, PartialEq, Clone)]
+pub struct Latency {
+    pub min: Duration,
+    pub avg: Duration,
+    pub median: Duration,
+    pub p95: Duration,
+    pub p99: Duration,
+    pub max: Duration,
+}
+
+impl Latency {
+    pub fn new(times: &[Duration]) -> Option<Self> {
+        if times.is_empty() {
+            return None;
+        }
+        let mut sorted = times.to_vec();
+        sorted.sort();
+        Some(Self {
+            min: sorted[0],
+            avg: sorted.iter().sum::<Duration>() / sorted.len() as u32,
+            median: percentile(&sorted, 50.0),
+            p95: percentile(&sorted, 95.0),
+            p99: percentile(&sorted, 99.0),
+            max: sorted[sorted.len() - 1],
+        })
+    }
+}
+
+impl std::fmt::Display for Latency {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let ms = |d: Duration| format!("{:.3}", d.as_secs_f64() * 1000.0);
+        write!(
+            f,
+            "min/avg/median/p95/p99/max = {}/{}/{}/{}/{}/{} ms",
+            ms(self.min),
+            ms(self.avg),
+            ms(self.median),
+            ms(self.p95),
+            ms(self.p99),
+            ms(self.max)
+        )
+    }
+}
+
+// The replies to one repeated query
+#[derive(Debug, Default)]
+pub struct Repeat {
+    sent: usize,
+    times: Vec<Duration>,
+    rcodes: BTreeMap<&'static str, usize>,
+    answers: Option<Vec<String>>,
+    changes: usize,
+    ttls: Vec<u32>, // minimum answer TTL of each reply with answers
+    idn: bool,      // show Unicode forms alongside xn-- names in changes
+}
+
+impl Repeat {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set_idn(&mut self, idn: bool) -> &mut Self {
+        self.idn = idn;
+        self
+    }
+
+    // Record the reply to query seq, or why there was none, returning the
+    // line to show for it
+    pub fn add(&mut self, seq: usize, reply: Result<(Response, Duration), String>) -> String {
+        self.sent += 1;
+        let (response, time) = match reply {
+            Ok(r) => r,
+            Err(e) => return format!("seq={} {}", seq, e),
+        };
+        self.times.push(time);
+
+        let rcode = rcode_str(response.header.flags.rcode());
+        *self.rcodes.entry(rcode).or_default() += 1;
+
+        let mut line = format!("seq={} {} {} answers", seq, rcode, response.answer.len());
+        if let Some(ttl) = response.answer.iter().map(|a| a.ttl).min() {
+            line.push_str(&format!(" ttl={}", ttl));
+            self.ttls.push(ttl);
+        }
+        line.push_str(&format!(" time={}", millis(time)));
+
+        let mut answers: Vec<String> = response
+            .answer
+            .iter()
+            .map(|a| format!("{} {}", a.rtype, a.rdata))
+            .collect();
+        answers.sort();
+        answers.dedup();
+        if let Some(last) = &self.answers {
+            if *last != answers {
+                self.changes += 1;
+                let added = answers.iter().filter(|a| !last.contains(a));
+                let removed = last.iter().filter(|a| !answers.contains(a));
+                let show = |a: &String| match self.idn {
+                    true => crate::show_idn(a),
+                    false => a.clone(),
+                };
+                let diff: Vec<String> = added
+                    .map(|a| format!("+{}", show(a)))
+                    .chain(removed.map(|a| format!("-{}", show(a))))
+                    .collect();
+                line.push_str(&format!(" answers changed: {}", diff.join(", ")));
+            }
+        }
+        self.answers = Some(answers);
+
+        line
+    }
+
+    pub fn summary(&self) -> String {
+        let received = self.times.len();
+        let loss = match self.sent {
+            0 => 0.0,
+            n => (n - received) as f64 * 100.0 / n as f64,
+        };
+        let mut out = vec![format!(
+            "{} queries, {} replies, {:.1}% loss",
+            self.sent, received, loss
+        )];
+
+        if let Some(latency) = Latency::new(&self.times) {
+            out.push(format!("rtt {}", latency));
+        }
+        if !self.rcodes.is_empty() {
+            let rcodes: Vec<String> = self
+                .rcodes
+                .iter()
+                .map(|(r, n)| format!("{} {}", r, n))
+                .collect();
+            out.push(format!("rcodes {}", rcodes.join(", ")));
+        }
+        if received > 0 {
+            out.push(format!("answers changed {} times", self.changes));
+        }
+        if let Some(ttl) = self.ttl_behavior() {
+            out.push(ttl);
+        }
+
+        out.join("\n")
+    }
+
+    fn ttl_behavior(&self) -> Option<String> {
+        let (first, last) = (*self.ttls.first()?, *self.ttls.last()?);
+        let refreshes = self.ttls.windows(2).filter(|w| w[1] > w[0]).count();
+
+        Some(if self.ttls.iter().all(|&t| t == first) {
+            format!("ttl constant at {}", first)
+        } else if refreshes == 0 {
+            format!("ttl counting down from {} to {} (cached)", first, last)
+        } else {
+            format!(
+                "ttl from {} to {}, refreshed {} times (cached)",
+                first, last, refreshes
+            )
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dns::{Answer, RecordClass, RecordType};
+    use crate::message::Message;
+    use crate::rdata::RData;
+
+    fn reply(rcode: u8, answers: &[(u8, u32)]) -> Response {
+        let mut msg = Message::query("example.com", RecordType::A, RecordClass::IN).unwrap();
+        msg.set_qr(true).set_rcode(rcode);
+        for &(last, ttl) in answers {
+            msg.add_answer(Answer::new(
+                "example.com.".to_string(),
+                RecordType::A,
+                RecordClass::IN,
+                ttl,
+                RData::A([192, 0, 2, last].into()),
+            ));
+        }
+        Response::from_bytes(msg.to_bytes().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_parse_duration() {
+        assert!(parse_duration("100ms") == Ok(Duration::from_millis(100)));
+        assert!(parse_duration("1.5s") == Ok(Duration::from_millis(1500)));
+        assert!(parse_duration("2") == Ok(Duration::from_secs(2)));
+        assert!(parse_duration("2m") == Ok(Duration::from_secs(120)));
+        assert!(parse_duration("250us") == Ok(Duration::from_micros(250)));
+        assert!(parse_duration("ms").is_err());
+        assert!(parse_duration("5 days").is_err());
+        assert!(parse_duration("99999999999999999999999h").is_err());
+    }
+
+    #[test]
+    fn test_latency() {
+        let times: Vec<Duration> = (1..=100).rev().map(Duration::from_millis).collect();
+        let l = Latency::new(&times).unwrap();
+        assert!(l.min == Duration::from_millis(1));
+        assert!(l.avg == Duration::from_micros(50500));
+        assert!(l.median == Duration::from_millis(50));
+        assert!(l.p95 == Duration::from_millis(95));
+        assert!(l.p99 == Duration::from_millis(99));
+        assert!(l.max == Duration::from_millis(100));
+        assert!(Latency::new(&[]).is_none());
+
+        let l = Latency::new(&[Duration::from_millis(3)]).unwrap();
+        assert!(
+            l.to_string() == "min/avg/median/p95/p99/max = 3.000/3.000/3.000/3.000/3.000/3.000 ms"
+        );
+    }
+
+    #[test]
+    fn test_repeat() {
+        let ms = Duration::from_millis;
+        let mut r = Repeat::new();
+
+        let line = r.add(1, Ok((reply(0, &[(1, 300), (2, 300)]), ms(4))));
+        assert!(line == "seq=1 NOERROR 2 answers ttl=300 time=4.000ms");
+        let line = r.add(2, Ok((reply(0, &[(2, 299), (1, 299)]), ms(2))));
+        assert!(line == "seq=2 NOERROR 2 answers ttl=299 time=2.000ms");
+        let line = r.add(3, Err("timed out".to_string()));
+        assert!(line == "seq=3 timed out");
+        let line = r.add(4, Ok((reply(0, &[(3, 300), (1, 300)]), ms(6))));
+        assert!(line == "seq=4 NOERROR 2 answers ttl=300 time=6.000ms answers changed: +A 192.0.2.3, -A 192.0.2.2");
+        let line = r.add(5, Ok((reply(2, &[]), ms(8))));
+        assert!(line == "seq=5 SERVFAIL 0 answers time=8.000ms answers changed: -A 192.0.2.1, -A 192.0.2.3");
+
+        let summary = r.summary();
+        assert!(summary.starts_with("5 queries, 4 replies, 20.0% loss\nrtt min/avg/median/p95/p99/max = 2.000/5.000/4.000/8.000/8.000/8.000 ms\n"));
+        assert!(summary.ends_with("rcodes NOERROR 3, SERVFAIL 1\nanswers changed 2 times\nttl from 300 to 300, refreshed 1 times (cached)"));
+
+        let mut r = Repeat::new();
+        r.add(1, Ok((reply(0, &[(1, 60)]), ms(1))));
+        r.add(2, Ok((reply(0, &[(1, 58)]), ms(1))));
+        assert!(r
+            .summary()
+            .ends_with("answers changed 0 times\nttl counting down from 60 to 58 (cached)"));
+
+        let mut r = Repeat::new();
+        r.add(1, Err("timed out".to_string()));
+        assert!(r.summary() == "1 queries, 0 replies, 100.0% loss");
+    }
+}
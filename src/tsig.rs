@@ -0,0 +1,668 @@
+// Transaction signatures - RFC 8945
+//
+// A TSIG record is appended to the additional section of each signed
+// message. Its MAC covers the message as it was before signing plus some
+// of the TSIG fields; responses also cover the MAC of the request, and
+// later messages of a multi-message response the MAC before them.
+use std::fmt;
+use std::fs;
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use byteorder::{BigEndian, ByteOrder};
+use hmac::{Hmac, KeyInit, Mac};
+use md5::Md5;
+use sha2::{Sha256, Sha384, Sha512};
+
+use crate::dns::{encode_name, Answer, Question, RecordClass, RecordType, HDR_SIZE};
+use crate::zone::absolute_name;
+
+const DEFAULT_FUDGE: u16 = 300;
+// a multi-message response may leave at most 99 messages unsigned in a row
+const MAX_UNSIGNED: usize = 99;
+
+// TSIG error values - RFC 8945 3
+pub const BADSIG: u16 = 16;
+pub const BADKEY: u16 = 17;
+pub const BADTIME: u16 = 18;
+pub const BADTRUNC: u16 = 22;
+
+pub fn error_str(error: u16) -> &'static str {
+    match error {
+        0 => "NOERROR",
+        BADSIG => "BADSIG",
+        BADKEY => "BADKEY",
+        BADTIME => "BADTIME",
+        BADTRUNC => "BADTRUNC",
+        _ => "UNKNOWN",
+    }
+}
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum Algorithm {
+    HmacMd5,
+    HmacSha256,
+    HmacSha384,
+    HmacSha512,
+}
+
+impl Algorithm {
+    // the algorithm's name as carried in TSIG records - RFC 8945 6
+    pub fn name(&self) -> &'static str {
+        match self {
+            Algorithm::HmacMd5 => "hmac-md5.sig-alg.reg.int.",
+            Algorithm::HmacSha256 => "hmac-sha256.",
+            Algorithm::HmacSha384 => "hmac-sha384.",
+            Algorithm::HmacSha512 => "hmac-sha512.",
+        }
+    }
+
+    fn mac(&self, secret: &[u8], data: &[u8]) -> Vec<u8> {
+        fn run<M: Mac + KeyInit>(secret: &[u8], data: &[u8]) -> Vec<u8> {
+            let mut mac = <M as KeyInit>::new_from_slice(secret).expect("HMAC takes any key size");
+            mac.update(data);
+            mac.finalize().into_bytes().to_vec()
+        }
+
+        match self {
+            Algorithm::HmacMd5 => run::<Hmac<Md5>>(secret, data),
+            Algorithm::HmacSha256 => run::<Hmac<Sha256>>(secret, data),
+            Algorithm::HmacSha384 => run::<Hmac<Sha384>>(secret, data),
+            Algorithm::HmacSha512 => run::<Hmac<Sha512>>(secret, data),
+        }
+    }
+}
+
+impl fmt::Display for Algorithm {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name().trim_end_matches('.'))
+    }
+}
+
+impl FromStr for Algorithm {
+    type Err = String;
+
+    // accepts the record names, with or without their trailing dot, and
+    // the short names BIND uses
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim_end_matches('.').to_ascii_lowercase().as_str() {
+            "hmac-md5" | "hmac-md5.sig-alg.reg.int" => Ok(Algorithm::HmacMd5),
+            "hmac-sha256" => Ok(Algorithm::HmacSha256),
+            "hmac-sha384" => Ok(Algorithm::HmacSha384),
+            "hmac-sha512" => Ok(Algorithm::HmacSha512),
+            _ => Err(format!("unsupported TSIG algorithm {}", s)),
+        }
+    }
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub struct Key {
+    pub name: String,
+    pub algorithm: Algorithm,
+    secret: Vec<u8>,
+}
+
+impl Key {
+    pub fn new(name: &str, algorithm: Algorithm, secret: Vec<u8>) -> Result<Self, String> {
+        Ok(Self {
+            name: absolute_name(name, ".")?.to_ascii_lowercase(),
+            algorithm,
+            secret,
+        })
+    }
+
+    fn decode_secret(secret: &str) -> Result<Vec<u8>, String> {
+        BASE64
+            .decode(secret.trim())
+            .map_err(|e| format!("key secret is not valid base64: {}", e))
+    }
+
+    // `[algorithm:]name:secret` as given to dig -y; the algorithm defaults
+    // to hmac-sha256
+    pub fn from_spec(spec: &str) -> Result<Self, String> {
+        let parts: Vec<&str> = spec.splitn(3, ':').collect();
+        let (algorithm, name, secret) = match parts[..] {
+            [name, secret] => (Algorithm::HmacSha256, name, secret),
+            [algorithm, name, secret] => (algorithm.parse()?, name, secret),
+            _ => return Err("key should be given as [algorithm:]name:secret".to_string()),
+        };
+        Self::new(name, algorithm, Self::decode_secret(secret)?)
+    }
+
+    // The first `key "name" { algorithm ...; secret "..."; };` statement
+    // in BIND configuration text, as written by tsig-keygen
+    pub fn from_bind(text: &str) -> Result<Self, String> {
+        let tokens = bind_tokens(text);
+        let mut iter = tokens.iter().map(|s| s.as_str());
+
+        iter.by_ref()
+            .find(|t| *t == "key")
+            .ok_or("no key statement found")?;
+        let name = iter.next().ok_or("key statement has no name")?;
+        if iter.next() != Some("{") {
+            return Err(format!("expected {{ after key {}", name));
+        }
+
+        let (mut algorithm, mut secret) = (None, None);
+        loop {
+            match iter.next() {
+                Some("algorithm") => algorithm = Some(iter.next().ok_or("missing algorithm")?),
+                Some("secret") => secret = Some(iter.next().ok_or("missing secret")?),
+                Some("}") | None => break,
+                Some(";") => continue,
+                Some(other) => return Err(format!("unexpected {} in key {}", other, name)),
+            }
+            if iter.next() != Some(";") {
+                return Err(format!("missing ; in key {}", name));
+            }
+        }
+
+        let algorithm = algorithm.ok_or(format!("key {} has no algorithm", name))?;
+        let secret = secret.ok_or(format!("key {} has no secret", name))?;
+        Self::new(name, algorithm.parse()?, Self::decode_secret(secret)?)
+    }
+
+    pub fn load(path: &str) -> Result<Self, String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+        Self::from_bind(&text).map_err(|e| format!("{}: {}", path, e))
+    }
+}
+
+// Split BIND configuration into words, quoted strings and the punctuation
+// { } ;, dropping #, // and /* */ comments
+fn bind_tokens(text: &str) -> Vec<String> {
+    let mut tokens = Vec::new();
+    let mut chars = text.chars().peekable();
+    let mut word = String::new();
+
+    let flush = |word: &mut String, tokens: &mut Vec<String>| {
+        if !word.is_empty() {
+            tokens.push(std::mem::take(word));
+        }
+    };
+
+    while let Some(c) = chars.next() {
+        match c {
+            '#' => {
+                flush(&mut word, &mut tokens);
+                chars.by_ref().find(|&c| c == '\n');
+            }
+            '/' if chars.peek() == Some(&'/') => {
+                flush(&mut word, &mut tokens);
+                chars.by_ref().find(|&c| c == '\n');
+            }
+            '/' if chars.peek() == Some(&'*') => {
+                flush(&mut word, &mut tokens);
+                chars.next();
+                let mut last = ' ';
+                for c in chars.by_ref() {
+                    if last == '*' && c == '/' {
+                        break;
+                    }
+                    last = c;
+                }
+            }
+            '"' => {
+                flush(&mut word, &mut tokens);
+                tokens.push(chars.by_ref().take_while(|&c| c != '"').collect());
+            }
+            '{' | '}' | ';' => {
+                flush(&mut word, &mut tokens);
+                tokens.push(c.to_string());
+            }
+            c if c.is_whitespace() => flush(&mut word, &mut tokens),
+            c => word.push(c),
+        }
+    }
+    flush(&mut word, &mut tokens);
+    tokens
+}
+
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+// The fields of a TSIG record's RDATA - RFC 8945 4.2
+#[derive(PartialEq, Debug, Clone)]
+struct Tsig {
+    algorithm: String,
+    time: u64,
+    fudge: u16,
+    mac: Vec<u8>,
+    original_id: u16,
+    error: u16,
+    other: Vec<u8>,
+}
+
+impl Tsig {
+    fn to_bytes(&self) -> Result<Vec<u8>, String> {
+        let mut bytes = encode_name(self.algorithm.as_str())?;
+        bytes.extend(&self.time.to_be_bytes()[2..]);
+        bytes.extend(self.fudge.to_be_bytes());
+        bytes.extend((self.mac.len() as u16).to_be_bytes());
+        bytes.extend(&self.mac);
+        bytes.extend(self.original_id.to_be_bytes());
+        bytes.extend(self.error.to_be_bytes());
+        bytes.extend((self.other.len() as u16).to_be_bytes());
+        bytes.extend(&self.other);
+        Ok(bytes)
+    }
+
+    fn from_bytes(data: &[u8]) -> Result<Self, String> {
+        let short = || "TSIG record too short".to_string();
+        let (algorithm, mut ptr) = crate::dns::read_name(data, 0)?;
+
+        let fixed = data.get(ptr..ptr + 10).ok_or_else(short)?;
+        let time = BigEndian::read_u48(fixed);
+        let fudge = BigEndian::read_u16(&fixed[6..]);
+        let mac_len = BigEndian::read_u16(&fixed[8..]) as usize;
+        ptr += 10;
+
+        let mac = data.get(ptr..ptr + mac_len).ok_or_else(short)?.to_vec();
+        ptr += mac_len;
+
+        let fixed = data.get(ptr..ptr + 6).ok_or_else(short)?;
+        let original_id = BigEndian::read_u16(fixed);
+        let error = BigEndian::read_u16(&fixed[2..]);
+        let other_len = BigEndian::read_u16(&fixed[4..]) as usize;
+        ptr += 6;
+
+        let other = data.get(ptr..ptr + other_len).ok_or_else(short)?.to_vec();
+
+        Ok(Self {
+            algorithm,
+            time,
+            fudge,
+            mac,
+            original_id,
+            error,
+            other,
+        })
+    }
+
+    // The TSIG fields covered by the MAC - RFC 8945 4.3.3. Later messages
+    // of a multi-message response cover only the timers.
+    fn variables(&self, key: &Key, timers_only: bool) -> Result<Vec<u8>, String> {
+        let mut bytes = Vec::new();
+        if !timers_only {
+            bytes.extend(encode_name(&key.name)?);
+            bytes.extend(RecordClass::ANY.to_u16().to_be_bytes());
+            bytes.extend(0u32.to_be_bytes());
+            bytes.extend(encode_name(&self.algorithm.to_ascii_lowercase())?);
+        }
+        bytes.extend(&self.time.to_be_bytes()[2..]);
+        bytes.extend(self.fudge.to_be_bytes());
+        if !timers_only {
+            bytes.extend(self.error.to_be_bytes());
+            bytes.extend((self.other.len() as u16).to_be_bytes());
+            bytes.extend(&self.other);
+        }
+        Ok(bytes)
+    }
+}
+
+// Offset of the last record in msg, and the record, if it is a TSIG
+fn find_tsig(msg: &[u8]) -> Result<Option<(usize, Answer)>, String> {
+    if msg.len() < HDR_SIZE {
+        return Err("message too short".to_string());
+    }
+    let count = |i: usize| BigEndian::read_u16(&msg[i..]) as usize;
+    let records = count(6) + count(8) + count(10);
+    if count(10) == 0 {
+        return Ok(None);
+    }
+
+    let mut ptr = HDR_SIZE;
+    for _ in 0..count(4) {
+        ptr += Question::from_bytes(msg, ptr)?.1;
+    }
+    for _ in 0..records - 1 {
+        ptr += Answer::from_bytes(msg, ptr)?.1;
+    }
+
+    let (rr, _) = Answer::from_bytes(msg, ptr)?;
+    if rr.rtype != RecordType::TSIG {
+        return Ok(None);
+    }
+    Ok(Some((ptr, rr)))
+}
+
+// The state of one signed exchange: a request and its response messages,
+// from either side. Each message signed or verified continues from the MAC
+// of the one before - RFC 8945 5.3.
+pub struct Session<'a> {
+    key: &'a Key,
+    fudge: u16,
+    prior_mac: Option<Vec<u8>>,
+    messages: usize,
+    unsigned: Vec<u8>,
+    unsigned_count: usize,
+}
+
+impl<'a> Session<'a> {
+    pub fn new(key: &'a Key) -> Self {
+        Self {
+            key,
+            fudge: DEFAULT_FUDGE,
+            prior_mac: None,
+            messages: 0,
+            unsigned: Vec::new(),
+            unsigned_count: 0,
+        }
+    }
+
+    pub fn set_fudge(&mut self, fudge: u16) {
+        self.fudge = fudge;
+    }
+
+    // the bytes covered by the MAC of a message, given the message without
+    // its TSIG and with its original ID
+    fn digest_input(&self, msg: &[u8], tsig: &Tsig) -> Result<Vec<u8>, String> {
+        let mut data = Vec::new();
+        if let Some(prior) = &self.prior_mac {
+            data.extend((prior.len() as u16).to_be_bytes());
+            data.extend(prior);
+        }
+        data.extend(&self.unsigned);
+        data.extend(msg);
+        data.extend(tsig.variables(self.key, self.messages > 1)?);
+        Ok(data)
+    }
+
+    // Append a TSIG record to msg, signed at time (seconds since the epoch).
+    // Fails, leaving msg as it was, if the key name cannot be encoded.
+    pub fn sign(&mut self, msg: &mut Vec<u8>, time: u64) -> Result<(), String> {
+        let mut tsig = Tsig {
+            algorithm: self.key.algorithm.name().to_string(),
+            time,
+            fudge: self.fudge,
+            mac: Vec::new(),
+            original_id: BigEndian::read_u16(msg),
+            error: 0,
+            other: Vec::new(),
+        };
+        tsig.mac = self
+            .key
+            .algorithm
+            .mac(&self.key.secret, &self.digest_input(msg, &tsig)?);
+
+        let rdata = tsig.to_bytes()?;
+        let owner = encode_name(&self.key.name)?;
+        msg.extend(owner);
+        msg.extend(RecordType::TSIG.to_u16().to_be_bytes());
+        msg.extend(RecordClass::ANY.to_u16().to_be_bytes());
+        msg.extend(0u32.to_be_bytes());
+        msg.extend((rdata.len() as u16).to_be_bytes());
+        msg.extend(rdata);
+
+        let ar_count = BigEndian::read_u16(&msg[10..]) + 1;
+        BigEndian::write_u16(&mut msg[10..], ar_count);
+
+        self.advance(tsig.mac);
+        Ok(())
+    }
+
+    // Account for a response message sent without a TSIG; the next signed
+    // message covers it
+    pub fn pass_unsigned(&mut self, msg: &[u8]) {
+        self.unsigned.extend(msg);
+        self.unsigned_count += 1;
+    }
+
+    fn advance(&mut self, mac: Vec<u8>) {
+        self.prior_mac = Some(mac);
+        self.messages += 1;
+        self.unsigned.clear();
+        self.unsigned_count = 0;
+    }
+
+    // Check the TSIG on the next message of the exchange at time now. Later
+    // messages of a multi-message response may be unsigned, in which case
+    // they are covered by the next signed one.
+    pub fn verify(&mut self, msg: &[u8], now: u64) -> Result<(), String> {
+        let (offset, rr) = match find_tsig(msg)? {
+            Some(t) => t,
+            None if self.messages > 1 && self.unsigned_count < MAX_UNSIGNED => {
+                self.pass_unsigned(msg);
+                return Ok(());
+            }
+            None => return Err("message is not signed".to_string()),
+        };
+
+        if !rr.name.eq_ignore_ascii_case(&self.key.name) {
+            return Err(format!("signed with unexpected key {}", rr.name));
+        }
+        let tsig = Tsig::from_bytes(&rr.data)?;
+        if tsig.algorithm.parse::<Algorithm>().ok() != Some(self.key.algorithm) {
+            return Err(format!(
+                "signed with unexpected algorithm {}",
+                tsig.algorithm
+            ));
+        }
+        if tsig.error != 0 {
+            return Err(format!("server reported TSIG {}", error_str(tsig.error)));
+        }
+
+        // the message as it was before signing
+        let mut unsigned = msg[..offset].to_vec();
+        BigEndian::write_u16(&mut unsigned, tsig.original_id);
+        let ar_count = BigEndian::read_u16(&unsigned[10..]) - 1;
+        BigEndian::write_u16(&mut unsigned[10..], ar_count);
+
+        let expected = self
+            .key
+            .algorithm
+            .mac(&self.key.secret, &self.digest_input(&unsigned, &tsig)?);
+        if !constant_time_eq(&expected, &tsig.mac) {
+            return Err("TSIG signature did not verify (BADSIG)".to_string());
+        }
+
+        if now.abs_diff(tsig.time) > tsig.fudge as u64 {
+            return Err(format!(
+                "TSIG time is {}s off, outside the fudge of {}s (BADTIME)",
+                now.abs_diff(tsig.time),
+                tsig.fudge
+            ));
+        }
+
+        self.advance(tsig.mac);
+        Ok(())
+    }
+
+    // a multi-message response must end with a signed message
+    pub fn finish(&self) -> Result<(), String> {
+        if self.unsigned_count > 0 {
+            return Err("last message of the response is not signed".to_string());
+        }
+        Ok(())
+    }
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dns::{Query, Response};
+
+    const SECRET: &str = "c2VjcmV0c2VjcmV0c2VjcmV0c2VjcmV0";
+
+    fn key(algorithm: Algorithm) -> Key {
+        Key::new("transfer-key", algorithm, BASE64.decode(SECRET).unwrap()).unwrap()
+    }
+
+    fn query() -> Vec<u8> {
+        let mut q =
+            Query::new("example.com".to_string(), RecordType::AXFR, RecordClass::IN).unwrap();
+        q.header.id = 0x1234;
+        q.query_serialize().unwrap()
+    }
+
+    // a response to query with one A record in the answer section
+    fn response(id: u16) -> Vec<u8> {
+        crate::xfr::tests::message(
+            &id.to_be_bytes(),
+            &[crate::xfr::tests::a("www.example.com.", [192, 0, 2, 1])],
+            0,
+        )
+    }
+
+    #[test]
+    fn test_key_formats() {
+        let bind = r#"
+            # generated by tsig-keygen
+            key "Transfer-Key." {
+                algorithm hmac-sha512; // the default is sha256
+                /* base64 */ secret "c2VjcmV0c2VjcmV0c2VjcmV0c2VjcmV0";
+            };
+        "#;
+        let k = Key::from_bind(bind).unwrap();
+        assert!(k.name == "transfer-key.");
+        assert!(k.algorithm == Algorithm::HmacSha512);
+        assert!(k.secret == b"secretsecretsecretsecret");
+
+        let k = Key::from_spec("transfer-key:c2VjcmV0").unwrap();
+        assert!(k.algorithm == Algorithm::HmacSha256 && k.secret == b"secret");
+        let k = Key::from_spec("hmac-md5.sig-alg.reg.int:transfer-key:c2VjcmV0").unwrap();
+        assert!(k.algorithm == Algorithm::HmacMd5);
+
+        assert!(Key::from_spec("hmac-sha1:k:c2VjcmV0").is_err());
+        assert!(Key::from_spec("k:not base64!").is_err());
+        assert!(Key::from_bind("key \"k\" { secret \"c2VjcmV0\"; };").is_err());
+    }
+
+    #[test]
+    fn test_known_mac() {
+        // the MAC over a request is an HMAC of the message followed by the
+        // TSIG variables, built by hand here
+        let k = key(Algorithm::HmacSha256);
+        let mut msg = query();
+        let original = msg.clone();
+        Session::new(&k).sign(&mut msg, 0x0102_0304_0506).unwrap();
+
+        let mut data = original;
+        data.extend(b"\x0ctransfer-key\x00");
+        data.extend([0, 255, 0, 0, 0, 0]);
+        data.extend(b"\x0bhmac-sha256\x00");
+        data.extend([1, 2, 3, 4, 5, 6, 1, 44, 0, 0, 0, 0]);
+        let expected = Algorithm::HmacSha256.mac(&k.secret, &data);
+
+        let (_, rr) = find_tsig(&msg).unwrap().unwrap();
+        let tsig = Tsig::from_bytes(&rr.data).unwrap();
+        assert!(tsig.mac == expected && tsig.mac.len() == 32);
+        assert!(tsig.time == 0x0102_0304_0506 && tsig.original_id == 0x1234);
+
+        let resp = Response::from_bytes(msg).unwrap();
+        assert!(resp.header.ar_count == 1 && resp.additional[0].rtype == RecordType::TSIG);
+        assert!(resp.additional[0].rclass == RecordClass::ANY);
+    }
+
+    #[test]
+    fn test_invalid_key_name() {
+        let mut k = key(Algorithm::HmacSha256);
+        k.name = "transfer..key.".to_string();
+        let mut msg = query();
+        assert!(Session::new(&k).sign(&mut msg, 1000).is_err());
+        assert!(msg == query());
+    }
+
+    #[test]
+    fn test_request_response() {
+        for algorithm in [
+            Algorithm::HmacMd5,
+            Algorithm::HmacSha256,
+            Algorithm::HmacSha384,
+            Algorithm::HmacSha512,
+        ] {
+            let k = key(algorithm);
+            let (mut client, mut server) = (Session::new(&k), Session::new(&k));
+
+            let mut req = query();
+            client.sign(&mut req, 1000).unwrap();
+            server.verify(&req, 1000).unwrap();
+
+            let mut resp = response(0x1234);
+            server.sign(&mut resp, 1001).unwrap();
+            client.verify(&resp, 1001).unwrap();
+            client.finish().unwrap();
+        }
+    }
+
+    #[test]
+    fn test_verify_failures() {
+        let k = key(Algorithm::HmacSha256);
+
+        // tampered
+        let mut req = query();
+        Session::new(&k).sign(&mut req, 1000).unwrap();
+        let mut bad = req.clone();
+        bad[14] ^= 1;
+        let err = Session::new(&k).verify(&bad, 1000).unwrap_err();
+        assert!(err.contains("BADSIG"), "{}", err);
+
+        // outside the fudge
+        let err = Session::new(&k).verify(&req, 1000 + 301).unwrap_err();
+        assert!(err.contains("BADTIME"));
+        assert!(Session::new(&k).verify(&req, 1000 - 300).is_ok());
+
+        // another key, and no signature at all
+        let other = Key::new("other-key", Algorithm::HmacSha256, b"x".to_vec()).unwrap();
+        assert!(Session::new(&other)
+            .verify(&req, 1000)
+            .unwrap_err()
+            .contains("unexpected key"));
+        assert!(Session::new(&k)
+            .verify(&query(), 1000)
+            .unwrap_err()
+            .contains("not signed"));
+
+        // a response must continue from the request's MAC
+        let mut server = Session::new(&k);
+        let mut resp = response(0x1234);
+        server.sign(&mut resp, 1000).unwrap();
+        let mut client = Session::new(&k);
+        client.sign(&mut query(), 1000).unwrap();
+        assert!(client.verify(&resp, 1000).is_err());
+    }
+
+    #[test]
+    fn test_multi_message() {
+        let k = key(Algorithm::HmacSha256);
+        let (mut client, mut server) = (Session::new(&k), Session::new(&k));
+
+        let mut req = query();
+        client.sign(&mut req, 1000).unwrap();
+        server.verify(&req, 1000).unwrap();
+
+        // signed, signed, unsigned, signed
+        let mut messages = Vec::new();
+        for i in 0..4 {
+            let mut msg = response(0x1234);
+            if i == 2 {
+                server.pass_unsigned(&msg);
+            } else {
+                server.sign(&mut msg, 1000 + i).unwrap();
+            }
+            messages.push(msg);
+        }
+
+        for msg in &messages[..3] {
+            client.verify(msg, 1002).unwrap();
+        }
+        assert!(client.finish().is_err());
+        client.verify(&messages[3], 1002).unwrap();
+        client.finish().unwrap();
+
+        // the first response message must be signed
+        let mut client = Session::new(&k);
+        client.sign(&mut query(), 1000).unwrap();
+        assert!(client.verify(&response(0x1234), 1000).is_err());
+    }
+}
@@ -0,0 +1,562 @@
+// Dynamic updates - RFC 2136
+//
+// An UPDATE message reuses the sections of a query under new names: the
+// zone section (question), prerequisites (answer) and updates (authority).
+// Prerequisites and deletions are told apart by the classes ANY and NONE
+// and by empty RDATA.
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use crate::dns::{
+    exchange_tcp, exchange_udp, rcode_str, Answer, RecordClass, RecordType, Response,
+};
+use crate::message::Message;
+use crate::rdata::RData;
+use crate::tsig::{self, Key, Session};
+use crate::zone::{absolute_name, parse_ttl, parse_zone};
+
+pub const OPCODE_UPDATE: u8 = 5;
+const UDP_SIZE: usize = 512;
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+// what an UPDATE response code means for the update - RFC 2136 2.2
+pub fn rcode_meaning(rcode: u8) -> &'static str {
+    match rcode {
+        0 => "update applied",
+        1 => "server could not parse the update",
+        2 => "server failed while applying the update",
+        3 => "a name that should be in use is not",
+        4 => "server does not support updates",
+        5 => "server refused the update",
+        6 => "a name that should not be in use is",
+        7 => "an RRset that should not exist does",
+        8 => "an RRset that should exist does not",
+        9 => "server is not authoritative for the zone",
+        10 => "a name in the update is outside the zone",
+        _ => "unknown response code",
+    }
+}
+
+// a record with no RDATA, as used by prerequisites and deletions
+fn empty(name: &str, rtype: RecordType, rclass: RecordClass) -> Answer {
+    Answer::new(
+        name.to_string(),
+        rtype,
+        rclass,
+        0,
+        RData::Unknown(Vec::new()),
+    )
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Update {
+    zone: String,
+    rclass: RecordClass,
+    prerequisites: Vec<Answer>,
+    updates: Vec<Answer>,
+}
+
+impl Update {
+    pub fn new(zone: &str) -> Self {
+        Self {
+            zone: zone.to_string(),
+            rclass: RecordClass::IN,
+            prerequisites: Vec::new(),
+            updates: Vec::new(),
+        }
+    }
+
+    pub fn zone(&self) -> &str {
+        &self.zone
+    }
+
+    pub fn set_class(&mut self, rclass: RecordClass) -> &mut Self {
+        self.rclass = rclass;
+        self
+    }
+
+    pub fn prerequisites(&self) -> &[Answer] {
+        &self.prerequisites
+    }
+
+    pub fn updates(&self) -> &[Answer] {
+        &self.updates
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.prerequisites.is_empty() && self.updates.is_empty()
+    }
+
+    // Prerequisites - RFC 2136 2.4
+
+    // an RRset of rtype exists at name, whatever its records
+    pub fn rrset_exists(&mut self, name: &str, rtype: RecordType) -> &mut Self {
+        self.prerequisites
+            .push(empty(name, rtype, RecordClass::ANY));
+        self
+    }
+
+    // the RRset of rr's name and type exists and holds exactly the records
+    // given by successive calls
+    pub fn rrset_matches(&mut self, mut rr: Answer) -> &mut Self {
+        rr.rclass = self.rclass;
+        rr.ttl = 0;
+        self.prerequisites.push(rr);
+        self
+    }
+
+    pub fn rrset_absent(&mut self, name: &str, rtype: RecordType) -> &mut Self {
+        self.prerequisites
+            .push(empty(name, rtype, RecordClass::NONE));
+        self
+    }
+
+    pub fn name_in_use(&mut self, name: &str) -> &mut Self {
+        self.prerequisites
+            .push(empty(name, RecordType::ANY, RecordClass::ANY));
+        self
+    }
+
+    pub fn name_not_in_use(&mut self, name: &str) -> &mut Self {
+        self.prerequisites
+            .push(empty(name, RecordType::ANY, RecordClass::NONE));
+        self
+    }
+
+    // Updates - RFC 2136 2.5
+
+    pub fn add(&mut self, mut rr: Answer) -> &mut Self {
+        rr.rclass = self.rclass;
+        self.updates.push(rr);
+        self
+    }
+
+    pub fn delete_rrset(&mut self, name: &str, rtype: RecordType) -> &mut Self {
+        self.updates.push(empty(name, rtype, RecordClass::ANY));
+        self
+    }
+
+    // every RRset at name
+    pub fn delete_name(&mut self, name: &str) -> &mut Self {
+        self.updates
+            .push(empty(name, RecordType::ANY, RecordClass::ANY));
+        self
+    }
+
+    // the one record matching rr's name, type and data
+    pub fn delete(&mut self, mut rr: Answer) -> &mut Self {
+        rr.rclass = RecordClass::NONE;
+        rr.ttl = 0;
+        self.updates.push(rr);
+        self
+    }
+
+    // fails if the zone or a record's names are not valid domain names
+    pub fn to_bytes(&self, id: u16) -> Result<Vec<u8>, String> {
+        let mut msg = Message::new();
+        msg.set_id(id).set_opcode(OPCODE_UPDATE).add_question(
+            &self.zone,
+            RecordType::SOA,
+            self.rclass,
+        )?;
+        msg.answer = self.prerequisites.clone();
+        msg.authority = self.updates.clone();
+        msg.to_bytes()
+    }
+
+    // Send the update to server, the zone's primary, over UDP or over TCP
+    // if it is too large or the response is truncated. With a key the
+    // update is signed and the response must be too.
+    pub fn send(&self, server: SocketAddr, key: Option<&Key>) -> Result<Response, String> {
+        let mut packet = self.to_bytes(rand::random())?;
+        let mut session = key.map(Session::new);
+        if let Some(s) = session.as_mut() {
+            s.sign(&mut packet, tsig::now())?;
+        }
+
+        let tcp = |e| format!("update to {} over TCP failed: {}", server, e);
+        let bytes = if packet.len() > UDP_SIZE {
+            exchange_tcp(server, &packet, TIMEOUT).map_err(tcp)?
+        } else {
+            let bytes = exchange_udp(server, &packet, TIMEOUT)
+                .map_err(|e| format!("update to {} failed: {}", server, e))?;
+            if bytes[2] & 0x02 != 0 {
+                exchange_tcp(server, &packet, TIMEOUT).map_err(tcp)?
+            } else {
+                bytes
+            }
+        };
+
+        if let Some(s) = session.as_mut() {
+            // a server that rejects the key answers NOTAUTH unsigned
+            if let Err(e) = s.verify(&bytes, tsig::now()) {
+                return Err(match bytes[3] & 0x0f {
+                    0 => e,
+                    rcode => format!("{}: {}", rcode_str(rcode), e),
+                });
+            }
+        }
+
+        Response::from_bytes(bytes)
+    }
+}
+
+// One update from a script, with the server it should go to if the script
+// named one
+#[derive(Debug, PartialEq)]
+pub struct ScriptUpdate {
+    pub line: usize,
+    pub server: Option<String>,
+    pub key: Option<Key>,
+    pub update: Update,
+}
+
+// the first whitespace separated word of text and the rest
+fn split_word(text: &str) -> (&str, &str) {
+    let text = text.trim_start();
+    match text.find(char::is_whitespace) {
+        Some(i) => (&text[..i], text[i..].trim_start()),
+        None => (text, ""),
+    }
+}
+
+// a single record in master file format, without $ORIGIN or $TTL
+fn record(text: &str, ttl: Option<u32>) -> Result<Answer, String> {
+    let text = match ttl {
+        Some(t) => format!("$TTL {}\n{}\n", t, text),
+        None => format!("{}\n", text),
+    };
+    let mut records = parse_zone(&text, ".").map_err(|e| e.message)?;
+    match records.len() {
+        1 => Ok(records.remove(0)),
+        _ => Err("expected a single record".to_string()),
+    }
+}
+
+// `name [ttl] [class] [type [rdata]]`, as taken by delete and prereq
+// yxrrset; the ttl is ignored
+fn rrset_spec(text: &str) -> Result<(String, Option<RecordType>, &str), String> {
+    let (name, mut rest) = split_word(text);
+    if name.is_empty() {
+        return Err("missing name".to_string());
+    }
+    let name = absolute_name(name, ".")?;
+
+    let (word, after) = split_word(rest);
+    if parse_ttl(word).is_ok() {
+        rest = after;
+    }
+    let (word, after) = split_word(rest);
+    if !after.is_empty() && word.parse::<RecordClass>().is_ok() {
+        rest = after;
+    }
+
+    let (word, rdata) = split_word(rest);
+    if word.is_empty() {
+        return Ok((name, None, ""));
+    }
+    let rtype = word
+        .parse::<RecordType>()
+        .map_err(|_| format!("unknown record type {}", word))?;
+    Ok((name, Some(rtype), rdata))
+}
+
+struct ScriptState {
+    server: Option<String>,
+    key: Option<Key>,
+    zone: Option<String>,
+    rclass: RecordClass,
+    ttl: Option<u32>,
+    current: Option<ScriptUpdate>,
+    done: Vec<ScriptUpdate>,
+}
+
+impl ScriptState {
+    fn update(&mut self, line: usize) -> Result<&mut Update, String> {
+        if self.current.is_none() {
+            let zone = self
+                .zone
+                .as_ref()
+                .ok_or("no zone given; start the script with `zone <name>`")?;
+            let mut update = Update::new(zone);
+            update.set_class(self.rclass);
+            self.current = Some(ScriptUpdate {
+                line,
+                server: self.server.clone(),
+                key: self.key.clone(),
+                update,
+            });
+        }
+        Ok(&mut self.current.as_mut().unwrap().update)
+    }
+
+    fn send(&mut self) {
+        if let Some(s) = self.current.take() {
+            if !s.update.is_empty() {
+                self.done.push(s);
+            }
+        }
+    }
+
+    fn command(&mut self, line: usize, text: &str) -> Result<(), String> {
+        let (cmd, args) = split_word(text);
+        match cmd {
+            "send" => self.send(),
+            "server" => {
+                let (host, port) = split_word(args);
+                if host.is_empty() {
+                    return Err("server needs a host".to_string());
+                }
+                self.server = Some(match port {
+                    "" => host.to_string(),
+                    p if host.contains(':') => format!("[{}]:{}", host, p),
+                    p => format!("{}:{}", host, p),
+                });
+            }
+            // `key [algorithm:]name secret`, as nsupdate takes it
+            "key" => {
+                let (name, secret) = split_word(args);
+                self.key = Some(Key::from_spec(&format!("{}:{}", name, secret))?);
+            }
+            "zone" => {
+                let (zone, _) = split_word(args);
+                self.zone = Some(absolute_name(zone, ".")?);
+            }
+            "class" => {
+                self.rclass = args
+                    .parse()
+                    .map_err(|_| format!("unknown class {}", args))?;
+            }
+            "ttl" => self.ttl = Some(parse_ttl(args)?),
+            "prereq" => self.prereq(line, args)?,
+            "update" => {
+                let (op, args) = split_word(args);
+                self.change(line, op, args)?;
+            }
+            "add" | "delete" | "del" => self.change(line, cmd, args)?,
+            _ => return Err(format!("unknown command {}", cmd)),
+        }
+        Ok(())
+    }
+
+    fn prereq(&mut self, line: usize, text: &str) -> Result<(), String> {
+        let (kind, args) = split_word(text);
+        let (name, rtype, rdata) = rrset_spec(args)?;
+        let update = self.update(line)?;
+
+        match (kind, rtype) {
+            ("nxdomain", None) => update.name_not_in_use(&name),
+            ("yxdomain", None) => update.name_in_use(&name),
+            ("nxrrset", Some(t)) => update.rrset_absent(&name, t),
+            ("yxrrset", Some(t)) if rdata.is_empty() => update.rrset_exists(&name, t),
+            ("yxrrset", Some(t)) => {
+                update.rrset_matches(record(&format!("{} 0 {} {}", name, t, rdata), None)?)
+            }
+            ("nxrrset" | "yxrrset", None) => return Err(format!("{} needs a type", kind)),
+            ("nxdomain" | "yxdomain", Some(_)) => {
+                return Err(format!("{} takes only a name", kind))
+            }
+            _ => return Err(format!("unknown prerequisite {}", kind)),
+        };
+        Ok(())
+    }
+
+    fn change(&mut self, line: usize, op: &str, text: &str) -> Result<(), String> {
+        match op {
+            "add" => {
+                let rr = record(text, self.ttl)?;
+                self.update(line)?.add(rr);
+            }
+            "delete" | "del" => {
+                let (name, rtype, rdata) = rrset_spec(text)?;
+                let rr = match rtype {
+                    Some(t) if !rdata.is_empty() => {
+                        Some(record(&format!("{} 0 {} {}", name, t, rdata), None)?)
+                    }
+                    _ => None,
+                };
+                let update = self.update(line)?;
+                match (rtype, rr) {
+                    (_, Some(rr)) => update.delete(rr),
+                    (Some(t), None) => update.delete_rrset(&name, t),
+                    (None, None) => update.delete_name(&name),
+                };
+            }
+            _ => return Err(format!("unknown update operation {}", op)),
+        }
+        Ok(())
+    }
+}
+
+// Parse an nsupdate style script into the updates it sends. Updates are
+// sent by `send`, a blank line or the end of the script. Names are taken
+// relative to the root, as nsupdate does.
+pub fn parse_script(text: &str) -> Result<Vec<ScriptUpdate>, String> {
+    let mut state = ScriptState {
+        server: None,
+        key: None,
+        zone: None,
+        rclass: RecordClass::IN,
+        ttl: None,
+        current: None,
+        done: Vec::new(),
+    };
+
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.starts_with(';') || line.starts_with('#') {
+            continue;
+        }
+        if line.is_empty() {
+            state.send();
+            continue;
+        }
+        state
+            .command(i + 1, line)
+            .map_err(|e| format!("line {}: {}", i + 1, e))?;
+    }
+    state.send();
+
+    Ok(state.done)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::UdpSocket;
+    use std::thread;
+
+    fn a(name: &str, ttl: u32) -> Answer {
+        Answer::new(
+            name.to_string(),
+            RecordType::A,
+            RecordClass::IN,
+            ttl,
+            RData::A([192, 0, 2, 1].into()),
+        )
+    }
+
+    fn class_type_ttl(records: &[Answer]) -> Vec<(RecordClass, RecordType, u32, u16)> {
+        records
+            .iter()
+            .map(|r| (r.rclass, r.rtype, r.ttl, r.len))
+            .collect()
+    }
+
+    #[test]
+    fn test_update_encoding() {
+        let mut update = Update::new("example.com.");
+        update
+            .name_not_in_use("new.example.com.")
+            .rrset_exists("example.com.", RecordType::SOA)
+            .rrset_absent("www.example.com.", RecordType::AAAA)
+            .rrset_matches(a("ns1.example.com.", 300))
+            .name_in_use("mail.example.com.")
+            .add(a("new.example.com.", 300))
+            .delete_rrset("old.example.com.", RecordType::A)
+            .delete_name("gone.example.com.")
+            .delete(a("ns1.example.com.", 300));
+
+        let msg = Response::from_bytes(update.to_bytes(7).unwrap()).unwrap();
+        assert!(msg.header.id == 7 && msg.header.flags.opcode() == 5);
+        assert!(!msg.header.flags.rd());
+        assert!(Update::new("bad..example.com").to_bytes(7).is_err());
+
+        let zone = msg.question.unwrap();
+        assert!(zone.get_name_str() == "example.com.");
+        assert!(zone.rtype == RecordType::SOA);
+
+        use RecordClass::{ANY, IN, NONE};
+        assert!(
+            class_type_ttl(&msg.answer)
+                == [
+                    (NONE, RecordType::ANY, 0, 0),
+                    (ANY, RecordType::SOA, 0, 0),
+                    (NONE, RecordType::AAAA, 0, 0),
+                    (IN, RecordType::A, 0, 4),
+                    (ANY, RecordType::ANY, 0, 0),
+                ]
+        );
+        assert!(
+            class_type_ttl(&msg.authority)
+                == [
+                    (IN, RecordType::A, 300, 4),
+                    (ANY, RecordType::A, 0, 0),
+                    (ANY, RecordType::ANY, 0, 0),
+                    (NONE, RecordType::A, 0, 4),
+                ]
+        );
+    }
+
+    #[test]
+    fn test_parse_script() {
+        let script = "; rotate www
+server 192.0.2.53 5353
+key hmac-sha512:update-key c2VjcmV0
+zone example.com
+ttl 300
+prereq yxrrset www.example.com A
+prereq nxdomain new.example.com.
+update delete www.example.com A 192.0.2.1
+update add www.example.com 60 A 192.0.2.2
+add new.example.com TXT \"hello  world\"
+send
+
+zone example.org
+del old.example.org
+delete old.example.org 300 IN MX
+";
+        let updates = parse_script(script).unwrap();
+        assert!(updates.len() == 2);
+
+        let first = &updates[0];
+        assert!(first.line == 6);
+        assert!(first.server.as_deref() == Some("192.0.2.53:5353"));
+        assert!(first.key.as_ref().unwrap().name == "update-key.");
+        assert!(first.update.zone() == "example.com.");
+        assert!(first.update.prerequisites().len() == 2);
+
+        let changes = first.update.updates();
+        assert!(changes[0].rclass == RecordClass::NONE);
+        assert!(changes[0].rdata == RData::A([192, 0, 2, 1].into()));
+        assert!(changes[1].ttl == 60);
+        assert!(changes[2].ttl == 300);
+        assert!(changes[2].rdata == RData::TXT(vec![b"hello  world".to_vec()]));
+
+        let second = &updates[1].update;
+        assert!(second.zone() == "example.org.");
+        assert!(second.updates()[0].rtype == RecordType::ANY);
+        assert!(second.updates()[1].rtype == RecordType::MX);
+        assert!(second.updates()[1].rclass == RecordClass::ANY);
+
+        let err = parse_script("add www.example.com 60 A 192.0.2.1").unwrap_err();
+        assert!(err.contains("line 1") && err.contains("no zone"));
+        let err = parse_script("zone example.com\nadd www.example.com A 192.0.2.1").unwrap_err();
+        assert!(err.contains("line 2") && err.contains("TTL"));
+        assert!(parse_script("zone example.com\nprereq nxrrset www").is_err());
+        assert!(parse_script("frobnicate").is_err());
+    }
+
+    #[test]
+    fn test_send_reports_rcode() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+
+        // a primary that is not authoritative for anything
+        thread::spawn(move || {
+            let mut buf = [0; 512];
+            let (size, peer) = server.recv_from(&mut buf).unwrap();
+            let mut resp = buf[..size].to_vec();
+            resp[2] |= 0x80;
+            resp[3] = (resp[3] & 0xf0) | 9;
+            server.send_to(&resp, peer).unwrap();
+        });
+
+        let mut update = Update::new("example.com.");
+        update.delete_name("www.example.com.");
+        let resp = update.send(addr, None).unwrap();
+
+        assert!(resp.header.flags.rcode() == 9);
+        assert!(rcode_meaning(9).contains("not authoritative"));
+    }
+}
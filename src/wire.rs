@@ -0,0 +1,582 @@
+// Annotated breakdown of DNS messages on the wire
+//
+// A message is walked field by field, printing the offset and bytes of
+// each field with what they mean: the header flags by name, every label
+// and compression pointer of every name, and the type, class, TTL,
+// RDLENGTH and RDATA of each record. Nothing has to be valid for the walk
+// to begin; it ends with an error line at the first field that cannot be
+// read. Input given as text is decoded from hex or base64 first.
+use std::fmt;
+use std::str::FromStr;
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use byteorder::{BigEndian, ByteOrder};
+
+use crate::dns::{opcode_str, push_label, rcode_str, RecordClass, RecordType, HDR_SIZE};
+use crate::name::Name;
+use crate::rdata::RData;
+use crate::zone::format_ttl;
+
+// bytes shown per line of a field
+const ROW: usize = 8;
+const OPCODE_UPDATE: u8 = 5;
+
+// How input to decode is encoded
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum InputFormat {
+    Hex,
+    Base64,
+    Binary,
+}
+
+impl FromStr for InputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "hex" => Ok(InputFormat::Hex),
+            "base64" => Ok(InputFormat::Base64),
+            "binary" | "bin" => Ok(InputFormat::Binary),
+            _ => Err(format!("unknown input format {}", s)),
+        }
+    }
+}
+
+impl fmt::Display for InputFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            InputFormat::Hex => "hex",
+            InputFormat::Base64 => "base64",
+            InputFormat::Binary => "binary",
+        })
+    }
+}
+
+// Hex as a plain or spaced stream (ab cd, abcd, 0xab, ab:cd) or as a
+// hexdump whose lines start with their offset, e.g. from Wireshark, xxd or
+// tcpdump -X. An offset is only skipped when it matches the bytes read so
+// far and either ends in ':' or is followed by single bytes, so that a
+// message with ID 0 written in 4 digit groups is kept as data. Only lines
+// that start with an offset may end in other text (the ASCII column).
+fn decode_hex(text: &str) -> Option<Vec<u8>> {
+    let mut bytes = Vec::new();
+    for line in text.lines() {
+        let mut tokens = line.split_whitespace().peekable();
+        let mut dump_line = false;
+        if let Some(first) = tokens.peek() {
+            let offset = first.trim_start_matches("0x").trim_end_matches(':');
+            let next_is_byte = line.split_whitespace().nth(1).is_some_and(|t| t.len() == 2);
+            let is_offset = first.ends_with(':') || (offset.len() >= 4 && next_is_byte);
+            if is_offset && usize::from_str_radix(offset, 16).ok() == Some(bytes.len()) {
+                tokens.next();
+                dump_line = true;
+            }
+        }
+
+        let mut digits = String::new();
+        for t in tokens.by_ref() {
+            let t = t.trim_start_matches("0x").replace(':', "");
+            if t.is_empty() || t.len() % 2 != 0 || !t.chars().all(|c| c.is_ascii_hexdigit()) {
+                if !dump_line {
+                    return None;
+                }
+                break;
+            }
+            digits.push_str(&t);
+        }
+        for i in (0..digits.len()).step_by(2) {
+            bytes.push(u8::from_str_radix(&digits[i..i + 2], 16).ok()?);
+        }
+    }
+
+    Some(bytes).filter(|b| !b.is_empty())
+}
+
+fn decode_base64(text: &str) -> Result<Vec<u8>, String> {
+    let text: String = text.split_whitespace().collect();
+    BASE64
+        .decode(text)
+        .map_err(|e| format!("input is not valid base64: {}", e))
+}
+
+// Decode input in format, or guess: anything that is not text is binary,
+// and text is tried as hex before base64
+pub fn decode_input(data: &[u8], format: Option<InputFormat>) -> Result<Vec<u8>, String> {
+    let text = std::str::from_utf8(data)
+        .ok()
+        .filter(|t| !t.chars().any(|c| c.is_control() && !c.is_whitespace()));
+
+    match (format, text) {
+        (Some(InputFormat::Binary), _) => Ok(data.to_vec()),
+        (_, None) if format.is_none() => Ok(data.to_vec()),
+        (_, None) => Err(format!("input is not {} text", format.unwrap())),
+        (Some(InputFormat::Hex), Some(t)) => decode_hex(t).ok_or("input is not valid hex".into()),
+        (Some(InputFormat::Base64), Some(t)) => decode_base64(t),
+        (None, Some(t)) => match decode_hex(t) {
+            Some(bytes) => Ok(bytes),
+            None => decode_base64(t).map_err(|_| "input is neither hex nor base64".to_string()),
+        },
+    }
+}
+
+// the names of the four sections and their counts
+fn section_names(opcode: u8) -> [(&'static str, &'static str); 4] {
+    match opcode {
+        // RFC 2136 2.2
+        OPCODE_UPDATE => [
+            ("zone", "ZOCOUNT"),
+            ("prerequisite", "PRCOUNT"),
+            ("update", "UPCOUNT"),
+            ("additional", "ADCOUNT"),
+        ],
+        _ => [
+            ("question", "QDCOUNT"),
+            ("answer", "ANCOUNT"),
+            ("authority", "NSCOUNT"),
+            ("additional", "ARCOUNT"),
+        ],
+    }
+}
+
+// EDNS option codes - https://www.iana.org/assignments/dns-parameters
+fn option_name(code: u16) -> &'static str {
+    match code {
+        3 => "NSID",
+        5 => "DAU",
+        6 => "DHU",
+        7 => "N3U",
+        8 => "CLIENT-SUBNET",
+        9 => "EXPIRE",
+        10 => "COOKIE",
+        11 => "TCP-KEEPALIVE",
+        12 => "PADDING",
+        13 => "CHAIN",
+        14 => "KEY-TAG",
+        15 => "EXTENDED-ERROR",
+        _ => "unknown",
+    }
+}
+
+fn flag_names(flags: u16) -> String {
+    let names: Vec<&str> = [
+        (0x8000, "qr"),
+        (0x0400, "aa"),
+        (0x0200, "tc"),
+        (0x0100, "rd"),
+        (0x0080, "ra"),
+        (0x0040, "z"),
+        (0x0020, "ad"),
+        (0x0010, "cd"),
+    ]
+    .iter()
+    .filter(|(bit, _)| flags & bit != 0)
+    .map(|(_, name)| *name)
+    .collect();
+
+    match names.is_empty() {
+        true => "none".to_string(),
+        false => names.join(" "),
+    }
+}
+
+struct Dump<'a> {
+    msg: &'a [u8],
+    lines: Vec<String>,
+}
+
+impl<'a> Dump<'a> {
+    // the error for a field described by what that does not fit
+    fn past_end(&self, what: &str, at: usize) -> String {
+        format!(
+            "{} at {:#06x} runs past end of message ({} bytes)",
+            what.split(':').next().unwrap_or(what),
+            at,
+            self.msg.len()
+        )
+    }
+
+    // print the len bytes at offset as one field, described by what
+    fn field(&mut self, at: usize, len: usize, what: &str) -> Result<&'a [u8], String> {
+        let bytes = self
+            .msg
+            .get(at..at + len)
+            .ok_or_else(|| self.past_end(what, at))?;
+
+        for (i, row) in bytes.chunks(ROW).enumerate() {
+            let hex: Vec<String> = row.iter().map(|b| format!("{:02x}", b)).collect();
+            let what = if i == 0 { what } else { "" };
+            let line = format!(
+                "{:04x}  {:<w$} {}",
+                at + i * ROW,
+                hex.join(" "),
+                what,
+                w = ROW * 3
+            );
+            self.lines.push(line.trim_end().to_string());
+        }
+        if bytes.is_empty() {
+            self.lines
+                .push(format!("{:04x}  {:<w$} {}", at, "", what, w = ROW * 3));
+        }
+        Ok(bytes)
+    }
+
+    // a 16 bit field, described by what given its value
+    fn u16(&mut self, at: usize, what: impl Fn(u16) -> String) -> Result<u16, String> {
+        let v = self.msg.get(at..at + 2).map(BigEndian::read_u16);
+        let v = v.ok_or_else(|| self.past_end(&what(0), at))?;
+        self.field(at, 2, &what(v))?;
+        Ok(v)
+    }
+
+    fn u32(&mut self, at: usize, what: impl Fn(u32) -> String) -> Result<u32, String> {
+        let v = self.msg.get(at..at + 4).map(BigEndian::read_u32);
+        let v = v.ok_or_else(|| self.past_end(&what(0), at))?;
+        self.field(at, 4, &what(v))?;
+        Ok(v)
+    }
+
+    fn note(&mut self, what: String) {
+        self.lines
+            .push(format!("      {:<w$} {}", "", what, w = ROW * 3));
+    }
+
+    // each label and pointer of the name at offset, then the whole name;
+    // returns the bytes the name occupies at offset
+    fn name(&mut self, at: usize) -> Result<usize, String> {
+        let mut ptr = at;
+        loop {
+            let len = *self
+                .msg
+                .get(ptr)
+                .ok_or(format!("name at {:#06x} runs past end of message", at))?
+                as usize;
+            match len & 0xc0 {
+                0x00 if len == 0 => {
+                    self.field(ptr, 1, "root")?;
+                    ptr += 1;
+                    break;
+                }
+                0x00 => {
+                    let label = self
+                        .msg
+                        .get(ptr + 1..ptr + 1 + len)
+                        .ok_or(format!("label at {:#06x} runs past end of message", ptr))?;
+                    let mut text = String::new();
+                    push_label(&mut text, label);
+                    self.field(ptr, 1 + len, &format!("label ({}): {}", len, text))?;
+                    ptr += 1 + len;
+                }
+                0xc0 => {
+                    let low = *self
+                        .msg
+                        .get(ptr + 1)
+                        .ok_or(format!("pointer at {:#06x} runs past end of message", ptr))?;
+                    let target = ((len & 0x3f) << 8) | low as usize;
+                    let what = match Name::from_wire(self.msg, target) {
+                        Ok((n, _)) => format!("pointer -> {:#06x} ({})", target, n),
+                        Err(e) => format!("pointer -> {:#06x} ({})", target, e),
+                    };
+                    self.field(ptr, 2, &what)?;
+                    ptr += 2;
+                    break;
+                }
+                _ => {
+                    self.field(ptr, 1, &format!("unsupported label type {:#04x}", len))?;
+                    return Err(format!("unsupported label type at {:#06x}", ptr));
+                }
+            }
+        }
+
+        let name =
+            Name::from_wire(self.msg, at).map_err(|e| format!("name at {:#06x}: {}", at, e))?;
+        self.note(format!("= {}", name.0));
+        Ok(ptr - at)
+    }
+
+    fn header(&mut self) -> Result<[u16; 4], String> {
+        self.lines.push(";; header".to_string());
+        self.u16(0, |v| format!("ID: {}", v))?;
+        self.u16(2, |v| {
+            let opcode = ((v >> 11) & 0x0f) as u8;
+            format!(
+                "flags: {}; opcode {} ({}); rcode {} ({})",
+                flag_names(v),
+                opcode_str(opcode),
+                opcode,
+                rcode_str((v & 0x0f) as u8),
+                v & 0x0f
+            )
+        })?;
+
+        let names = section_names(self.msg.get(2).map_or(0, |b| (b >> 3) & 0x0f));
+        let mut counts = [0; 4];
+        for (i, count) in counts.iter_mut().enumerate() {
+            *count = self.u16(4 + i * 2, |v| format!("{}: {}", names[i].1, v))?;
+        }
+        Ok(counts)
+    }
+
+    fn question(&mut self, at: usize) -> Result<usize, String> {
+        let mut ptr = at + self.name(at)?;
+        self.u16(ptr, |v| format!("QTYPE: {}", RecordType::from_u16(v)))?;
+        self.u16(ptr + 2, |v| format!("QCLASS: {}", RecordClass::from_u16(v)))?;
+        ptr += 4;
+        Ok(ptr - at)
+    }
+
+    fn record(&mut self, at: usize) -> Result<usize, String> {
+        let mut ptr = at + self.name(at)?;
+        let rtype =
+            RecordType::from_u16(self.u16(ptr, |v| format!("TYPE: {}", RecordType::from_u16(v)))?);
+        ptr += 2;
+
+        if rtype == RecordType::OPT {
+            self.u16(ptr, |v| format!("CLASS: UDP payload size {}", v))?;
+            self.u32(ptr + 2, |v| {
+                format!(
+                    "TTL: extended rcode {}, version {}, flags {}",
+                    v >> 24,
+                    (v >> 16) & 0xff,
+                    if v & 0x8000 != 0 { "do" } else { "none" }
+                )
+            })?;
+        } else {
+            self.u16(ptr, |v| format!("CLASS: {}", RecordClass::from_u16(v)))?;
+            self.u32(ptr + 2, |v| format!("TTL: {} ({})", v, format_ttl(v)))?;
+        }
+        ptr += 6;
+
+        let len = self.u16(ptr, |v| format!("RDLENGTH: {}", v))? as usize;
+        ptr += 2;
+        let end = ptr + len;
+        if end > self.msg.len() {
+            return Err(self.past_end("RDATA", ptr));
+        }
+
+        let used = self.rdata(rtype, ptr, len)?;
+        if used < len {
+            self.field(ptr + used, len - used, "RDATA: left over")?;
+        } else if used > len {
+            return Err(format!("RDATA at {:#06x} is longer than its RDLENGTH", ptr));
+        }
+        Ok(end - at)
+    }
+
+    // the fields of a record's data, returning the bytes they occupy
+    fn rdata(&mut self, rtype: RecordType, at: usize, len: usize) -> Result<usize, String> {
+        if len == 0 {
+            self.note("RDATA: empty".to_string());
+            return Ok(0);
+        }
+
+        let mut ptr = at;
+        match rtype {
+            RecordType::NS | RecordType::CNAME | RecordType::PTR => ptr += self.name(ptr)?,
+            RecordType::MX => {
+                self.u16(ptr, |v| format!("preference: {}", v))?;
+                ptr += 2;
+                ptr += self.name(ptr)?;
+            }
+            RecordType::SRV => {
+                self.u16(ptr, |v| format!("priority: {}", v))?;
+                self.u16(ptr + 2, |v| format!("weight: {}", v))?;
+                self.u16(ptr + 4, |v| format!("port: {}", v))?;
+                ptr += 6;
+                ptr += self.name(ptr)?;
+            }
+            RecordType::SOA => {
+                ptr += self.name(ptr)?;
+                ptr += self.name(ptr)?;
+                self.u32(ptr, |v| format!("serial: {}", v))?;
+                for (i, field) in ["refresh", "retry", "expire", "minimum"].iter().enumerate() {
+                    self.u32(ptr + 4 + i * 4, |v| {
+                        format!("{}: {} ({})", field, v, format_ttl(v))
+                    })?;
+                }
+                ptr += 20;
+            }
+            RecordType::OPT => {
+                while ptr < at + len {
+                    let code = self.u16(ptr, |v| format!("option: {} ({})", option_name(v), v))?;
+                    let olen = self.u16(ptr + 2, |v| format!("option length: {}", v))? as usize;
+                    if olen > 0 {
+                        self.field(ptr + 4, olen, &format!("{} data", option_name(code)))?;
+                    }
+                    ptr += 4 + olen;
+                }
+            }
+            _ => {
+                let what = match RData::from_bytes(rtype, self.msg, at, len) {
+                    Ok(RData::Unknown(_)) => format!("RDATA: {} bytes", len),
+                    Ok(rdata) => format!("RDATA: {}", rdata),
+                    Err(e) => format!("RDATA: {}", e),
+                };
+                self.field(at, len, &what)?;
+                ptr += len;
+            }
+        }
+        Ok(ptr - at)
+    }
+
+    fn walk(&mut self) -> Result<(), String> {
+        let counts = self.header()?;
+        let names = section_names((self.msg[2] >> 3) & 0x0f);
+
+        let mut ptr = HDR_SIZE;
+        for (section, &count) in counts.iter().enumerate() {
+            if count == 0 {
+                continue;
+            }
+            self.lines.push(format!(";; {} section", names[section].0));
+            for _ in 0..count {
+                ptr += match section {
+                    0 => self.question(ptr)?,
+                    _ => self.record(ptr)?,
+                };
+            }
+        }
+
+        if ptr < self.msg.len() {
+            let extra = self.msg.len() - ptr;
+            self.lines.push(format!(";; {} trailing bytes", extra));
+            self.field(ptr, extra, "trailing data")?;
+        }
+        Ok(())
+    }
+}
+
+// An annotated breakdown of msg, one field per line. A message that cannot
+// be read to the end gets as far as it can and ends with an error line.
+pub fn annotate(msg: &[u8]) -> String {
+    let mut dump = Dump {
+        msg,
+        lines: vec![format!("; {} bytes", msg.len())],
+    };
+    if let Err(e) = dump.walk() {
+        dump.lines.push(format!("; error: {}", e));
+    }
+    dump.lines.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dns::Answer;
+    use crate::message::Message;
+
+    fn response() -> Vec<u8> {
+        let mut msg = Message::query("www.example.com", RecordType::MX, RecordClass::IN).unwrap();
+        msg.set_id(0x1234).set_qr(true).set_ra(true);
+        msg.add_answer(Answer::new(
+            "www.example.com.".to_string(),
+            RecordType::MX,
+            RecordClass::IN,
+            3600,
+            RData::MX {
+                preference: 10,
+                exchange: "mail.example.com.".to_string(),
+            },
+        ));
+        msg.to_bytes().unwrap()
+    }
+
+    #[test]
+    fn test_decode_input() {
+        let bytes = vec![0x12, 0x34, 0xab, 0xcd];
+        assert!(decode_input(b"1234abcd", None) == Ok(bytes.clone()));
+        assert!(decode_input(b"12 34 AB CD\n", None) == Ok(bytes.clone()));
+        assert!(decode_input(b"0x12 0x34 0xab 0xcd", None) == Ok(bytes.clone()));
+        assert!(decode_input(b"12:34:ab:cd", None) == Ok(bytes.clone()));
+        assert!(decode_input(b"EjSrzQ==", None) == Ok(bytes.clone()));
+        assert!(decode_input(b"\x12\x34\xab\xcd", None) == Ok(bytes.clone()));
+        assert!(decode_input(b"1234abcd", Some(InputFormat::Binary)) == Ok(b"1234abcd".to_vec()));
+        assert!(decode_input(b"EjSrzQ==", Some(InputFormat::Hex)).is_err());
+        assert!(decode_input(b"not hex or base64!", None).is_err());
+
+        // Wireshark and xxd style dumps, offsets and ASCII columns skipped
+        let dump = "0000   12 34 ab cd 00 01 00 00 00 00 00 00 00 00 00 00   .4..............\n\
+                    0010   ff 01                                             ..\n";
+        let mut expected = bytes.clone();
+        expected.extend([0, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0xff, 1]);
+        assert!(decode_input(dump.as_bytes(), None) == Ok(expected));
+        let dump = "00000000: 1234 abcd  .4..\n";
+        assert!(decode_input(dump.as_bytes(), None) == Ok(bytes));
+
+        // a leading 0000 that is a message ID of 0, not an offset
+        let query = b"0000 0100 0001 0000 0000 0000 0000 0100 01";
+        let decoded = decode_input(query, None).unwrap();
+        assert!(decoded.len() == 17 && decoded[..4] == [0, 0, 1, 0]);
+    }
+
+    #[test]
+    fn test_annotate() {
+        let out = annotate(&response());
+        let lines: Vec<&str> = out.lines().collect();
+
+        assert!(lines[0] == "; 54 bytes");
+        assert!(lines[1] == ";; header");
+        assert!(lines[2] == "0000  12 34                    ID: 4660");
+        assert!(
+            lines[3] == "0002  81 80                    flags: qr rd ra; opcode QUERY (0); rcode NOERROR (0)"
+        );
+        assert!(lines[4] == "0004  00 01                    QDCOUNT: 1");
+        assert!(
+            out.contains("\n;; question section\n000c  03 77 77 77              label (3): www\n")
+        );
+        assert!(out.contains("\n001c  00                       root\n"));
+        assert!(out.contains("\n                               = www.example.com.\n"));
+        assert!(out.contains("\n001f  00 01                    QCLASS: IN\n"));
+        assert!(out.contains("\n;; answer section\n0021  c0 0c                    pointer -> 0x000c (www.example.com.)\n"));
+        assert!(out.contains("TTL: 3600 (1h)"));
+        assert!(out.contains("RDLENGTH: 9"));
+        assert!(out.contains("preference: 10"));
+        assert!(out.contains("label (4): mail\n"));
+        assert!(out.contains("pointer -> 0x0010 (example.com.)"));
+        assert!(!out.contains("error"));
+    }
+
+    #[test]
+    fn test_annotate_errors() {
+        let bytes = response();
+        let out = annotate(&bytes[..bytes.len() - 3]);
+        assert!(out.ends_with("; error: RDATA at 0x002d runs past end of message (51 bytes)"));
+
+        let out = annotate(&bytes[..5]);
+        assert!(out.ends_with("; error: QDCOUNT at 0x0004 runs past end of message (5 bytes)"));
+
+        // a pointer to itself is shown along with why it cannot be followed
+        let mut bytes = bytes[..HDR_SIZE].to_vec();
+        bytes[7] = 0;
+        bytes.extend([0xc0, 0x0c, 0, 1, 0, 1]);
+        let out = annotate(&bytes);
+        assert!(out.contains("pointer -> 0x000c (compression pointer loop)"));
+        assert!(out.ends_with("; error: name at 0x000c: compression pointer loop"));
+    }
+
+    #[test]
+    fn test_annotate_opt_and_update() {
+        let mut msg = Message::new();
+        msg.set_opcode(OPCODE_UPDATE)
+            .add_question("example.com", RecordType::SOA, RecordClass::IN)
+            .unwrap();
+        let opt = Answer::new(
+            ".".to_string(),
+            RecordType::OPT,
+            RecordClass::Unknown(1232),
+            0x8000,
+            RData::Unknown(vec![0, 10, 0, 8, 1, 2, 3, 4, 5, 6, 7, 8]),
+        );
+        msg.add_additional(opt);
+
+        let out = annotate(&msg.to_bytes().unwrap());
+        assert!(out.contains("opcode UPDATE (5)"));
+        assert!(out.contains("ZOCOUNT: 1") && out.contains("ADCOUNT: 1"));
+        assert!(out.contains(";; zone section"));
+        assert!(out.contains("CLASS: UDP payload size 1232"));
+        assert!(out.contains("TTL: extended rcode 0, version 0, flags do"));
+        assert!(out.contains("option: COOKIE (10)"));
+        assert!(out.contains("01 02 03 04 05 06 07 08  COOKIE data"));
+    }
+}
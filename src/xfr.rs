@@ -0,0 +1,492 @@
+// Zone transfers over TCP - RFC 5936 (AXFR) and RFC 1995 (IXFR)
+use std::io::Read;
+
+use crate::dns::{rcode_str, read_tcp_message, Answer, Query, RecordClass, RecordType, Response};
+use crate::rdata::RData;
+use crate::tsig::{self, Key, Session};
+
+// One step of an incremental transfer: the records removed and added to
+// move the zone from serial `from` to serial `to`. The SOA records that
+// delimit the step are not in removed or added; soa is the zone's SOA at
+// serial `to`.
+#[derive(PartialEq, Debug)]
+pub struct IxfrDiff {
+    pub from: u32,
+    pub to: u32,
+    pub soa: Answer,
+    pub removed: Vec<Answer>,
+    pub added: Vec<Answer>,
+}
+
+#[derive(PartialEq, Debug)]
+pub enum Ixfr {
+    // the requested serial is current; holds the server's SOA
+    UpToDate(Answer),
+    // the server fell back to sending the whole zone, as AXFR would
+    Full(Vec<Answer>),
+    Incremental(Vec<IxfrDiff>),
+}
+
+// whether serial a is newer than b - RFC 1982
+pub fn serial_newer(a: u32, b: u32) -> bool {
+    a != b && (a.wrapping_sub(b) as i32) > 0
+}
+
+fn serial(rr: &Answer) -> Option<u32> {
+    match rr.rdata {
+        RData::SOA { serial, .. } => Some(serial),
+        _ => None,
+    }
+}
+
+fn transfer_query(zone: &str, rtype: RecordType) -> Result<Query, String> {
+    let mut query = Query::new(zone.to_string(), rtype, RecordClass::IN)?;
+    query.header.flags.set_rd(false);
+    query.header.flags.set_ad(false);
+    Ok(query)
+}
+
+// Read response messages for query id, collecting answer records until
+// done reports the transfer complete. done is given the records so far and
+// whether the last one ended a message. With a TSIG session every message
+// is verified, and the transfer must end on a signed one.
+fn read_transfer<R: Read>(
+    stream: &mut R,
+    id: u16,
+    mut tsig: Option<&mut Session>,
+    mut done: impl FnMut(&[Answer], bool) -> bool,
+) -> Result<Vec<Answer>, String> {
+    let mut records: Vec<Answer> = Vec::new();
+    loop {
+        let bytes = read_tcp_message(stream)
+            .map_err(|e| format!("Failed to read message: {}", e))?
+            .ok_or("connection closed before the transfer completed")?;
+        if let Some(session) = tsig.as_mut() {
+            session.verify(&bytes, tsig::now())?;
+        }
+        let resp = Response::from_bytes(bytes)?;
+
+        if resp.header.id != id {
+            return Err(format!("unexpected message ID {}", resp.header.id));
+        }
+        if resp.header.flags.rcode() != 0 {
+            return Err(format!(
+                "transfer failed: {}",
+                rcode_str(resp.header.flags.rcode())
+            ));
+        }
+
+        let count = resp.answer.len();
+        for (i, rr) in resp.answer.into_iter().enumerate() {
+            if records.is_empty() && rr.rtype != RecordType::SOA {
+                return Err("transfer did not start with an SOA".to_string());
+            }
+            records.push(rr);
+            if done(&records, i + 1 == count) {
+                if let Some(session) = tsig {
+                    session.finish()?;
+                }
+                return Ok(records);
+            }
+        }
+    }
+}
+
+// an AXFR style transfer ends at the second SOA
+fn axfr_complete(records: &[Answer]) -> bool {
+    records.len() > 1 && records[records.len() - 1].rtype == RecordType::SOA
+}
+
+// Perform a full zone transfer of zone from server (<IP:port>), signed
+// with key if given. The returned records start with the zone's SOA; the
+// closing SOA that marks the end of the transfer is not included.
+pub fn axfr(zone: &str, server: &str, key: Option<&Key>) -> Result<Vec<Answer>, String> {
+    let mut query = transfer_query(zone, RecordType::AXFR)?;
+    let mut session = key.map(Session::new);
+
+    let mut stream = query
+        .send_tcp(server, session.as_mut())
+        .map_err(|e| format!("Failed to connect to {}: {}", server, e))?;
+
+    let mut records = read_transfer(&mut stream, query.header.id, session.as_mut(), |r, _| {
+        axfr_complete(r)
+    })?;
+    records.pop();
+
+    Ok(records)
+}
+
+// Request the changes to zone since serial from server (<IP:port>),
+// signed with key if given.
+pub fn ixfr(zone: &str, server: &str, serial: u32, key: Option<&Key>) -> Result<Ixfr, String> {
+    let mut query = transfer_query(zone, RecordType::IXFR)?;
+    let mut session = key.map(Session::new);
+
+    // the server only looks at the serial of the SOA we claim to have
+    let name = query.question.get_name_str();
+    query.authority.push(Answer::new(
+        name.clone(),
+        RecordType::SOA,
+        RecordClass::IN,
+        0,
+        RData::SOA {
+            mname: name.clone(),
+            rname: name,
+            serial,
+            refresh: 0,
+            retry: 0,
+            expire: 0,
+            minimum: 0,
+        },
+    ));
+
+    let mut stream = query
+        .send_tcp(server, session.as_mut())
+        .map_err(|e| format!("Failed to connect to {}: {}", server, e))?;
+
+    let records = read_transfer(&mut stream, query.header.id, session.as_mut(), |r, end| {
+        ixfr_complete(r, end, serial)
+    })?;
+    parse_ixfr(records)
+}
+
+// Work out whether an IXFR response to a query for changes since serial
+// is complete - RFC 1995 4
+fn ixfr_complete(records: &[Answer], message_end: bool, since: u32) -> bool {
+    // a lone SOA no newer than ours means we are up to date; a newer one
+    // starts an AXFR style response sent a record per message
+    if records.len() == 1 {
+        return message_end && !serial(&records[0]).is_some_and(|s| serial_newer(s, since));
+    }
+
+    // anything but an SOA second means a full, AXFR style, response
+    if records[1].rtype != RecordType::SOA {
+        return axfr_complete(&records[1..]);
+    }
+
+    // After the first SOA, SOAs alternate between starting a deletion and
+    // starting an addition. The transfer ends when the SOA in a deletion
+    // position carries the new serial.
+    let last = &records[records.len() - 1];
+    if last.rtype != RecordType::SOA || serial(last) != serial(&records[0]) {
+        return false;
+    }
+    let soas = records[1..]
+        .iter()
+        .filter(|r| r.rtype == RecordType::SOA)
+        .count();
+    soas % 2 == 1
+}
+
+fn parse_ixfr(mut records: Vec<Answer>) -> Result<Ixfr, String> {
+    if records.len() == 1 {
+        return Ok(Ixfr::UpToDate(records.remove(0)));
+    }
+
+    // a full zone, or an empty one: the SOA is followed straight by its
+    // closing copy
+    let empty_zone = records.len() == 2 && serial(&records[1]) == serial(&records[0]);
+    if records[1].rtype != RecordType::SOA || empty_zone {
+        records.pop();
+        return Ok(Ixfr::Full(records));
+    }
+
+    // drop the leading and closing copies of the new SOA; what remains
+    // alternates deletion SOA, removed records, addition SOA, added records
+    records.pop();
+    let mut diffs: Vec<IxfrDiff> = Vec::new();
+    let mut adding = true;
+
+    for rr in records.into_iter().skip(1) {
+        match serial(&rr) {
+            Some(from) if adding => {
+                // soa is replaced when the addition SOA arrives
+                diffs.push(IxfrDiff {
+                    from,
+                    to: from,
+                    soa: rr,
+                    removed: Vec::new(),
+                    added: Vec::new(),
+                });
+                adding = false;
+            }
+            Some(to) => {
+                let diff = diffs.last_mut().unwrap();
+                diff.to = to;
+                diff.soa = rr;
+                adding = true;
+            }
+            None => {
+                // records[1] is an SOA, so a step is always open here
+                let diff = diffs.last_mut().unwrap();
+                if adding {
+                    diff.added.push(rr);
+                } else {
+                    diff.removed.push(rr);
+                }
+            }
+        }
+    }
+
+    if !adding {
+        return Err("IXFR response ended inside a deletion sequence".to_string());
+    }
+
+    Ok(Ixfr::Incremental(diffs))
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::dns::{read_tcp_message, write_tcp_message};
+    use crate::message::Message;
+    use crate::rdata::RData;
+    use std::net::TcpListener;
+    use std::thread;
+
+    pub(crate) fn soa(zone: &str, serial: u32) -> Answer {
+        Answer::new(
+            zone.to_string(),
+            RecordType::SOA,
+            RecordClass::IN,
+            3600,
+            RData::SOA {
+                mname: format!("ns1.{}", zone),
+                rname: format!("hostmaster.{}", zone),
+                serial,
+                refresh: 3600,
+                retry: 600,
+                expire: 86400,
+                minimum: 300,
+            },
+        )
+    }
+
+    pub(crate) fn a(name: &str, ip: [u8; 4]) -> Answer {
+        Answer::new(
+            name.to_string(),
+            RecordType::A,
+            RecordClass::IN,
+            300,
+            RData::A(ip.into()),
+        )
+    }
+
+    // a response message to query carrying records in its answer section
+    pub(crate) fn message(query: &[u8], records: &[Answer], rcode: u8) -> Vec<u8> {
+        let mut msg = Message::new();
+        msg.set_id(u16::from_be_bytes([query[0], query[1]]))
+            .set_qr(true)
+            .set_rcode(rcode);
+        msg.answer = records.to_vec();
+        msg.to_bytes().unwrap()
+    }
+
+    // a one shot authoritative stand-in answering a single TCP query with
+    // the given response messages
+    pub(crate) fn stand_in(messages: Vec<Vec<Answer>>, rcode: u8) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let query = read_tcp_message(&mut stream).unwrap().unwrap();
+            for records in messages {
+                write_tcp_message(&mut stream, &message(&query, &records, rcode)).unwrap();
+            }
+        });
+
+        addr
+    }
+
+    // as stand_in, but checking the query's TSIG and signing every message
+    // but the middle one of three
+    fn signed_stand_in(messages: Vec<Vec<Answer>>, key: Key) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let query = read_tcp_message(&mut stream).unwrap().unwrap();
+            let mut session = Session::new(&key);
+            if session.verify(&query, tsig::now()).is_err() {
+                return;
+            }
+            for (i, records) in messages.iter().enumerate() {
+                let mut msg = message(&query, records, 0);
+                if i == 1 && messages.len() == 3 {
+                    session.pass_unsigned(&msg);
+                } else {
+                    session.sign(&mut msg, tsig::now()).unwrap();
+                }
+                write_tcp_message(&mut stream, &msg).unwrap();
+            }
+        });
+
+        addr
+    }
+
+    #[test]
+    fn test_axfr_tsig() {
+        let zone = "example.com.";
+        let key = Key::from_spec("hmac-sha256:xfr-key:c2VjcmV0").unwrap();
+        let messages = vec![
+            vec![soa(zone, 7)],
+            vec![a("www.example.com.", [192, 0, 2, 1])],
+            vec![soa(zone, 7)],
+        ];
+
+        let server = signed_stand_in(messages.clone(), key.clone());
+        let records = axfr("example.com", &server, Some(&key)).unwrap();
+        assert!(records.len() == 2);
+
+        // the stand-in drops the connection on a bad signature
+        let wrong = Key::from_spec("hmac-sha256:xfr-key:b3RoZXI=").unwrap();
+        let server = signed_stand_in(messages.clone(), key.clone());
+        assert!(axfr("example.com", &server, Some(&wrong)).is_err());
+
+        // an unsigned response is rejected
+        let server = stand_in(messages, 0);
+        let err = axfr("example.com", &server, Some(&key)).unwrap_err();
+        assert!(err.contains("not signed"));
+    }
+
+    #[test]
+    fn test_axfr_multi_message() {
+        let zone = "example.com.";
+        let server = stand_in(
+            vec![
+                vec![soa(zone, 7), a("www.example.com.", [192, 0, 2, 1])],
+                vec![a("mail.example.com.", [192, 0, 2, 2])],
+                vec![soa(zone, 7)],
+            ],
+            0,
+        );
+
+        let records = axfr("example.com", &server, None).unwrap();
+
+        assert!(records.len() == 3);
+        assert!(records[0] == soa(zone, 7));
+        assert!(records[2].rdata == RData::A([192, 0, 2, 2].into()));
+    }
+
+    #[test]
+    fn test_axfr_refused() {
+        let server = stand_in(vec![vec![]], 5);
+
+        let err = axfr("example.com", &server, None).unwrap_err();
+        assert!(err.contains("REFUSED"));
+    }
+
+    #[test]
+    fn test_axfr_truncated_stream() {
+        let zone = "example.com.";
+        let server = stand_in(
+            vec![vec![soa(zone, 7), a("www.example.com.", [192, 0, 2, 1])]],
+            0,
+        );
+
+        assert!(axfr("example.com", &server, None).is_err());
+    }
+
+    #[test]
+    fn test_ixfr_incremental() {
+        let zone = "example.com.";
+        let www1 = a("www.example.com.", [192, 0, 2, 1]);
+        let www2 = a("www.example.com.", [192, 0, 2, 2]);
+        let mail = a("mail.example.com.", [192, 0, 2, 25]);
+
+        // 1 -> 2 swaps www, 2 -> 3 adds mail; split over two messages
+        let server = stand_in(
+            vec![
+                vec![soa(zone, 3), soa(zone, 1), www1.clone(), soa(zone, 2)],
+                vec![
+                    www2.clone(),
+                    soa(zone, 2),
+                    soa(zone, 3),
+                    mail.clone(),
+                    soa(zone, 3),
+                ],
+            ],
+            0,
+        );
+
+        let diffs = match ixfr("example.com", &server, 1, None).unwrap() {
+            Ixfr::Incremental(d) => d,
+            other => panic!("unexpected {:?}", other),
+        };
+
+        assert!(diffs.len() == 2);
+        assert!(diffs[0].from == 1 && diffs[0].to == 2 && diffs[0].soa == soa(zone, 2));
+        assert!(diffs[0].removed == vec![www1] && diffs[0].added == vec![www2]);
+        assert!(diffs[1].from == 2 && diffs[1].to == 3);
+        assert!(diffs[1].removed.is_empty() && diffs[1].added == vec![mail]);
+    }
+
+    #[test]
+    fn test_ixfr_up_to_date() {
+        let zone = "example.com.";
+        let server = stand_in(vec![vec![soa(zone, 3)]], 0);
+
+        assert!(ixfr("example.com", &server, 3, None).unwrap() == Ixfr::UpToDate(soa(zone, 3)));
+    }
+
+    #[test]
+    fn test_ixfr_axfr_fallback() {
+        let zone = "example.com.";
+        let www = a("www.example.com.", [192, 0, 2, 1]);
+        let server = stand_in(vec![vec![soa(zone, 3), www.clone(), soa(zone, 3)]], 0);
+
+        let full = ixfr("example.com", &server, 1, None).unwrap();
+        assert!(full == Ixfr::Full(vec![soa(zone, 3), www]));
+    }
+
+    #[test]
+    fn test_ixfr_empty_zone() {
+        // an AXFR style reply holding just the SOA and its closing copy
+        let zone = "example.com.";
+        let server = stand_in(vec![vec![soa(zone, 3), soa(zone, 3)]], 0);
+        assert!(ixfr("example.com", &server, 1, None).unwrap() == Ixfr::Full(vec![soa(zone, 3)]));
+
+        let server = stand_in(vec![vec![soa(zone, 3)], vec![soa(zone, 3)]], 0);
+        assert!(ixfr("example.com", &server, 1, None).unwrap() == Ixfr::Full(vec![soa(zone, 3)]));
+    }
+
+    #[test]
+    fn test_serial_newer() {
+        assert!(serial_newer(2, 1));
+        assert!(!serial_newer(1, 1) && !serial_newer(1, 2));
+        // serials wrap around
+        assert!(serial_newer(1, u32::MAX));
+        assert!(!serial_newer(u32::MAX, 1));
+    }
+
+    #[test]
+    fn test_ixfr_one_record_per_message() {
+        // BIND's one-answer transfer format, falling back to AXFR
+        let zone = "example.com.";
+        let www = a("www.example.com.", [192, 0, 2, 1]);
+        let server = stand_in(
+            vec![vec![soa(zone, 3)], vec![www.clone()], vec![soa(zone, 3)]],
+            0,
+        );
+
+        let full = ixfr("example.com", &server, 1, None).unwrap();
+        assert!(full == Ixfr::Full(vec![soa(zone, 3), www]));
+
+        // an older serial from the server still means up to date
+        let server = stand_in(vec![vec![soa(zone, 2)]], 0);
+        assert!(ixfr("example.com", &server, 3, None).unwrap() == Ixfr::UpToDate(soa(zone, 2)));
+    }
+
+    #[test]
+    fn test_ixfr_query_carries_soa() {
+        let mut query = transfer_query("example.com", RecordType::IXFR).unwrap();
+        query.authority.push(soa("example.com.", 42));
+
+        let resp = Response::from_bytes(query.query_serialize().unwrap()).unwrap();
+        assert!(resp.header.ns_count == 1);
+        assert!(serial(&resp.authority[0]) == Some(42));
+        assert!(!resp.header.flags.rd());
+    }
+}
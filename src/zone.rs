@@ -0,0 +1,1307 @@
+// RFC 1035 master (zone) file parsing and writing
+//
+// The parser supports $ORIGIN, $TTL, $INCLUDE and BIND's $GENERATE, relative names and
+// @, parentheses spanning lines, comments, quoted strings and \X / \DDD
+// escapes. Records come back fully qualified with their TTL and class
+// filled in from the file's defaults.
+use std::cmp::Ordering;
+use std::fmt;
+use std::fs;
+use std::net::{Ipv4Addr, Ipv6Addr};
+use std::path::{Path, PathBuf};
+
+use crate::dns::{push_label, Answer, RecordClass, RecordType};
+use crate::name::Name;
+use crate::rdata::RData;
+
+const MAX_INCLUDE_DEPTH: usize = 16;
+// most records one $GENERATE may make, as BIND limits it
+const MAX_GENERATE: u32 = 65535;
+
+#[derive(Debug, PartialEq)]
+pub struct ZoneError {
+    pub file: String,
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ZoneError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}:{}:{}: {}",
+            self.file, self.line, self.column, self.message
+        )
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Token {
+    text: String,
+    quoted: bool,
+    line: usize,
+    column: usize,
+}
+
+// one logical line: a record or directive, with parentheses joined up
+#[derive(Debug)]
+struct Entry {
+    tokens: Vec<Token>,
+    blank_owner: bool,
+}
+
+fn tokenize(text: &str, file: &str) -> Result<Vec<Entry>, ZoneError> {
+    let err = |line, column, message: &str| ZoneError {
+        file: file.to_string(),
+        line,
+        column,
+        message: message.to_string(),
+    };
+
+    let mut entries = Vec::new();
+    let mut entry = Entry {
+        tokens: Vec::new(),
+        blank_owner: false,
+    };
+    let mut depth = 0;
+    let mut open_paren = (0, 0);
+    let mut line_start = true;
+    let (mut line, mut column) = (1, 1);
+
+    let mut chars = text.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        match c {
+            '\n' => {
+                chars.next();
+                if depth == 0 && !entry.tokens.is_empty() {
+                    entries.push(entry);
+                    entry = Entry {
+                        tokens: Vec::new(),
+                        blank_owner: false,
+                    };
+                } else if depth == 0 {
+                    // a blank or comment-only line says nothing about the
+                    // owner of the next record
+                    entry.blank_owner = false;
+                }
+                line += 1;
+                column = 1;
+                line_start = true;
+                continue;
+            }
+            ';' => {
+                while chars.peek().is_some_and(|&c| c != '\n') {
+                    chars.next();
+                }
+                continue;
+            }
+            ' ' | '\t' | '\r' => {
+                if line_start && depth == 0 && entry.tokens.is_empty() {
+                    entry.blank_owner = true;
+                }
+                chars.next();
+                column += 1;
+            }
+            '(' => {
+                chars.next();
+                if depth == 0 {
+                    open_paren = (line, column);
+                }
+                depth += 1;
+                column += 1;
+            }
+            ')' => {
+                if depth == 0 {
+                    return Err(err(line, column, "unbalanced )"));
+                }
+                chars.next();
+                depth -= 1;
+                column += 1;
+            }
+            '"' => {
+                let (start_line, start_col) = (line, column);
+                chars.next();
+                column += 1;
+                let mut s = String::new();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => {
+                            s.push('\\');
+                            match chars.next() {
+                                Some('\n') | None => {
+                                    return Err(err(start_line, start_col, "unterminated string"))
+                                }
+                                Some(e) => s.push(e),
+                            }
+                            column += 2;
+                            continue;
+                        }
+                        Some('\n') | None => {
+                            return Err(err(start_line, start_col, "unterminated string"))
+                        }
+                        Some(c) => s.push(c),
+                    }
+                    column += 1;
+                }
+                column += 1;
+                entry.tokens.push(Token {
+                    text: s,
+                    quoted: true,
+                    line: start_line,
+                    column: start_col,
+                });
+            }
+            _ => {
+                let start_col = column;
+                let mut s = String::new();
+                while let Some(&c) = chars.peek() {
+                    if c.is_whitespace() || matches!(c, ';' | '(' | ')' | '"') {
+                        break;
+                    }
+                    chars.next();
+                    column += 1;
+                    s.push(c);
+                    if c == '\\' {
+                        match chars.peek() {
+                            Some(&e) if e != '\n' => {
+                                chars.next();
+                                column += 1;
+                                s.push(e);
+                            }
+                            _ => return Err(err(line, column - 1, "dangling escape")),
+                        }
+                    }
+                }
+                entry.tokens.push(Token {
+                    text: s,
+                    quoted: false,
+                    line,
+                    column: start_col,
+                });
+            }
+        }
+        line_start = false;
+    }
+
+    if depth > 0 {
+        return Err(err(open_paren.0, open_paren.1, "unclosed ("));
+    }
+    if !entry.tokens.is_empty() {
+        entries.push(entry);
+    }
+
+    Ok(entries)
+}
+
+// Decode \X and \DDD escapes - RFC 1035 5.1
+fn unescape(text: &str) -> Result<Vec<u8>, String> {
+    let bytes = text.as_bytes();
+    let mut out = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] != b'\\' {
+            out.push(bytes[i]);
+            i += 1;
+            continue;
+        }
+
+        let digits = bytes
+            .get(i + 1..i + 4)
+            .filter(|d| d.iter().all(u8::is_ascii_digit));
+        match digits {
+            Some(d) => {
+                let v: u32 = std::str::from_utf8(d).unwrap().parse().unwrap();
+                if v > 255 {
+                    return Err(format!("escape \\{} out of range", v));
+                }
+                out.push(v as u8);
+                i += 4;
+            }
+            None => {
+                let e = bytes.get(i + 1).ok_or("dangling escape")?;
+                out.push(*e);
+                i += 2;
+            }
+        }
+    }
+    Ok(out)
+}
+
+// Split a presentation name into raw labels, honouring escapes. The bool is
+// true when the name is fully qualified.
+pub(crate) fn parse_labels(text: &str) -> Result<(Vec<Vec<u8>>, bool), String> {
+    if text == "." {
+        return Ok((Vec::new(), true));
+    }
+
+    let mut labels = Vec::new();
+    let mut label = String::new();
+    let mut chars = text.chars();
+    let mut absolute = false;
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                label.push(c);
+                label.push(chars.next().ok_or("dangling escape")?);
+            }
+            '.' => {
+                if label.is_empty() {
+                    return Err(format!("empty label in {}", text));
+                }
+                labels.push(unescape(&label)?);
+                label.clear();
+                if chars.as_str().is_empty() {
+                    absolute = true;
+                }
+            }
+            _ => label.push(c),
+        }
+    }
+    if !label.is_empty() {
+        labels.push(unescape(&label)?);
+    }
+    if labels.is_empty() {
+        return Err("empty name".to_string());
+    }
+
+    Ok((labels, absolute))
+}
+
+// Qualify a name from a master file against origin, returning it in
+// canonical presentation format with its trailing dot
+pub(crate) fn absolute_name(text: &str, origin: &str) -> Result<String, String> {
+    if text == "@" {
+        return Ok(origin.to_string());
+    }
+
+    let (mut labels, absolute) = parse_labels(text)?;
+    if !absolute {
+        labels.extend(parse_labels(origin)?.0);
+    }
+
+    Ok(Name::from_labels(labels)?.to_string())
+}
+
+// Parse a TTL given in seconds or BIND style units, e.g. 3600 or 1h30m
+pub fn parse_ttl(text: &str) -> Result<u32, String> {
+    if let Ok(v) = text.parse::<u32>() {
+        return Ok(v);
+    }
+
+    let mut total: u64 = 0;
+    let mut value: Option<u64> = None;
+    for c in text.chars() {
+        if let Some(d) = c.to_digit(10) {
+            value = Some(value.unwrap_or(0) * 10 + d as u64);
+            if value > Some(u32::MAX as u64) {
+                return Err(format!("TTL {} out of range", text));
+            }
+            continue;
+        }
+
+        let unit = match c.to_ascii_lowercase() {
+            'w' => 604800,
+            'd' => 86400,
+            'h' => 3600,
+            'm' => 60,
+            's' => 1,
+            _ => return Err(format!("invalid TTL {}", text)),
+        };
+        total += value.take().ok_or(format!("invalid TTL {}", text))? * unit;
+    }
+    total += value.unwrap_or(0);
+
+    u32::try_from(total).map_err(|_| format!("TTL {} out of range", text))
+}
+
+struct Parser {
+    origin: String,
+    default_ttl: Option<u32>,
+    last_ttl: Option<u32>,
+    last_class: RecordClass,
+    last_owner: Option<String>,
+    records: Vec<Answer>,
+}
+
+impl Parser {
+    fn parse_text(
+        &mut self,
+        text: &str,
+        file: &str,
+        dir: &Path,
+        depth: usize,
+    ) -> Result<(), ZoneError> {
+        for entry in tokenize(text, file)? {
+            let first = &entry.tokens[0];
+            let at = |t: &Token, message: String| ZoneError {
+                file: file.to_string(),
+                line: t.line,
+                column: t.column,
+                message,
+            };
+
+            if !entry.blank_owner && !first.quoted && first.text.starts_with('$') {
+                self.directive(&entry.tokens, file, dir, depth)?;
+                continue;
+            }
+
+            let (owner, rest) = if entry.blank_owner {
+                match &self.last_owner {
+                    Some(o) => (o.clone(), &entry.tokens[..]),
+                    None => return Err(at(first, "no previous owner name".to_string())),
+                }
+            } else {
+                let owner = absolute_name(&first.text, &self.origin).map_err(|e| at(first, e))?;
+                (owner, &entry.tokens[1..])
+            };
+
+            self.record(owner, rest, first)
+                .map_err(|(t, e)| at(&t, e))?;
+        }
+
+        Ok(())
+    }
+
+    fn directive(
+        &mut self,
+        tokens: &[Token],
+        file: &str,
+        dir: &Path,
+        depth: usize,
+    ) -> Result<(), ZoneError> {
+        let at = |t: &Token, message: String| ZoneError {
+            file: file.to_string(),
+            line: t.line,
+            column: t.column,
+            message,
+        };
+        let arg = |i: usize| {
+            tokens
+                .get(i)
+                .ok_or_else(|| at(&tokens[0], format!("{} needs an argument", tokens[0].text)))
+        };
+
+        match tokens[0].text.to_ascii_uppercase().as_str() {
+            "$ORIGIN" => {
+                let t = arg(1)?;
+                self.origin = absolute_name(&t.text, &self.origin).map_err(|e| at(t, e))?;
+            }
+            "$TTL" => {
+                let t = arg(1)?;
+                self.default_ttl = Some(parse_ttl(&t.text).map_err(|e| at(t, e))?);
+            }
+            "$INCLUDE" => {
+                let t = arg(1)?;
+                if depth >= MAX_INCLUDE_DEPTH {
+                    return Err(at(t, "$INCLUDE nested too deeply".to_string()));
+                }
+
+                let path = dir.join(&t.text);
+                let text = fs::read_to_string(&path)
+                    .map_err(|e| at(t, format!("cannot read {}: {}", path.display(), e)))?;
+
+                // the included file may set its own origin; ours is restored after
+                let saved = self.origin.clone();
+                if let Some(o) = tokens.get(2) {
+                    self.origin = absolute_name(&o.text, &self.origin).map_err(|e| at(o, e))?;
+                }
+                let sub_dir = path.parent().map(Path::to_path_buf).unwrap_or_default();
+                self.parse_text(&text, &path.display().to_string(), &sub_dir, depth + 1)?;
+                self.origin = saved;
+            }
+            "$GENERATE" => self.generate(tokens).map_err(|(t, e)| at(&t, e))?,
+            other => return Err(at(&tokens[0], format!("unknown directive {}", other))),
+        }
+
+        Ok(())
+    }
+
+    // $GENERATE start-stop[/step] lhs [ttl] [class] type rhs
+    fn generate(&mut self, tokens: &[Token]) -> Result<(), (Token, String)> {
+        if tokens.len() < 5 {
+            return Err((
+                tokens[0].clone(),
+                "$GENERATE needs a range, owner, type and data".to_string(),
+            ));
+        }
+
+        let range = &tokens[1];
+        let bad_range = || (range.clone(), format!("invalid range {}", range.text));
+        let (span, step) = match range.text.split_once('/') {
+            Some((span, step)) => (span, step.parse::<u32>().map_err(|_| bad_range())?),
+            None => (range.text.as_str(), 1),
+        };
+        let (start, stop) = span.split_once('-').ok_or_else(bad_range)?;
+        let start: u32 = start.parse().map_err(|_| bad_range())?;
+        let stop: u32 = stop.parse().map_err(|_| bad_range())?;
+        if step == 0 || start > stop {
+            return Err(bad_range());
+        }
+        if (stop - start) / step >= MAX_GENERATE {
+            return Err((
+                range.clone(),
+                format!(
+                    "range {} makes more than {} records",
+                    range.text, MAX_GENERATE
+                ),
+            ));
+        }
+
+        let mut i = start;
+        while i <= stop {
+            let expand = |t: &Token| -> Result<Token, (Token, String)> {
+                Ok(Token {
+                    text: substitute(&t.text, i).map_err(|e| (t.clone(), e))?,
+                    ..t.clone()
+                })
+            };
+
+            let lhs = expand(&tokens[2])?;
+            let owner = absolute_name(&lhs.text, &self.origin).map_err(|e| (lhs.clone(), e))?;
+
+            let mut rest: Vec<Token> = tokens[3..tokens.len() - 1].to_vec();
+            rest.push(expand(&tokens[tokens.len() - 1])?);
+            self.record(owner, &rest, &tokens[0])?;
+
+            i = match i.checked_add(step) {
+                Some(n) => n,
+                None => break,
+            };
+        }
+
+        Ok(())
+    }
+
+    // [ttl] [class] type rdata..., in either order for ttl and class
+    fn record(
+        &mut self,
+        owner: String,
+        tokens: &[Token],
+        start: &Token,
+    ) -> Result<(), (Token, String)> {
+        let mut ttl = None;
+        let mut class = None;
+        let mut i = 0;
+
+        let rtype = loop {
+            let t = tokens
+                .get(i)
+                .ok_or_else(|| (start.clone(), "missing record type".to_string()))?;
+            i += 1;
+
+            if ttl.is_none() {
+                if let Ok(v) = parse_ttl(&t.text) {
+                    ttl = Some(v);
+                    continue;
+                }
+            }
+            if class.is_none() {
+                if let Ok(c) = t.text.parse::<RecordClass>() {
+                    class = Some(c);
+                    continue;
+                }
+            }
+            match t.text.parse::<RecordType>() {
+                Ok(rtype) => break (rtype, t),
+                Err(e) => return Err((t.clone(), e)),
+            }
+        };
+        let (rtype, type_token) = rtype;
+
+        let rdata = parse_rdata(rtype, &tokens[i..], &self.origin, type_token)?;
+
+        let ttl = match ttl.or(self.default_ttl).or(self.last_ttl) {
+            Some(v) => v,
+            None => match &rdata {
+                // RFC 1035 zones without $TTL fall back to the SOA minimum
+                RData::SOA { minimum, .. } => *minimum,
+                _ => return Err((start.clone(), "no TTL specified and no $TTL".to_string())),
+            },
+        };
+        let class = class.unwrap_or(self.last_class);
+
+        self.last_ttl = Some(ttl);
+        self.last_class = class;
+        self.last_owner = Some(owner.clone());
+        self.records
+            .push(Answer::new(owner, rtype, class, ttl, rdata));
+
+        Ok(())
+    }
+}
+
+// expand $, ${offset,width,base} and \$ in a $GENERATE template
+fn substitute(template: &str, value: u32) -> Result<String, String> {
+    let mut out = String::new();
+    let mut chars = template.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                out.push('\\');
+                if let Some(e) = chars.next() {
+                    if e == '$' {
+                        out.pop();
+                    }
+                    out.push(e);
+                }
+            }
+            '$' if chars.peek() == Some(&'{') => {
+                chars.next();
+                let spec: String = chars.by_ref().take_while(|&c| c != '}').collect();
+                let mut parts = spec.split(',');
+                let offset: i64 = parts
+                    .next()
+                    .unwrap_or("0")
+                    .trim()
+                    .parse()
+                    .map_err(|_| format!("invalid modifier {{{}}}", spec))?;
+                let width: usize = parts
+                    .next()
+                    .unwrap_or("0")
+                    .trim()
+                    .parse()
+                    .map_err(|_| format!("invalid modifier {{{}}}", spec))?;
+                let base = parts.next().unwrap_or("d").trim();
+
+                let v = value as i64 + offset;
+                if v < 0 {
+                    return Err(format!("modifier {{{}}} gives a negative value", spec));
+                }
+                out.push_str(&match base {
+                    "d" => format!("{:0w$}", v, w = width),
+                    "o" => format!("{:0w$o}", v, w = width),
+                    "x" => format!("{:0w$x}", v, w = width),
+                    "X" => format!("{:0w$X}", v, w = width),
+                    // nibble labels for reverse IPv6 zones
+                    "n" | "N" => {
+                        let hex = format!("{:0w$x}", v, w = width);
+                        let hex = if base == "N" { hex.to_uppercase() } else { hex };
+                        let nibbles: Vec<String> = hex.chars().rev().map(String::from).collect();
+                        nibbles.join(".")
+                    }
+                    _ => return Err(format!("invalid base {} in {{{}}}", base, spec)),
+                });
+            }
+            '$' => out.push_str(&value.to_string()),
+            c => out.push(c),
+        }
+    }
+    Ok(out)
+}
+
+fn rdata_err(t: &Token, e: impl fmt::Display) -> (Token, String) {
+    (t.clone(), e.to_string())
+}
+
+// <character-string> from a quoted or bare token
+fn char_string(t: &Token) -> Result<Vec<u8>, (Token, String)> {
+    let s = unescape(&t.text).map_err(|e| rdata_err(t, e))?;
+    if s.len() > 255 {
+        return Err(rdata_err(t, "string longer than 255 bytes"));
+    }
+    Ok(s)
+}
+
+fn parse_rdata(
+    rtype: RecordType,
+    tokens: &[Token],
+    origin: &str,
+    type_token: &Token,
+) -> Result<RData, (Token, String)> {
+    let need = |n: usize| {
+        if tokens.len() < n {
+            let last = tokens.last().unwrap_or(type_token);
+            return Err(rdata_err(
+                last,
+                format!("{} record needs {} fields", rtype, n),
+            ));
+        }
+        if let Some(t) = tokens.get(n) {
+            if !matches!(rtype, RecordType::TXT) {
+                return Err(rdata_err(
+                    t,
+                    format!("unexpected {} in {} record", t.text, rtype),
+                ));
+            }
+        }
+        Ok(())
+    };
+    let name = |t: &Token| absolute_name(&t.text, origin).map_err(|e| rdata_err(t, e));
+    let int = |t: &Token| t.text.parse::<u16>().map_err(|e| rdata_err(t, e));
+    let time = |t: &Token| parse_ttl(&t.text).map_err(|e| rdata_err(t, e));
+
+    // generic RFC 3597 form works for every type
+    if let Some(t) = tokens.first().filter(|t| t.text == "\\#" && !t.quoted) {
+        let len_token = tokens
+            .get(1)
+            .ok_or_else(|| rdata_err(t, "\\# needs a length"))?;
+        let len: usize = len_token
+            .text
+            .parse()
+            .map_err(|e| rdata_err(len_token, e))?;
+        let hex: String = tokens[2..].iter().map(|t| t.text.as_str()).collect();
+        if !hex.len().is_multiple_of(2) || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(rdata_err(t, "invalid hex in \\# data"));
+        }
+        let data: Vec<u8> = (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap())
+            .collect();
+        if data.len() != len {
+            return Err(rdata_err(
+                len_token,
+                format!("\\# length {} but {} bytes of data", len, data.len()),
+            ));
+        }
+        return RData::from_bytes(rtype, &data, 0, len).map_err(|e| rdata_err(t, e));
+    }
+
+    let rdata = match rtype {
+        RecordType::A => {
+            need(1)?;
+            RData::A(
+                tokens[0]
+                    .text
+                    .parse::<Ipv4Addr>()
+                    .map_err(|e| rdata_err(&tokens[0], e))?,
+            )
+        }
+        RecordType::AAAA => {
+            need(1)?;
+            RData::AAAA(
+                tokens[0]
+                    .text
+                    .parse::<Ipv6Addr>()
+                    .map_err(|e| rdata_err(&tokens[0], e))?,
+            )
+        }
+        RecordType::NS => {
+            need(1)?;
+            RData::NS(name(&tokens[0])?)
+        }
+        RecordType::CNAME => {
+            need(1)?;
+            RData::CNAME(name(&tokens[0])?)
+        }
+        RecordType::PTR => {
+            need(1)?;
+            RData::PTR(name(&tokens[0])?)
+        }
+        RecordType::MX => {
+            need(2)?;
+            RData::MX {
+                preference: int(&tokens[0])?,
+                exchange: name(&tokens[1])?,
+            }
+        }
+        RecordType::SOA => {
+            need(7)?;
+            RData::SOA {
+                mname: name(&tokens[0])?,
+                rname: name(&tokens[1])?,
+                serial: tokens[2]
+                    .text
+                    .parse()
+                    .map_err(|e| rdata_err(&tokens[2], e))?,
+                refresh: time(&tokens[3])?,
+                retry: time(&tokens[4])?,
+                expire: time(&tokens[5])?,
+                minimum: time(&tokens[6])?,
+            }
+        }
+        RecordType::TXT => {
+            need(1)?;
+            RData::TXT(tokens.iter().map(char_string).collect::<Result<_, _>>()?)
+        }
+        RecordType::SRV => {
+            need(4)?;
+            RData::SRV {
+                priority: int(&tokens[0])?,
+                weight: int(&tokens[1])?,
+                port: int(&tokens[2])?,
+                target: name(&tokens[3])?,
+            }
+        }
+        RecordType::HINFO => {
+            need(2)?;
+            RData::HINFO {
+                cpu: char_string(&tokens[0])?,
+                os: char_string(&tokens[1])?,
+            }
+        }
+        _ => {
+            return Err(rdata_err(
+                type_token,
+                format!("{} records are only supported in \\# generic form", rtype),
+            ))
+        }
+    };
+
+    Ok(rdata)
+}
+
+fn new_parser(origin: &str) -> Result<Parser, String> {
+    Ok(Parser {
+        origin: absolute_name(origin, ".")?,
+        default_ttl: None,
+        last_ttl: None,
+        last_class: RecordClass::IN,
+        last_owner: None,
+        records: Vec::new(),
+    })
+}
+
+// Parse master file text with the given initial origin. $INCLUDE paths are
+// resolved against the current directory.
+pub fn parse_zone(text: &str, origin: &str) -> Result<Vec<Answer>, ZoneError> {
+    let mut parser = new_parser(origin).map_err(|message| ZoneError {
+        file: "<origin>".to_string(),
+        line: 0,
+        column: 0,
+        message,
+    })?;
+    parser.parse_text(text, "<input>", Path::new(""), 0)?;
+    Ok(parser.records)
+}
+
+// Parse a master file with the given initial origin. $INCLUDE paths are
+// resolved against the file's directory.
+pub fn parse_zone_file<P: AsRef<Path>>(path: P, origin: &str) -> Result<Vec<Answer>, ZoneError> {
+    let path = path.as_ref();
+    let file = path.display().to_string();
+    let err = |message| ZoneError {
+        file: file.clone(),
+        line: 0,
+        column: 0,
+        message,
+    };
+
+    let text = fs::read_to_string(path).map_err(|e| err(e.to_string()))?;
+    let mut parser = new_parser(origin).map_err(err)?;
+    let dir = path
+        .parent()
+        .map(Path::to_path_buf)
+        .unwrap_or_else(PathBuf::new);
+    parser.parse_text(&text, &file, &dir, 0)?;
+    Ok(parser.records)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TtlStyle {
+    Seconds, // 5400
+    Units,   // 1h30m
+}
+
+#[derive(Debug, Clone)]
+pub struct WriteOptions {
+    // emit $ORIGIN and write names under it relative to it
+    pub origin: Option<String>,
+    pub ttl_style: TtlStyle,
+    // pad owner, TTL, class and type into columns
+    pub align: bool,
+    // sort into DNSSEC canonical order - RFC 4034 6
+    pub canonical: bool,
+}
+
+impl Default for WriteOptions {
+    fn default() -> Self {
+        Self {
+            origin: None,
+            ttl_style: TtlStyle::Seconds,
+            align: true,
+            canonical: false,
+        }
+    }
+}
+
+// a TTL in BIND unit style, e.g. 5400 -> 1h30m
+pub fn format_ttl(ttl: u32) -> String {
+    if ttl == 0 {
+        return "0".to_string();
+    }
+
+    let mut out = String::new();
+    let mut rest = ttl;
+    for (unit, secs) in [
+        ('w', 604800),
+        ('d', 86400),
+        ('h', 3600),
+        ('m', 60),
+        ('s', 1),
+    ] {
+        if rest >= secs {
+            out.push_str(&format!("{}{}", rest / secs, unit));
+            rest %= secs;
+        }
+    }
+    out
+}
+
+// lower cased raw labels, root first, for canonical comparison
+fn canonical_labels(name: &str) -> Vec<Vec<u8>> {
+    let mut labels = parse_labels(name).map(|(l, _)| l).unwrap_or_default();
+    labels.reverse();
+    for l in labels.iter_mut() {
+        l.make_ascii_lowercase();
+    }
+    labels
+}
+
+// Order two names canonically - RFC 4034 6.1
+pub fn canonical_cmp(a: &str, b: &str) -> Ordering {
+    canonical_labels(a).cmp(&canonical_labels(b))
+}
+
+// Sort records by owner in canonical order, with the SOA leading its
+// owner's records, then by class and type, and by RDATA wire format within
+// an RRset - RFC 4034 6.3
+pub fn canonical_sort(records: &mut [Answer]) {
+    let type_key = |r: &Answer| match r.rtype {
+        RecordType::SOA => 0,
+        t => t.to_u16() as u32 + 1,
+    };
+    records.sort_by(|a, b| {
+        canonical_cmp(&a.name, &b.name)
+            .then(a.rclass.to_u16().cmp(&b.rclass.to_u16()))
+            .then(type_key(a).cmp(&type_key(b)))
+            .then(canonical_rdata(a).cmp(&canonical_rdata(b)))
+    });
+}
+
+// RDATA with names lower cased, as used for canonical RR ordering. Data
+// with names that cannot be encoded sorts first.
+fn canonical_rdata(r: &Answer) -> Vec<u8> {
+    r.rdata
+        .map_names(|n| n.to_ascii_lowercase())
+        .to_bytes()
+        .unwrap_or_default()
+}
+
+// name relative to origin: @ for the origin itself, the leading labels for
+// names under it, and the name unchanged otherwise
+fn relative_name(name: &str, origin: &str) -> String {
+    let labels = match parse_labels(name) {
+        Ok((l, true)) => l,
+        _ => return name.to_string(),
+    };
+    let origin_labels = parse_labels(origin).map(|(l, _)| l).unwrap_or_default();
+
+    if labels.len() < origin_labels.len() {
+        return name.to_string();
+    }
+    let (head, tail) = labels.split_at(labels.len() - origin_labels.len());
+    let under = tail
+        .iter()
+        .zip(&origin_labels)
+        .all(|(a, b)| a.eq_ignore_ascii_case(b));
+
+    match (under, head.is_empty()) {
+        (false, _) => name.to_string(),
+        (true, true) => "@".to_string(),
+        (true, false) => {
+            let parts: Vec<String> = head
+                .iter()
+                .map(|l| {
+                    let mut s = String::new();
+                    push_label(&mut s, l);
+                    s
+                })
+                .collect();
+            parts.join(".")
+        }
+    }
+}
+
+// Render records in master file format
+pub fn write_zone(records: &[Answer], opts: &WriteOptions) -> String {
+    let mut records = records.to_vec();
+    if opts.canonical {
+        canonical_sort(&mut records);
+    }
+
+    let origin = opts
+        .origin
+        .as_ref()
+        .and_then(|o| absolute_name(o, ".").ok());
+    let name = |n: &str| match &origin {
+        Some(o) => relative_name(n, o),
+        None => n.to_string(),
+    };
+
+    let rows: Vec<[String; 5]> = records
+        .iter()
+        .map(|r| {
+            [
+                name(&r.name),
+                match opts.ttl_style {
+                    TtlStyle::Seconds => r.ttl.to_string(),
+                    TtlStyle::Units => format_ttl(r.ttl),
+                },
+                r.rclass.to_string(),
+                r.rtype.to_string(),
+                r.rdata.map_names(name).to_string(),
+            ]
+        })
+        .collect();
+
+    let mut widths = [0; 4];
+    if opts.align {
+        for row in &rows {
+            for (w, col) in widths.iter_mut().zip(row.iter()) {
+                *w = (*w).max(col.len());
+            }
+        }
+    }
+
+    let mut out = String::new();
+    if let Some(o) = &origin {
+        out.push_str(&format!("$ORIGIN {}\n", o));
+    }
+    for row in rows {
+        if opts.align {
+            out.push_str(&format!(
+                "{:<w0$} {:>w1$} {:<w2$} {:<w3$} {}\n",
+                row[0],
+                row[1],
+                row[2],
+                row[3],
+                row[4],
+                w0 = widths[0],
+                w1 = widths[1],
+                w2 = widths[2],
+                w3 = widths[3]
+            ));
+        } else {
+            out.push_str(&row.join("\t"));
+            out.push('\n');
+        }
+    }
+    out
+}
+
+// Check a parsed zone for common mistakes, returning one warning per problem
+pub fn lint_zone(records: &[Answer], origin: &str) -> Vec<String> {
+    let mut warnings = Vec::new();
+    let origin = absolute_name(origin, ".").unwrap_or_else(|_| origin.to_string());
+    let same = |a: &str, b: &str| a.eq_ignore_ascii_case(b);
+    let under = |n: &str| relative_name(n, &origin) != n || same(n, &origin);
+
+    let soas: Vec<&Answer> = records
+        .iter()
+        .filter(|r| r.rtype == RecordType::SOA)
+        .collect();
+    match soas.len() {
+        0 => warnings.push(format!("no SOA record for {}", origin)),
+        1 if !same(&soas[0].name, &origin) => warnings.push(format!(
+            "SOA owner {} is not the origin {}",
+            soas[0].name, origin
+        )),
+        1 => {}
+        n => warnings.push(format!("{} SOA records; a zone has exactly one", n)),
+    }
+
+    if !records
+        .iter()
+        .any(|r| r.rtype == RecordType::NS && same(&r.name, &origin))
+    {
+        warnings.push(format!("no NS records at the apex {}", origin));
+    }
+
+    let mut names: Vec<&str> = Vec::new();
+    for r in records {
+        if !names.iter().any(|n| same(n, &r.name)) {
+            names.push(&r.name);
+        }
+    }
+
+    for n in names {
+        let at: Vec<&Answer> = records.iter().filter(|r| same(&r.name, n)).collect();
+
+        if !under(n) {
+            warnings.push(format!("{} is outside the zone {}", n, origin));
+        }
+
+        let cnames = at.iter().filter(|r| r.rtype == RecordType::CNAME).count();
+        let others = at
+            .iter()
+            .filter(|r| {
+                !matches!(
+                    r.rtype,
+                    RecordType::CNAME | RecordType::RRSIG | RecordType::NSEC
+                )
+            })
+            .count();
+        if cnames > 1 {
+            warnings.push(format!("{} has {} CNAME records", n, cnames));
+        }
+        if cnames > 0 && others > 0 {
+            warnings.push(format!("{} has a CNAME alongside other data", n));
+        }
+
+        // RFC 2181 5.2: every record in an RRset shares one TTL
+        for (i, r) in at.iter().enumerate() {
+            let earlier = &at[..i];
+            if earlier
+                .iter()
+                .any(|e| e.rtype == r.rtype && e.rclass == r.rclass && e.ttl != r.ttl)
+            {
+                warnings.push(format!("{} {} has records with differing TTLs", n, r.rtype));
+            }
+            if earlier
+                .iter()
+                .any(|e| e.rtype == r.rtype && e.rclass == r.rclass && e.rdata == r.rdata)
+            {
+                warnings.push(format!("duplicate record {}", r));
+            }
+        }
+    }
+
+    // RFC 2181 10.3: NS and MX must not point at an alias
+    for r in records {
+        let target = match &r.rdata {
+            RData::NS(t) => t,
+            RData::MX { exchange, .. } => exchange,
+            _ => continue,
+        };
+        if records
+            .iter()
+            .any(|c| c.rtype == RecordType::CNAME && same(&c.name, target))
+        {
+            warnings.push(format!(
+                "{} {} target {} is a CNAME",
+                r.name, r.rtype, target
+            ));
+        }
+    }
+
+    warnings.dedup();
+    warnings
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ZONE: &str = r#"
+$ORIGIN example.com.
+$TTL 1h
+@   IN  SOA ns1 hostmaster (
+        2022010101 ; serial
+        3h 15m 1w 300 )
+    IN  NS  ns1
+    IN  MX  10 mail
+ns1     A   192.0.2.1
+mail 600 IN A 192.0.2.25
+        AAAA 2001:db8::25
+www     CNAME @
+txt     TXT "v=spf1 -all" "semi;colon" bare
+odd\.dot\065 A 192.0.2.9
+_sip._udp SRV 0 5 5060 sip.example.net.
+gen     TYPE999 \# 3 abcdef
+"#;
+
+    #[test]
+    fn test_parse_zone() {
+        let records = parse_zone(ZONE, "example.com").unwrap();
+
+        assert!(records.len() == 11);
+        assert!(records[0].name == "example.com.");
+        assert!(records[0].ttl == 3600);
+        assert!(
+            records[0].rdata
+                == RData::SOA {
+                    mname: "ns1.example.com.".to_string(),
+                    rname: "hostmaster.example.com.".to_string(),
+                    serial: 2022010101,
+                    refresh: 10800,
+                    retry: 900,
+                    expire: 604800,
+                    minimum: 300,
+                }
+        );
+        assert!(records[1].name == "example.com.");
+        assert!(records[1].rdata == RData::NS("ns1.example.com.".to_string()));
+        assert!(records[4].ttl == 600);
+        // blank owner continues mail; $TTL applies since no TTL is given
+        assert!(records[5].name == "mail.example.com." && records[5].ttl == 3600);
+        assert!(records[6].rdata == RData::CNAME("example.com.".to_string()));
+        assert!(
+            records[7].rdata
+                == RData::TXT(vec![
+                    b"v=spf1 -all".to_vec(),
+                    b"semi;colon".to_vec(),
+                    b"bare".to_vec()
+                ])
+        );
+        assert!(records[8].name == "odd\\.dotA.example.com.");
+        assert!(records[9].name == "_sip._udp.example.com.");
+        assert!(records[10].rtype == RecordType::Unknown(999));
+        assert!(records[10].rdata == RData::Unknown(vec![0xab, 0xcd, 0xef]));
+    }
+
+    #[test]
+    fn test_blank_and_comment_lines() {
+        // indented comments and whitespace-only lines between records leave
+        // the next record's owner alone
+        let text = "$TTL 60\nwww A 192.0.2.1\n    ; web servers end here\n  \t\nftp A 192.0.2.2\n\t; more\n    AAAA 2001:db8::2\n";
+        let records = parse_zone(text, "example.com").unwrap();
+        assert!(records.len() == 3);
+        assert!(records[0].name == "www.example.com.");
+        assert!(records[1].name == "ftp.example.com.");
+        assert!(records[2].name == "ftp.example.com." && records[2].rtype == RecordType::AAAA);
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let err = parse_zone("$TTL 60\nwww A 192.0.2.300\n", "example.com").unwrap_err();
+        assert!(err.line == 2 && err.column == 7);
+
+        let err = parse_zone("$TTL 60\n\nwww A (\n 192.0.2.1\n", "example.com").unwrap_err();
+        assert!(err.line == 3 && err.column == 7 && err.message == "unclosed (");
+
+        let err = parse_zone("www 60 A 192.0.2.1\n  FOO x\n", "example.com").unwrap_err();
+        assert!(err.line == 2 && err.column == 3);
+
+        let err = parse_zone("www A 192.0.2.1\n", "example.com").unwrap_err();
+        assert!(err.message.contains("no TTL"));
+
+        let err = parse_zone("$TTL 60\nwww TXT \"open\n", "example.com").unwrap_err();
+        assert!(err.line == 2 && err.column == 9);
+
+        let long = "a".repeat(64);
+        assert!(parse_zone(&format!("$TTL 60\n{} A 192.0.2.1\n", long), "example.com").is_err());
+        assert!(err.to_string().starts_with("<input>:2:9: "));
+    }
+
+    #[test]
+    fn test_generate() {
+        let text = "$TTL 60\n$GENERATE 1-3 host-$ A 192.0.2.$\n$GENERATE 10-20/10 ${0,3,x} PTR h${-9}.example.com.\n";
+        let records = parse_zone(text, "example.com").unwrap();
+
+        assert!(records.len() == 5);
+        assert!(records[2].name == "host-3.example.com.");
+        assert!(records[2].rdata == RData::A(Ipv4Addr::new(192, 0, 2, 3)));
+        assert!(records[4].name == "014.example.com.");
+        assert!(records[4].rdata == RData::PTR("h11.example.com.".to_string()));
+
+        let err =
+            parse_zone("$TTL 60\n$GENERATE 0-65535 h$ A 192.0.2.1\n", "example.com").unwrap_err();
+        assert!(err.line == 2 && err.column == 11);
+        assert!(err.message == "range 0-65535 makes more than 65535 records");
+        assert!(parse_zone(
+            "$GENERATE 0-4294967295/65538 h$ 60 A 192.0.2.1\n",
+            "example.com"
+        )
+        .is_ok());
+
+        assert!(substitute("${0,4,n}", 0x1a) == Ok("a.1.0.0".to_string()));
+        assert!(substitute("\\$$", 7) == Ok("$7".to_string()));
+    }
+
+    #[test]
+    fn test_include() {
+        let dir = std::env::temp_dir().join(format!("rig-zone-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("hosts.inc"), "www A 192.0.2.80\n").unwrap();
+        fs::write(
+            dir.join("main.zone"),
+            "$TTL 60\n$INCLUDE hosts.inc sub\nafter A 192.0.2.1\n",
+        )
+        .unwrap();
+
+        let records = parse_zone_file(dir.join("main.zone"), "example.com.").unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert!(records[0].name == "www.sub.example.com.");
+        // the origin reverts once the include is done
+        assert!(records[1].name == "after.example.com.");
+    }
+
+    #[test]
+    fn test_parse_ttl() {
+        assert!(parse_ttl("3600") == Ok(3600));
+        assert!(parse_ttl("1h30m") == Ok(5400));
+        assert!(parse_ttl("1W2D") == Ok(777600));
+        assert!(parse_ttl("h").is_err());
+        assert!(parse_ttl("MX").is_err());
+    }
+
+    #[test]
+    fn test_format_ttl() {
+        assert!(format_ttl(5400) == "1h30m");
+        assert!(format_ttl(604800 + 1) == "1w1s");
+        assert!(format_ttl(0) == "0");
+        assert!(parse_ttl(&format_ttl(1234567)) == Ok(1234567));
+    }
+
+    #[test]
+    fn test_canonical_order() {
+        // the example ordering from RFC 4034 6.1
+        let mut names = vec![
+            "z.example.",
+            "zABC.a.EXAMPLE.",
+            "a.example.",
+            "\\001.z.example.",
+            "example.",
+            "*.z.example.",
+            "yljkjljk.a.example.",
+            "Z.a.example.",
+            "\\200.z.example.",
+        ];
+        names.sort_by(|a, b| canonical_cmp(a, b));
+        assert!(
+            names
+                == vec![
+                    "example.",
+                    "a.example.",
+                    "yljkjljk.a.example.",
+                    "Z.a.example.",
+                    "zABC.a.EXAMPLE.",
+                    "z.example.",
+                    "\\001.z.example.",
+                    "*.z.example.",
+                    "\\200.z.example.",
+                ]
+        );
+    }
+
+    #[test]
+    fn test_write_round_trip() {
+        let records = parse_zone(ZONE, "example.com").unwrap();
+        let opts = WriteOptions {
+            origin: Some("example.com".to_string()),
+            ttl_style: TtlStyle::Units,
+            align: true,
+            canonical: true,
+        };
+
+        let text = write_zone(&records, &opts);
+        assert!(text.starts_with("$ORIGIN example.com.\n@ "));
+        assert!(text.contains("@          1h IN SOA     ns1 hostmaster 2022010101 "));
+        assert!(text.contains("www        1h IN CNAME   @\n"));
+        assert!(text.contains("sip.example.net."));
+
+        let mut reparsed = parse_zone(&text, ".").unwrap();
+        let mut expected = records.clone();
+        canonical_sort(&mut reparsed);
+        canonical_sort(&mut expected);
+        assert!(reparsed == expected);
+    }
+
+    #[test]
+    fn test_lint() {
+        let text = "$TTL 60\n@ SOA ns1 host 1 2 3 4 5\nwww CNAME host\nwww A 192.0.2.1\n@ MX 10 www\nhost 30 A 192.0.2.2\nhost 60 A 192.0.2.3\n";
+        let records = parse_zone(text, "example.com").unwrap();
+        let warnings = lint_zone(&records, "example.com");
+
+        assert!(warnings.iter().any(|w| w.contains("no NS records")));
+        assert!(warnings.iter().any(|w| w.contains("CNAME alongside")));
+        assert!(warnings.iter().any(|w| w.contains("differing TTLs")));
+        assert!(warnings
+            .iter()
+            .any(|w| w.contains("target www.example.com. is a CNAME")));
+        assert!(lint_zone(&parse_zone(ZONE, "example.com").unwrap(), "example.com").is_empty());
+    }
+}